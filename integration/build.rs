@@ -1,11 +1,14 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-
     println!("cargo:rerun-if-changed=.env");
 
-    tonic_prost_build::configure().compile_protos(
-        &["proto/integration.proto", "proto/health.proto"],
-        &["proto"],
-    )?;
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?);
+
+    tonic_prost_build::configure()
+        .file_descriptor_set_path(out_dir.join("integration_descriptor.bin"))
+        .compile_protos(
+            &["proto/integration.proto", "proto/health.proto"],
+            &["proto"],
+        )?;
 
     Ok(())
 }
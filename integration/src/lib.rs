@@ -0,0 +1,185 @@
+//! Services and layers exercised by the integration tests, shared by the lambda binary and the
+//! self-contained tests that serve them locally.
+
+mod log_layer;
+mod meta_echo_layer;
+mod auth_interceptor;
+
+use std::time::Duration;
+use crate::api::server_stream_request::StreamTestCase;
+use crate::api::test_server::{Test, TestServer};
+use crate::api::unary_request::UnaryTestCase;
+use crate::api::{ServerStreamRequest, ServerStreamResponse, UnaryRequest, UnaryResponse};
+use crate::auth_interceptor::AuthInterceptor;
+use crate::log_layer::LogServiceNameLayer;
+use crate::meta_echo_layer::MetaEchoLayer;
+use lambda_grpc_web::lambda_runtime::Context;
+use lambda_grpc_web::{
+    ConnectConfig, HealthConfig, LambdaDeadline, LambdaRequestInfo, LambdaRouter, LambdaServer,
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{pending, StreamExt};
+use tonic::{Request, Response, Status};
+use tower::layer::util::{Identity, Stack};
+
+pub mod api {
+    tonic::include_proto!("integration.v1");
+    tonic::include_proto!("grpc.health.v1");
+
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("integration_descriptor");
+}
+
+struct IntegrationTestService;
+
+#[tonic::async_trait]
+impl Test for IntegrationTestService {
+    async fn unary(
+        &self,
+        request: Request<UnaryRequest>,
+    ) -> Result<Response<UnaryResponse>, Status> {
+        let (_meta, extensions, payload) = request.into_parts();
+
+        match payload.test_case() {
+            UnaryTestCase::Unknown => panic!("Unknown test case"),
+            UnaryTestCase::Ok => Ok(Response::new(UnaryResponse {
+                message: Some("ok response".to_string()),
+            })),
+            UnaryTestCase::Panic => panic!("panic test case"),
+            UnaryTestCase::LambdaContextInHeaders => {
+                let mut res = Response::new(UnaryResponse {
+                    message: Some("lambda context response".to_string()),
+                });
+
+                let ctx = extensions
+                    .get::<Context>()
+                    .expect("lambda context missing from request extensions");
+
+                res.metadata_mut().insert(
+                    "lambda_ctx_deadline_ms",
+                    ctx.deadline.to_string().parse().unwrap(),
+                );
+
+                // absent when served locally, as there is no invocation event
+                if let Some(source_ip) = extensions
+                    .get::<LambdaRequestInfo>()
+                    .and_then(|info| info.source_ip)
+                {
+                    res.metadata_mut().insert(
+                        "lambda_request_source_ip",
+                        source_ip.to_string().parse().unwrap(),
+                    );
+                }
+
+                Ok(res)
+            }
+            UnaryTestCase::LambdaDeadlineInHeaders => {
+                let mut res = Response::new(UnaryResponse {
+                    message: Some("lambda deadline response".to_string()),
+                });
+
+                let deadline = extensions
+                    .get::<LambdaDeadline>()
+                    .expect("lambda deadline missing from request extensions");
+
+                res.metadata_mut().insert(
+                    "lambda_deadline_remaining_ms",
+                    deadline.remaining().as_millis().to_string().parse().unwrap(),
+                );
+
+                Ok(res)
+            }
+            UnaryTestCase::NeverRespond => {
+                pending::<()>().next().await;
+                unreachable!("pending stream never resolves")
+            }
+        }
+    }
+
+    type ServerStreamStream = ReceiverStream<Result<ServerStreamResponse, Status>>;
+    async fn server_stream(
+        &self,
+        request: Request<ServerStreamRequest>,
+    ) -> Result<Response<Self::ServerStreamStream>, Status> {
+        let (tx, rx) = mpsc::channel::<Result<ServerStreamResponse, Status>>(1);
+
+        tokio::spawn(async move {
+            match request.into_inner().test_case() {
+                StreamTestCase::Unknown => panic!("Unknown test case"),
+                StreamTestCase::Ok => {
+                    tx.send(Ok(ServerStreamResponse {
+                        message: Some("ok first response".to_string()),
+                    }))
+                    .await
+                    .unwrap();
+
+                    tx.send(Ok(ServerStreamResponse {
+                        message: Some("ok second response".to_string()),
+                    }))
+                    .await
+                    .unwrap();
+                }
+                StreamTestCase::Empty => {} // nothing to do, will return Ok
+                StreamTestCase::ImmediateError => {
+                    tx.send(Err(Status::internal("immediate error")))
+                        .await
+                        .unwrap();
+                }
+                StreamTestCase::ErrorAfterPartialResponse => {
+
+                    eprintln!("Sending first ok response");
+                    tx.send(Ok(ServerStreamResponse {
+                        message: Some("first ok response".to_string()),
+                    }))
+                    .await
+                    .unwrap();
+
+                    eprintln!("Sending error response");
+                    let result = tx.send(Err(Status::aborted("error after partial response")))
+                        .await;
+                    eprintln!("Error send result: {:?}", result);
+
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    eprintln!("Task completing after delay");
+                }
+                StreamTestCase::NeverRespond => {
+                    pending::<()>().next().await;
+                }
+                StreamTestCase::PartialResponseThenNeverRespond => {
+                    tx.send(Ok(ServerStreamResponse {
+                        message: Some("first ok response".to_string()),
+                    }))
+                    .await
+                    .unwrap();
+
+                    pending::<()>().next().await;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+pub type IntegrationRouter = LambdaRouter<Stack<MetaEchoLayer, Stack<LogServiceNameLayer, Identity>>>;
+
+pub fn router() -> IntegrationRouter {
+    router_from(LambdaServer::builder())
+}
+
+/// The integration router on top of a server already configured by the test
+pub fn router_from(server: LambdaServer) -> IntegrationRouter {
+    let connect = ConnectConfig::new()
+        .json_descriptors(api::FILE_DESCRIPTOR_SET)
+        .expect("integration descriptors are valid");
+
+    server
+        .connect(connect)
+        .health(HealthConfig::new())
+        .layer(LogServiceNameLayer::default())
+        .layer(MetaEchoLayer::default())
+        .add_service(TestServer::with_interceptor(IntegrationTestService, AuthInterceptor))
+        .with_reflection(api::FILE_DESCRIPTOR_SET)
+        .expect("integration descriptors are valid")
+}
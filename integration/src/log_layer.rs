@@ -39,7 +39,7 @@ where
 
     fn call(&mut self, req: Request<B>) -> Self::Future {
         if let Some((service, method)) = grpc_service_method(&req) {
-            println!("gRPC request: service={}, method={}", service, method);
+            tracing::info!(service, method, "gRPC request");
         } else {
             panic!("Missing service method")
         }
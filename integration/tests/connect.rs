@@ -0,0 +1,169 @@
+//! The integration services called with the Connect protocol, in-process through the `testing`
+//! harness, as Connect-ES clients would call them via a Function URL.
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use integration::api::unary_request::UnaryTestCase;
+use integration::api::{UnaryRequest, UnaryResponse};
+use lambda_grpc_web::testing::TestInvoker;
+use prost::Message;
+use serde_json::{Value, json};
+use tower::Service;
+
+async fn connect_call(
+    path: &str,
+    content_type: &str,
+    body: impl Into<Bytes>,
+) -> http::Response<Bytes> {
+    let request = http::Request::post(path)
+        .header("content-type", content_type)
+        .header("connect-protocol-version", "1")
+        .header("echo-meta", "abc-123")
+        .body(Full::new(body.into()))
+        .unwrap();
+
+    let response = TestInvoker::new(integration::router())
+        .call(request)
+        .await
+        .unwrap();
+
+    let (parts, body) = response.into_parts();
+    http::Response::from_parts(parts, body.collect().await.unwrap().to_bytes())
+}
+
+/// Splits a Connect streaming body into its envelopes
+fn envelopes(mut body: &[u8]) -> Vec<(u8, Value)> {
+    let mut envelopes = Vec::new();
+    while !body.is_empty() {
+        let len = u32::from_be_bytes(body[1..5].try_into().unwrap()) as usize;
+        envelopes.push((body[0], serde_json::from_slice(&body[5..5 + len]).unwrap()));
+        body = &body[5 + len..];
+    }
+    envelopes
+}
+
+fn envelope(message: &[u8]) -> Vec<u8> {
+    let mut envelope = vec![0];
+    envelope.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    envelope.extend_from_slice(message);
+    envelope
+}
+
+#[tokio::test]
+async fn test_unary_proto_ok() {
+    let request = UnaryRequest {
+        test_case: UnaryTestCase::Ok.into(),
+    };
+
+    let response = connect_call(
+        "/integration.v1.Test/Unary",
+        "application/proto",
+        request.encode_to_vec(),
+    )
+    .await;
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "application/proto");
+    assert_eq!(response.headers()["meta-value"], "abc-123");
+    assert_eq!(
+        UnaryResponse::decode(response.into_body()).unwrap(),
+        UnaryResponse {
+            message: Some("ok response".to_string()),
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_unary_json_ok() {
+    let response = connect_call(
+        "/integration.v1.Test/Unary",
+        "application/json",
+        r#"{"testCase":"OK"}"#,
+    )
+    .await;
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "application/json");
+    assert_eq!(
+        serde_json::from_slice::<Value>(response.body()).unwrap(),
+        json!({ "message": "ok response" })
+    );
+}
+
+#[tokio::test]
+async fn test_unary_json_panic() {
+    let response = connect_call(
+        "/integration.v1.Test/Unary",
+        "application/json",
+        r#"{"testCase":"PANIC"}"#,
+    )
+    .await;
+
+    assert_eq!(response.status(), 500);
+    assert_eq!(
+        serde_json::from_slice::<Value>(response.body()).unwrap(),
+        json!({ "code": "internal", "message": "panic test case" })
+    );
+}
+
+#[tokio::test]
+async fn test_unary_invalid_json() {
+    let response = connect_call(
+        "/integration.v1.Test/Unary",
+        "application/json",
+        r#"{"testCase":"#,
+    )
+    .await;
+
+    assert_eq!(response.status(), 400);
+    let error: Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(error["code"], "invalid_argument");
+}
+
+#[tokio::test]
+async fn test_stream_json_ok() {
+    let response = connect_call(
+        "/integration.v1.Test/ServerStream",
+        "application/connect+json",
+        envelope(br#"{"testCase":"OK"}"#),
+    )
+    .await;
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/connect+json"
+    );
+    assert_eq!(
+        envelopes(response.body()),
+        vec![
+            (0, json!({ "message": "ok first response" })),
+            (0, json!({ "message": "ok second response" })),
+            (2, json!({})),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_stream_json_error_after_partial_response() {
+    let response = connect_call(
+        "/integration.v1.Test/ServerStream",
+        "application/connect+json",
+        envelope(br#"{"testCase":"ERROR_AFTER_PARTIAL_RESPONSE"}"#),
+    )
+    .await;
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        envelopes(response.body()),
+        vec![
+            (0, json!({ "message": "first ok response" })),
+            (
+                2,
+                json!({
+                    "error": { "code": "aborted", "message": "error after partial response" },
+                }),
+            ),
+        ]
+    );
+}
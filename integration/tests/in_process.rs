@@ -0,0 +1,320 @@
+//! The integration services driven in-process with the `testing` harness, with each call passing
+//! through `lambda_http` event parsing as it would when invoked via a Function URL.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use integration::api::health_check_response::ServingStatus;
+use integration::api::health_client::HealthClient;
+use integration::api::server_stream_request::StreamTestCase;
+use integration::api::test_client::TestClient;
+use integration::api::unary_request::UnaryTestCase;
+use integration::api::{HealthCheckRequest, ServerStreamRequest, UnaryRequest, UnaryResponse};
+use lambda_grpc_web::testing::{TestInvoker, TestTransport};
+use prost::Message;
+use std::time::Duration;
+
+fn test_client() -> TestClient<TestTransport> {
+    TestClient::new(TestTransport::new(integration::router()))
+}
+
+fn text_test_client() -> TestClient<TestTransport> {
+    TestClient::new(TestTransport::new(integration::router()).text())
+}
+
+#[tokio::test]
+async fn test_unary_ok() {
+    let request = tonic::Request::new(UnaryRequest {
+        test_case: UnaryTestCase::Ok.into(),
+    });
+
+    let response = test_client().unary(request).await.unwrap();
+
+    assert_eq!(
+        response.into_inner(),
+        UnaryResponse {
+            message: Some("ok response".to_string()),
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_unary_lambda_context() {
+    let request = tonic::Request::new(UnaryRequest {
+        test_case: UnaryTestCase::LambdaContextInHeaders.into(),
+    });
+
+    let response = test_client().unary(request).await.unwrap();
+
+    let deadline_ms = response
+        .metadata()
+        .get("lambda_ctx_deadline_ms")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .parse::<u64>()
+        .unwrap();
+
+    assert!(deadline_ms > 0);
+    assert_eq!(
+        response.metadata().get("lambda_request_source_ip").unwrap(),
+        "127.0.0.1"
+    );
+}
+
+#[tokio::test]
+async fn test_unary_panic() {
+    let request = tonic::Request::new(UnaryRequest {
+        test_case: UnaryTestCase::Panic.into(),
+    });
+
+    let err = test_client().unary(request).await.unwrap_err();
+
+    assert_eq!(err.code(), tonic::Code::Internal);
+    assert_eq!(err.message(), "panic test case");
+}
+
+#[tokio::test]
+async fn test_unary_no_response() {
+    let mut request = tonic::Request::new(UnaryRequest {
+        test_case: UnaryTestCase::NeverRespond.into(),
+    });
+    request.set_timeout(Duration::from_millis(500));
+
+    let err = tokio::time::timeout(Duration::from_secs(5), test_client().unary(request))
+        .await
+        .expect("deadline layer should terminate the request")
+        .unwrap_err();
+
+    assert_eq!(err.code(), tonic::Code::DeadlineExceeded);
+    assert_eq!(err.message(), "Client deadline (grpc-timeout) exceeded");
+}
+
+#[tokio::test]
+async fn test_stream_ok() {
+    let request = tonic::Request::new(ServerStreamRequest {
+        test_case: StreamTestCase::Ok.into(),
+    });
+
+    let mut stream = test_client()
+        .server_stream(request)
+        .await
+        .unwrap()
+        .into_inner();
+
+    let first = stream.message().await.unwrap().expect("stream message");
+    assert_eq!(first.message.unwrap(), "ok first response");
+
+    let second = stream.message().await.unwrap().expect("stream message");
+    assert_eq!(second.message.unwrap(), "ok second response");
+
+    assert_eq!(stream.message().await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_stream_error_after_partial_response() {
+    let request = tonic::Request::new(ServerStreamRequest {
+        test_case: StreamTestCase::ErrorAfterPartialResponse.into(),
+    });
+
+    let mut stream = test_client()
+        .server_stream(request)
+        .await
+        .unwrap()
+        .into_inner();
+
+    let message = stream.message().await.unwrap().expect("stream message");
+    assert_eq!(message.message.unwrap(), "first ok response");
+
+    let err = stream.message().await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Aborted);
+    assert_eq!(err.message(), "error after partial response");
+}
+
+#[tokio::test]
+async fn test_meta_echo_tower_layer() {
+    let mut request = tonic::Request::new(UnaryRequest {
+        test_case: UnaryTestCase::Ok.into(),
+    });
+
+    request
+        .metadata_mut()
+        .insert("echo-meta", "abc-123".parse().unwrap());
+
+    let response = test_client().unary(request).await.unwrap();
+
+    assert_eq!(response.metadata().get("meta-value").unwrap(), "abc-123");
+}
+
+#[tokio::test]
+async fn test_auth_interceptor() {
+    let mut request = tonic::Request::new(UnaryRequest {
+        test_case: UnaryTestCase::Ok.into(),
+    });
+
+    request
+        .metadata_mut()
+        .insert("authorization", "reject".parse().unwrap());
+
+    let err = test_client().unary(request).await.unwrap_err();
+
+    assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    assert_eq!(err.message(), "requested to reject");
+}
+
+#[tokio::test]
+async fn test_text_unary_ok() {
+    let request = tonic::Request::new(UnaryRequest {
+        test_case: UnaryTestCase::Ok.into(),
+    });
+
+    let response = text_test_client().unary(request).await.unwrap();
+
+    assert_eq!(
+        response.into_inner(),
+        UnaryResponse {
+            message: Some("ok response".to_string()),
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_text_unary_body_not_base64_encoded() {
+    // function urls may deliver the (already base64) grpc-web-text body as-is
+    let invoker = TestInvoker::new(integration::router()).base64_encoded(false);
+    let mut client = TestClient::new(TestTransport::from(invoker).text());
+
+    let request = tonic::Request::new(UnaryRequest {
+        test_case: UnaryTestCase::Ok.into(),
+    });
+
+    let response = client.unary(request).await.unwrap();
+
+    assert_eq!(response.into_inner().message.unwrap(), "ok response");
+}
+
+#[tokio::test]
+async fn test_text_unary_panic() {
+    let request = tonic::Request::new(UnaryRequest {
+        test_case: UnaryTestCase::Panic.into(),
+    });
+
+    let err = text_test_client().unary(request).await.unwrap_err();
+
+    assert_eq!(err.code(), tonic::Code::Internal);
+    assert_eq!(err.message(), "panic test case");
+}
+
+#[tokio::test]
+async fn test_text_stream_ok() {
+    let request = tonic::Request::new(ServerStreamRequest {
+        test_case: StreamTestCase::Ok.into(),
+    });
+
+    let mut stream = text_test_client()
+        .server_stream(request)
+        .await
+        .unwrap()
+        .into_inner();
+
+    let first = stream.message().await.unwrap().expect("stream message");
+    assert_eq!(first.message.unwrap(), "ok first response");
+
+    let second = stream.message().await.unwrap().expect("stream message");
+    assert_eq!(second.message.unwrap(), "ok second response");
+
+    assert_eq!(stream.message().await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_text_stream_error_after_partial_response() {
+    let request = tonic::Request::new(ServerStreamRequest {
+        test_case: StreamTestCase::ErrorAfterPartialResponse.into(),
+    });
+
+    let mut stream = text_test_client()
+        .server_stream(request)
+        .await
+        .unwrap()
+        .into_inner();
+
+    let message = stream.message().await.unwrap().expect("stream message");
+    assert_eq!(message.message.unwrap(), "first ok response");
+
+    let err = stream.message().await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Aborted);
+}
+
+#[tokio::test]
+async fn test_text_stream_frames_are_complete_base64() {
+    use http_body_util::BodyExt;
+    use tower::Service;
+
+    let request = ServerStreamRequest {
+        test_case: StreamTestCase::Ok.into(),
+    };
+    let mut body = vec![0];
+    body.extend_from_slice(&(request.encoded_len() as u32).to_be_bytes());
+    body.extend_from_slice(&request.encode_to_vec());
+
+    let request = http::Request::post("/integration.v1.Test/ServerStream")
+        .header("content-type", "application/grpc-web-text")
+        .header("accept", "*/*")
+        .body(http_body_util::Full::new(bytes::Bytes::from(
+            STANDARD.encode(body),
+        )))
+        .unwrap();
+
+    let response = TestInvoker::new(integration::router())
+        .call(request)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response.headers().get("content-type").unwrap(),
+        "application/grpc-web-text+proto"
+    );
+
+    let mut body = response.into_body();
+    let mut frames = 0;
+    while let Some(frame) = body.frame().await {
+        let data = frame.unwrap().into_data().unwrap();
+        // every frame is decodable by itself, so clients can read each message as it arrives
+        STANDARD.decode(&data).expect("complete base64 quanta");
+        frames += 1;
+    }
+
+    assert!(frames > 0);
+}
+
+#[tokio::test]
+async fn test_health_check_registered_service() {
+    let mut client = HealthClient::new(TestTransport::new(integration::router()));
+
+    let response = client
+        .check(HealthCheckRequest {
+            service: "integration.v1.Test".to_string(),
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(response.into_inner().status, ServingStatus::Serving as i32);
+}
+
+#[tokio::test]
+async fn test_health_watch_ends_before_deadline() {
+    let mut client = HealthClient::new(TestTransport::new(integration::router()));
+
+    let mut stream = client
+        .watch(HealthCheckRequest {
+            service: String::new(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    let first = stream.message().await.unwrap().unwrap();
+    assert_eq!(first.status, ServingStatus::Serving as i32);
+
+    // the stream completes with OK rather than being terminated with DEADLINE_EXCEEDED
+    assert_eq!(stream.message().await.unwrap(), None);
+}
@@ -8,6 +8,7 @@ use crate::api::{
     HealthCheckRequest, ServerStreamRequest, ServerStreamResponse, UnaryRequest, UnaryResponse,
 };
 use http::Uri;
+use std::time::Duration;
 use http::header::CONTENT_TYPE;
 use hyper_rustls::HttpsConnector;
 use hyper_util::client::legacy::Client;
@@ -147,6 +148,29 @@ async fn test_unary_lambda_context(ctx: &mut IntegrationContext) {
     assert!(deadline_ms > 0);
 }
 
+#[test_context(IntegrationContext)]
+#[tokio::test]
+async fn test_unary_lambda_deadline(ctx: &mut IntegrationContext) {
+    let mut request = tonic::Request::new(UnaryRequest {
+        test_case: UnaryTestCase::LambdaDeadlineInHeaders.into(),
+    });
+    request.set_timeout(Duration::from_secs(2));
+
+    let response = ctx.test_client.unary(request).await.unwrap();
+
+    let remaining_ms = response
+        .metadata()
+        .get("lambda_deadline_remaining_ms")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .parse::<u64>()
+        .unwrap();
+
+    assert!(remaining_ms > 0);
+    assert!(remaining_ms <= 2000);
+}
+
 #[test_context(IntegrationContext)]
 #[tokio::test]
 async fn test_stream_ok(ctx: &mut IntegrationContext) {
@@ -243,27 +267,65 @@ async fn test_stream_error_after_partial_response(ctx: &mut IntegrationContext)
     }
 }
 
+#[test_context(IntegrationContext)]
+#[tokio::test]
+async fn test_unary_no_response(ctx: &mut IntegrationContext) {
+    let mut request = tonic::Request::new(UnaryRequest {
+        test_case: UnaryTestCase::NeverRespond.into(),
+    });
+    request.set_timeout(Duration::from_millis(500));
+
+    let err = tokio::time::timeout(Duration::from_secs(5), ctx.test_client.unary(request))
+        .await
+        .expect("deadline layer should terminate the request")
+        .unwrap_err();
+
+    assert_eq!(err.code(), tonic::Code::DeadlineExceeded);
+    assert_eq!(err.message(), "Client deadline (grpc-timeout) exceeded");
+}
+
 #[test_context(IntegrationContext)]
 #[tokio::test]
 async fn test_stream_no_response(ctx: &mut IntegrationContext) {
-    let request = tonic::Request::new(ServerStreamRequest {
+    let mut request = tonic::Request::new(ServerStreamRequest {
         test_case: StreamTestCase::NeverRespond.into(),
     });
+    request.set_timeout(Duration::from_millis(500));
 
     let response = ctx.test_client.server_stream(request).await.unwrap();
 
     let mut stream = response.into_inner();
 
-    // let next = stream.message().await;
-    // dbg!(next);
-    // dbg!(stream.trailers().await);
+    let err = tokio::time::timeout(Duration::from_secs(5), stream.message())
+        .await
+        .expect("deadline layer should terminate the stream")
+        .unwrap_err();
 
-    let result =
-        tokio::time::timeout(std::time::Duration::from_millis(100), stream.message()).await;
+    assert_eq!(err.code(), tonic::Code::DeadlineExceeded);
+    assert_eq!(err.message(), "Client deadline (grpc-timeout) exceeded");
+}
 
-    assert!(result.is_err(), "Expected timeout but stream responded");
+#[test_context(IntegrationContext)]
+#[tokio::test]
+async fn test_stream_partial_response_then_no_response(ctx: &mut IntegrationContext) {
+    let mut request = tonic::Request::new(ServerStreamRequest {
+        test_case: StreamTestCase::PartialResponseThenNeverRespond.into(),
+    });
+    request.set_timeout(Duration::from_millis(500));
+
+    let response = ctx.test_client.server_stream(request).await.unwrap();
+
+    let mut stream = response.into_inner();
+
+    let message = stream.message().await.unwrap().expect("stream message");
+    assert_eq!(message.message.unwrap().as_str(), "first ok response");
+
+    let err = tokio::time::timeout(Duration::from_secs(5), stream.message())
+        .await
+        .expect("deadline layer should terminate the stream")
+        .unwrap_err();
 
-    drop(stream);
+    assert_eq!(err.code(), tonic::Code::DeadlineExceeded);
 }
 
 #[test_context(IntegrationContext)]
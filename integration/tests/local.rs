@@ -0,0 +1,167 @@
+//! Self-contained variant of the integration tests, serving the integration services on a local
+//! HTTP/1.1 listener with `serve_local` rather than needing a deployed or `cargo lambda` function.
+
+use http::Uri;
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::TokioExecutor;
+use integration::api::server_stream_request::StreamTestCase;
+use integration::api::test_client::TestClient;
+use integration::api::unary_request::UnaryTestCase;
+use integration::api::{ServerStreamRequest, UnaryRequest, UnaryResponse};
+use std::time::Duration;
+use test_context::{AsyncTestContext, test_context};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tonic::body::Body;
+use tonic_web::{GrpcWebCall, GrpcWebClientLayer, GrpcWebClientService};
+
+struct LocalContext {
+    test_client: TestClient<GrpcWebClientService<Client<HttpConnector, GrpcWebCall<Body>>>>,
+    server: JoinHandle<()>,
+}
+
+impl AsyncTestContext for LocalContext {
+    async fn setup() -> LocalContext {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin: Uri = format!("http://{}", listener.local_addr().unwrap())
+            .try_into()
+            .unwrap();
+
+        let server = tokio::spawn(async move {
+            integration::router()
+                .serve_local_with_listener(listener)
+                .await
+                .expect("local server failed");
+        });
+
+        let client = Client::builder(TokioExecutor::new()).build_http();
+
+        let svc = tower::ServiceBuilder::new()
+            .layer(GrpcWebClientLayer::new())
+            .service(client);
+
+        LocalContext {
+            test_client: TestClient::with_origin(svc, origin),
+            server,
+        }
+    }
+
+    async fn teardown(self) {
+        self.server.abort();
+    }
+}
+
+#[test_context(LocalContext)]
+#[tokio::test]
+async fn test_unary_ok(ctx: &mut LocalContext) {
+    let request = tonic::Request::new(UnaryRequest {
+        test_case: UnaryTestCase::Ok.into(),
+    });
+
+    let response = ctx.test_client.unary(request).await.unwrap();
+
+    assert_eq!(
+        response.into_inner(),
+        UnaryResponse {
+            message: Some("ok response".to_string()),
+        }
+    );
+}
+
+#[test_context(LocalContext)]
+#[tokio::test]
+async fn test_unary_lambda_context(ctx: &mut LocalContext) {
+    let request = tonic::Request::new(UnaryRequest {
+        test_case: UnaryTestCase::LambdaContextInHeaders.into(),
+    });
+
+    let response = ctx.test_client.unary(request).await.unwrap();
+
+    let deadline_ms = response
+        .metadata()
+        .get("lambda_ctx_deadline_ms")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .parse::<u64>()
+        .unwrap();
+
+    assert!(deadline_ms > 0);
+}
+
+#[test_context(LocalContext)]
+#[tokio::test]
+async fn test_unary_panic(ctx: &mut LocalContext) {
+    let request = tonic::Request::new(UnaryRequest {
+        test_case: UnaryTestCase::Panic.into(),
+    });
+
+    let err = ctx.test_client.unary(request).await.unwrap_err();
+
+    assert_eq!(err.code(), tonic::Code::Internal);
+    assert_eq!(err.message(), "panic test case");
+}
+
+#[test_context(LocalContext)]
+#[tokio::test]
+async fn test_stream_ok(ctx: &mut LocalContext) {
+    let request = tonic::Request::new(ServerStreamRequest {
+        test_case: StreamTestCase::Ok.into(),
+    });
+
+    let mut stream = ctx
+        .test_client
+        .server_stream(request)
+        .await
+        .unwrap()
+        .into_inner();
+
+    let first = stream.message().await.unwrap().expect("stream message");
+    assert_eq!(first.message.unwrap(), "ok first response");
+
+    let second = stream.message().await.unwrap().expect("stream message");
+    assert_eq!(second.message.unwrap(), "ok second response");
+
+    assert_eq!(stream.message().await.unwrap(), None);
+}
+
+#[test_context(LocalContext)]
+#[tokio::test]
+async fn test_stream_no_response(ctx: &mut LocalContext) {
+    let mut request = tonic::Request::new(ServerStreamRequest {
+        test_case: StreamTestCase::NeverRespond.into(),
+    });
+    request.set_timeout(Duration::from_millis(500));
+
+    let mut stream = ctx
+        .test_client
+        .server_stream(request)
+        .await
+        .unwrap()
+        .into_inner();
+
+    let err = tokio::time::timeout(Duration::from_secs(5), stream.message())
+        .await
+        .expect("deadline layer should terminate the stream")
+        .unwrap_err();
+
+    assert_eq!(err.code(), tonic::Code::DeadlineExceeded);
+}
+
+#[test_context(LocalContext)]
+#[tokio::test]
+async fn test_local_timeout_terminates_request(ctx: &mut LocalContext) {
+    // the default local timeout is 3s, less the 500ms deadline margin
+    let request = tonic::Request::new(UnaryRequest {
+        test_case: UnaryTestCase::NeverRespond.into(),
+    });
+
+    let err = tokio::time::timeout(Duration::from_secs(5), ctx.test_client.unary(request))
+        .await
+        .expect("deadline layer should terminate the request")
+        .unwrap_err();
+
+    assert_eq!(err.code(), tonic::Code::DeadlineExceeded);
+    assert_eq!(err.message(), "Lambda deadline exceeded");
+}
@@ -0,0 +1,263 @@
+//! End-to-end tests of `serve` and `serve_buffered` through the lambda runtime client, against a
+//! mock of the Lambda Runtime API. These assert the exact bytes the runtime streams back, which
+//! the Function URL relays to the client as-is.
+
+use bytes::Bytes;
+use integration::api::server_stream_request::StreamTestCase;
+use integration::api::unary_request::UnaryTestCase;
+use integration::api::{ServerStreamRequest, ServerStreamResponse, UnaryRequest, UnaryResponse};
+use lambda_grpc_web::LambdaServer;
+use lambda_grpc_web::testing::MockRuntimeApi;
+use prost::Message;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Notify;
+
+fn grpc_web_frame(flag: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![flag];
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn grpc_web_request(path: &str, message: impl Message) -> http::Request<Bytes> {
+    http::Request::post(path)
+        .header("content-type", "application/grpc-web+proto")
+        .body(grpc_web_frame(0, &message.encode_to_vec()).into())
+        .unwrap()
+}
+
+fn unary_request(test_case: UnaryTestCase) -> http::Request<Bytes> {
+    grpc_web_request(
+        "/integration.v1.Test/Unary",
+        UnaryRequest {
+            test_case: test_case.into(),
+        },
+    )
+}
+
+#[tokio::test]
+async fn test_streamed_unary_ok() {
+    let runtime = MockRuntimeApi::start().await.unwrap();
+
+    let response = runtime
+        .run(integration::router().serve(), async {
+            runtime
+                .invoke(unary_request(UnaryTestCase::Ok))
+                .result()
+                .await
+                .into_response()
+        })
+        .await;
+
+    assert!(response.is_streaming());
+
+    let prelude = response.prelude().expect("metadata prelude");
+    assert_eq!(prelude["statusCode"], 200);
+    assert_eq!(
+        prelude["headers"]["content-type"],
+        "application/grpc-web+proto"
+    );
+
+    let mut expected = grpc_web_frame(
+        0,
+        &UnaryResponse {
+            message: Some("ok response".to_string()),
+        }
+        .encode_to_vec(),
+    );
+    expected.extend(grpc_web_frame(0x80, b"grpc-status:0\r\n"));
+
+    assert_eq!(response.payload(), expected);
+    assert_eq!(response.trailers, None);
+}
+
+#[tokio::test]
+async fn test_streamed_unary_panic() {
+    let runtime = MockRuntimeApi::start().await.unwrap();
+
+    let response = runtime
+        .run(integration::router().serve(), async {
+            runtime
+                .invoke(unary_request(UnaryTestCase::Panic))
+                .result()
+                .await
+                .into_response()
+        })
+        .await;
+
+    // trailers-only, the status is sent in the prelude headers with an empty body
+    let prelude = response.prelude().expect("metadata prelude");
+    assert_eq!(prelude["headers"]["grpc-status"], "13");
+    assert_eq!(prelude["headers"]["grpc-message"], "panic%20test%20case");
+    assert!(response.payload().is_empty());
+}
+
+#[tokio::test]
+async fn test_streamed_server_stream() {
+    let runtime = MockRuntimeApi::start().await.unwrap();
+
+    let request = grpc_web_request(
+        "/integration.v1.Test/ServerStream",
+        ServerStreamRequest {
+            test_case: StreamTestCase::Ok.into(),
+        },
+    );
+
+    let response = runtime
+        .run(integration::router().serve(), async {
+            runtime.invoke(request).result().await.into_response()
+        })
+        .await;
+
+    let mut expected = Vec::new();
+    for message in ["ok first response", "ok second response"] {
+        let message = ServerStreamResponse {
+            message: Some(message.to_string()),
+        };
+        expected.extend(grpc_web_frame(0, &message.encode_to_vec()));
+    }
+    expected.extend(grpc_web_frame(0x80, b"grpc-status:0\r\n"));
+
+    assert_eq!(response.payload(), expected);
+}
+
+#[tokio::test]
+async fn test_consecutive_invocations() {
+    let runtime = MockRuntimeApi::start().await.unwrap();
+
+    runtime
+        .run(integration::router().serve(), async {
+            for _ in 0..3 {
+                let response = runtime
+                    .invoke(unary_request(UnaryTestCase::Ok))
+                    .result()
+                    .await
+                    .into_response();
+
+                assert_eq!(response.prelude().unwrap()["statusCode"], 200);
+            }
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn test_buffered_unary_ok() {
+    let runtime = MockRuntimeApi::start().await.unwrap();
+
+    let response = runtime
+        .run(integration::router().serve_buffered(), async {
+            runtime
+                .invoke(unary_request(UnaryTestCase::Ok))
+                .result()
+                .await
+                .into_response()
+        })
+        .await;
+
+    assert!(!response.is_streaming());
+
+    let payload: serde_json::Value = serde_json::from_slice(response.payload()).unwrap();
+    assert_eq!(payload["statusCode"], 200);
+    assert_eq!(payload["isBase64Encoded"], true);
+    assert_eq!(
+        payload["headers"]["content-type"],
+        "application/grpc-web+proto"
+    );
+}
+
+#[tokio::test]
+async fn test_init_hook_runs_before_the_first_invocation() {
+    let runtime = MockRuntimeApi::start().await.unwrap();
+    let initialized = Arc::new(AtomicBool::new(false));
+
+    let server = integration::router_from(LambdaServer::builder().on_init({
+        let initialized = initialized.clone();
+        move || {
+            let initialized = initialized.clone();
+            async move {
+                initialized.store(true, Ordering::SeqCst);
+                Ok::<_, lambda_grpc_web::lambda_runtime::Error>(())
+            }
+        }
+    }));
+
+    let response = runtime
+        .run(server.serve(), async {
+            runtime
+                .invoke(unary_request(UnaryTestCase::Ok))
+                .result()
+                .await
+                .into_response()
+        })
+        .await;
+
+    assert!(initialized.load(Ordering::SeqCst));
+    assert_eq!(response.prelude().unwrap()["statusCode"], 200);
+}
+
+#[tokio::test]
+async fn test_failed_init_hook_reports_init_error() {
+    let runtime = MockRuntimeApi::start().await.unwrap();
+
+    let server = integration::router_from(
+        LambdaServer::builder().on_init(|| async { Err::<(), _>("database unreachable") }),
+    );
+
+    let err = server.serve().await.unwrap_err();
+    assert_eq!(err.to_string(), "database unreachable");
+
+    let init_error = runtime.init_error().expect("init error was not reported");
+    assert_eq!(init_error.error_type.as_deref(), Some("Runtime.InitError"));
+    assert_eq!(init_error.body["errorType"], "Runtime.InitError");
+    assert_eq!(init_error.body["errorMessage"], "database unreachable");
+}
+
+#[tokio::test]
+async fn test_shutdown_hooks_run_on_shutdown() {
+    let runtime = MockRuntimeApi::start().await.unwrap();
+    let flushed = Arc::new(AtomicBool::new(false));
+    // stands in for Lambda shutting the function instance down
+    let shutdown = Arc::new(Notify::new());
+
+    let server = LambdaServer::builder()
+        .on_shutdown({
+            let flushed = flushed.clone();
+            move || {
+                let flushed = flushed.clone();
+                async move {
+                    flushed.store(true, Ordering::SeqCst);
+                    Ok::<_, lambda_grpc_web::lambda_runtime::Error>(())
+                }
+            }
+        })
+        .shutdown_signal({
+            let shutdown = shutdown.clone();
+            move || {
+                let shutdown = shutdown.clone();
+                async move { shutdown.notified().await }
+            }
+        });
+    let server = integration::router_from(server);
+
+    let (served, ()) = tokio::join!(server.serve(), async {
+        // the mock only hands out the invocation once the extension completed its init
+        let response = runtime
+            .invoke(unary_request(UnaryTestCase::Ok))
+            .result()
+            .await
+            .into_response();
+        assert_eq!(response.prelude().unwrap()["statusCode"], 200);
+
+        shutdown.notify_one();
+    });
+
+    served.unwrap();
+    assert!(flushed.load(Ordering::SeqCst));
+
+    let extensions = runtime.extensions();
+    assert_eq!(extensions.len(), 1);
+    assert_eq!(extensions[0].name, "_lambda-grpc-web-graceful-shutdown");
+    assert!(extensions[0].events.is_empty());
+    assert!(extensions[0].initialized);
+}
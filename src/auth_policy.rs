@@ -0,0 +1,439 @@
+//! Declarative authorization of requests per gRPC method, by the claims of the caller.
+
+use crate::auth_jwt::JwtClaims;
+use crate::grpc_call::service_method;
+use http::{Request, Response};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tonic::Status;
+use tonic::body::Body;
+use tower::{Layer, Service};
+
+type Predicate = Arc<dyn Fn(&JwtClaims) -> bool + Send + Sync>;
+
+/// What a method requires of its callers
+#[derive(Clone)]
+pub struct MethodPolicy {
+    anonymous: bool,
+    scopes: Vec<String>,
+    predicates: Vec<Predicate>,
+}
+
+impl MethodPolicy {
+    /// Any caller, with or without a bearer token
+    pub fn allow_anonymous() -> Self {
+        Self {
+            anonymous: true,
+            scopes: Vec::new(),
+            predicates: Vec::new(),
+        }
+    }
+
+    /// Any caller with a valid bearer token
+    pub fn authenticated() -> Self {
+        Self {
+            anonymous: false,
+            ..Self::allow_anonymous()
+        }
+    }
+
+    /// Callers granted every one of the scopes
+    pub fn scopes<I>(scopes: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Self::authenticated().require_scopes(scopes)
+    }
+
+    /// Also require the scopes, from the space separated `scope` claim or `scp` array
+    pub fn require_scopes<I>(mut self, scopes: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.anonymous = false;
+        self.scopes.extend(scopes.into_iter().map(Into::into));
+        self
+    }
+
+    /// Also require the claims to satisfy the predicate, i.e. to carry a role:
+    ///
+    /// ```ignore
+    /// MethodPolicy::authenticated().require(|claims| {
+    ///     claims.get("cognito:groups").and_then(|groups| groups.as_array())
+    ///         .is_some_and(|groups| groups.iter().any(|group| group == "admin"))
+    /// })
+    /// ```
+    pub fn require<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&JwtClaims) -> bool + Send + Sync + 'static,
+    {
+        self.anonymous = false;
+        self.predicates.push(Arc::new(predicate));
+        self
+    }
+
+    fn check(&self, path: &str, claims: Option<&JwtClaims>) -> Result<(), Status> {
+        if self.anonymous {
+            return Ok(());
+        }
+
+        let claims = claims.ok_or_else(|| Status::unauthenticated("Missing bearer token"))?;
+
+        if let Some(missing) = claims.missing_scope(&self.scopes) {
+            return Err(Status::permission_denied(format!(
+                "Token is missing the `{missing}` scope"
+            )));
+        }
+
+        if !self.predicates.iter().all(|predicate| predicate(claims)) {
+            return Err(Status::permission_denied(format!(
+                "Not permitted to call {path}"
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for MethodPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MethodPolicy")
+            .field("anonymous", &self.anonymous)
+            .field("scopes", &self.scopes)
+            .field("predicates", &self.predicates.len())
+            .finish()
+    }
+}
+
+/// A table of the [`MethodPolicy`] of each method, checked against the [`JwtClaims`] of every
+/// request before it reaches the user supplied layers. Methods are keyed by their request path,
+/// `/package.Service/Method`, or `/package.Service/` for every method of the service without a
+/// policy of its own.
+///
+/// Methods without any policy require an authenticated caller, unless changed with
+/// [`AuthorizationPolicy::default_policy`].
+#[derive(Clone, Debug)]
+pub struct AuthorizationPolicy {
+    policies: BTreeMap<String, MethodPolicy>,
+    default: MethodPolicy,
+}
+
+impl Default for AuthorizationPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AuthorizationPolicy {
+    pub fn new() -> Self {
+        Self {
+            policies: BTreeMap::new(),
+            default: MethodPolicy::authenticated(),
+        }
+    }
+
+    /// Set the policy of a method, or of a whole service with a path ending in `/`
+    pub fn method(mut self, path: impl Into<String>, policy: MethodPolicy) -> Self {
+        self.policies.insert(path.into(), policy);
+        self
+    }
+
+    /// The policy of methods without one of their own
+    pub fn default_policy(mut self, policy: MethodPolicy) -> Self {
+        self.default = policy;
+        self
+    }
+
+    fn policy(&self, path: &str) -> &MethodPolicy {
+        self.policies
+            .get(path)
+            .or_else(|| {
+                let (service, _) = service_method(path)?;
+                self.policies.get(&format!("/{service}/"))
+            })
+            .unwrap_or(&self.default)
+    }
+
+    /// Checks every path names a service in `services`, and that method paths name one of the
+    /// `methods`. When the methods aren't known, only whole services can be named.
+    pub(crate) fn validate(
+        &self,
+        services: &[&str],
+        methods: Option<&HashSet<String>>,
+    ) -> Result<(), PolicyError> {
+        for path in self.policies.keys() {
+            let (service, method) =
+                service_method(path).ok_or_else(|| PolicyError::InvalidPath(path.clone()))?;
+
+            if !services.contains(&service) {
+                return Err(PolicyError::UnknownService(path.clone()));
+            }
+            match methods {
+                _ if method.is_empty() => {}
+                Some(methods) if !methods.contains(path) => {
+                    return Err(PolicyError::UnknownMethod(path.clone()));
+                }
+                Some(_) => {}
+                None => return Err(PolicyError::UnverifiableMethod(path.clone())),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn layer(&self) -> AuthorizationLayer {
+        AuthorizationLayer {
+            policy: Arc::new(self.clone()),
+        }
+    }
+}
+
+/// An [`AuthorizationPolicy`] names a method the router doesn't serve
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicyError {
+    /// The path isn't of the form `/package.Service/Method` or `/package.Service/`
+    InvalidPath(String),
+    /// No service of that name was added to the router
+    UnknownService(String),
+    /// The service has no method of that name, as described to
+    /// `LambdaRouter::with_reflection`
+    UnknownMethod(String),
+    /// The path names a method, but the methods served are unknown without
+    /// `LambdaRouter::with_reflection`
+    UnverifiableMethod(String),
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyError::InvalidPath(path) => write!(f, "Invalid method path in policy: {path}"),
+            PolicyError::UnknownService(path) => {
+                write!(f, "Policy for {path} names a service that isn't served")
+            }
+            PolicyError::UnknownMethod(path) => {
+                write!(f, "Policy for {path} names a method that isn't served")
+            }
+            PolicyError::UnverifiableMethod(path) => write!(
+                f,
+                "Policy for {path} names a method, which requires the served methods to be \
+                 described with_reflection"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+#[derive(Clone)]
+pub(crate) struct AuthorizationLayer {
+    policy: Arc<AuthorizationPolicy>,
+}
+
+impl<S> Layer<S> for AuthorizationLayer {
+    type Service = AuthorizationService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuthorizationService {
+            inner,
+            policy: self.policy.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct AuthorizationService<S> {
+    inner: S,
+    policy: Arc<AuthorizationPolicy>,
+}
+
+impl<S> Service<Request<Body>> for AuthorizationService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let path = req.uri().path();
+        let claims = req.extensions().get::<JwtClaims>();
+
+        match self.policy.policy(path).check(path, claims) {
+            Ok(()) => Box::pin(self.inner.call(req)),
+            Err(status) => Box::pin(async move { Ok(status.into_http()) }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JwtAuthConfig;
+    use serde_json::json;
+    use std::convert::Infallible;
+    use tonic::Code;
+    use tower::ServiceExt;
+
+    fn claims(claims: serde_json::Value) -> JwtClaims {
+        JwtClaims::from(claims.as_object().unwrap().clone())
+    }
+
+    fn policy() -> AuthorizationPolicy {
+        AuthorizationPolicy::new()
+            .method("/test.Public/", MethodPolicy::allow_anonymous())
+            .method("/test.Public/Private", MethodPolicy::authenticated())
+            .method("/test.Orders/List", MethodPolicy::scopes(["orders:read"]))
+            .method(
+                "/test.Orders/Delete",
+                MethodPolicy::scopes(["orders:write"]).require(|claims| {
+                    claims
+                        .get("roles")
+                        .and_then(|roles| roles.as_array())
+                        .is_some_and(|roles| roles.iter().any(|role| role == "admin"))
+                }),
+            )
+    }
+
+    async fn call(path: &str, claims: Option<JwtClaims>) -> Code {
+        let inner = tower::service_fn(|_req: Request<Body>| async {
+            Ok::<_, Infallible>(Response::new(Body::empty()))
+        });
+
+        let mut req = Request::post(path).body(Body::empty()).unwrap();
+        if let Some(claims) = claims {
+            req.extensions_mut().insert(claims);
+        }
+
+        let res = policy().layer().layer(inner).oneshot(req).await.unwrap();
+        res.headers()
+            .get("grpc-status")
+            .map(|status| Code::from_bytes(status.as_bytes()))
+            .unwrap_or(Code::Ok)
+    }
+
+    #[tokio::test]
+    async fn applies_method_then_service_then_default_policy() {
+        assert_eq!(call("/test.Public/Anything", None).await, Code::Ok);
+        assert_eq!(
+            call("/test.Public/Private", None).await,
+            Code::Unauthenticated
+        );
+        assert_eq!(
+            call("/test.Other/Method", None).await,
+            Code::Unauthenticated
+        );
+        assert_eq!(
+            call("/test.Other/Method", Some(claims(json!({"sub": "user"})))).await,
+            Code::Ok
+        );
+    }
+
+    #[tokio::test]
+    async fn requires_scopes_and_predicates() {
+        let reader = claims(json!({"scope": "orders:read"}));
+        let writer = claims(json!({"scope": "orders:read orders:write"}));
+        let admin = claims(json!({"scope": "orders:write", "roles": ["admin"]}));
+
+        assert_eq!(
+            call("/test.Orders/List", Some(reader.clone())).await,
+            Code::Ok
+        );
+        assert_eq!(
+            call("/test.Orders/Delete", Some(reader)).await,
+            Code::PermissionDenied
+        );
+        assert_eq!(
+            call("/test.Orders/Delete", Some(writer)).await,
+            Code::PermissionDenied
+        );
+        assert_eq!(call("/test.Orders/Delete", Some(admin)).await, Code::Ok);
+    }
+
+    #[tokio::test]
+    async fn public_paths_still_need_an_anonymous_policy() {
+        let inner = tower::service_fn(|_req: Request<Body>| async {
+            Ok::<_, Infallible>(Response::new(Body::empty()))
+        });
+        // as composed by the router, authentication lets requests without a token through
+        let authentication = JwtAuthConfig::new("https://issuer.example.com", "api")
+            .allow_unauthenticated("/grpc.health.v1.Health/")
+            .layer()
+            .token_optional();
+        let call = |policy: AuthorizationPolicy| {
+            let svc = authentication.layer(policy.layer().layer(inner));
+            async move {
+                let req = Request::post("/grpc.health.v1.Health/Check")
+                    .body(Body::empty())
+                    .unwrap();
+                let res = svc.oneshot(req).await.unwrap();
+                res.headers()
+                    .get("grpc-status")
+                    .map(|status| Code::from_bytes(status.as_bytes()))
+                    .unwrap_or(Code::Ok)
+            }
+        };
+
+        assert_eq!(
+            call(AuthorizationPolicy::new()).await,
+            Code::Unauthenticated
+        );
+        assert_eq!(
+            call(
+                AuthorizationPolicy::new()
+                    .method("/grpc.health.v1.Health/", MethodPolicy::allow_anonymous())
+            )
+            .await,
+            Code::Ok
+        );
+    }
+
+    #[test]
+    fn validates_paths_against_the_served_methods() {
+        let methods: HashSet<String> = ["/test.Public/Private", "/test.Orders/List"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        assert_eq!(
+            policy().validate(&["test.Public", "test.Orders"], None),
+            Err(PolicyError::UnverifiableMethod(
+                "/test.Orders/Delete".to_string()
+            ))
+        );
+        assert_eq!(
+            AuthorizationPolicy::new()
+                .method("/test.Orders/", MethodPolicy::authenticated())
+                .validate(&["test.Orders"], None),
+            Ok(())
+        );
+        assert_eq!(
+            policy().validate(&["test.Public"], Some(&methods)),
+            Err(PolicyError::UnknownService(
+                "/test.Orders/Delete".to_string()
+            ))
+        );
+        assert_eq!(
+            policy().validate(&["test.Public", "test.Orders"], Some(&methods)),
+            Err(PolicyError::UnknownMethod(
+                "/test.Orders/Delete".to_string()
+            ))
+        );
+        assert_eq!(
+            AuthorizationPolicy::new()
+                .method("test.Orders/List", MethodPolicy::authenticated())
+                .validate(&["test.Orders"], None),
+            Err(PolicyError::InvalidPath("test.Orders/List".to_string()))
+        );
+    }
+}
@@ -0,0 +1,206 @@
+//! Support for the `BUFFERED` lambda invoke mode, as used behind API Gateway REST & HTTP APIs, ALBs
+//! and Function URLs not configured with `RESPONSE_STREAM`. The whole grpc-web response is
+//! collected before it is returned to the lambda runtime, so this is only suitable for unary
+//! calls or short, bounded server streams.
+
+use crate::grpc_web::{encode_trailers_frame, is_text};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use bytes::{Bytes, BytesMut};
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use http::{HeaderMap, HeaderValue, Response};
+use http_body_util::BodyExt;
+use lambda_http::tracing::log::warn;
+use tonic::Status;
+use tonic::body::Body;
+
+/// Maximum size of a synchronous lambda invocation response payload
+pub(crate) const MAX_RESPONSE_SIZE: usize = 6 * 1024 * 1024;
+
+/// Collects the grpc-web response body into a single buffered lambda response. Trailers are
+/// expected to have been encoded into the body as the final grpc-web frame by the grpc-web layer,
+/// any trailers frame still present, or status the body failed with, is encoded the same way.
+///
+/// Responses exceeding [`MAX_RESPONSE_SIZE`] are replaced with a `RESOURCE_EXHAUSTED` status.
+pub(crate) async fn buffer_response(res: Response<Body>) -> Response<lambda_http::Body> {
+    let (mut parts, mut body) = res.into_parts();
+
+    let is_text = is_text(&parts.headers);
+
+    let mut buf = BytesMut::new();
+
+    while let Some(frame) = body.frame().await {
+        let bytes = match frame {
+            Ok(frame) => match frame.into_data() {
+                Ok(data) => data,
+                Err(frame) => match frame.into_trailers() {
+                    Ok(trailers) => trailers_frame(&trailers, is_text),
+                    Err(_) => continue,
+                },
+            },
+            Err(status) => {
+                warn!("Response body failed while buffering: {status:?}");
+                let mut trailers = HeaderMap::new();
+                let _ = status.add_header(&mut trailers);
+                trailers_frame(&trailers, is_text)
+            }
+        };
+
+        buf.extend_from_slice(&bytes);
+
+        if payload_size(buf.len(), is_text) > MAX_RESPONSE_SIZE {
+            return resource_exhausted(parts);
+        }
+    }
+
+    parts.headers.remove(CONTENT_LENGTH);
+
+    let body = if buf.is_empty() {
+        lambda_http::Body::Empty
+    } else if is_text {
+        // grpc-web-text bodies are base64 already, so can be passed through as text
+        match String::from_utf8(buf.to_vec()) {
+            Ok(text) => lambda_http::Body::Text(text),
+            Err(err) => lambda_http::Body::Binary(err.into_bytes()),
+        }
+    } else {
+        lambda_http::Body::Binary(buf.to_vec())
+    };
+
+    Response::from_parts(parts, body)
+}
+
+/// Encodes the trailers frame in the encoding of the body, as the grpc-web layer does
+fn trailers_frame(trailers: &HeaderMap, is_text: bool) -> Bytes {
+    let frame = encode_trailers_frame(trailers);
+    if is_text {
+        Bytes::from(STANDARD.encode(frame))
+    } else {
+        frame
+    }
+}
+
+/// Size the body will occupy in the lambda response payload, accounting for binary bodies being
+/// base64 encoded by the runtime.
+fn payload_size(len: usize, is_text: bool) -> usize {
+    if is_text { len } else { len.div_ceil(3) * 4 }
+}
+
+fn resource_exhausted(mut parts: http::response::Parts) -> Response<lambda_http::Body> {
+    warn!(
+        "Buffered response exceeds the lambda payload limit, responding with `resource_exhausted`"
+    );
+
+    let status = Status::resource_exhausted(format!(
+        "Response exceeds the {}MB lambda payload limit",
+        MAX_RESPONSE_SIZE / 1024 / 1024
+    ));
+
+    // respond trailers-only, keeping the content type negotiated by the grpc-web layer
+    let content_type = parts.headers.get(CONTENT_TYPE).cloned();
+    parts.headers.clear();
+    parts.headers.insert(
+        CONTENT_TYPE,
+        content_type.unwrap_or(HeaderValue::from_static("application/grpc-web+proto")),
+    );
+    let _ = status.add_header(&mut parts.headers);
+
+    Response::from_parts(parts, lambda_http::Body::Empty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body::Frame;
+    use http_body_util::StreamBody;
+
+    fn grpc_web_response(frames: Vec<Frame<Bytes>>) -> Response<Body> {
+        let stream = futures_util::stream::iter(frames.into_iter().map(Ok::<_, Status>));
+        let mut res = Response::new(Body::new(StreamBody::new(stream)));
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/grpc-web+proto"),
+        );
+        res
+    }
+
+    fn ok_trailers() -> HeaderMap {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        trailers
+    }
+
+    #[tokio::test]
+    async fn collects_body_and_trailers() {
+        let res = grpc_web_response(vec![
+            Frame::data(Bytes::from_static(b"\x00\x00\x00\x00\x02hi")),
+            Frame::trailers(ok_trailers()),
+        ]);
+
+        let res = buffer_response(res).await;
+
+        let lambda_http::Body::Binary(body) = res.body() else {
+            panic!("expected binary body");
+        };
+
+        let mut expected = b"\x00\x00\x00\x00\x02hi".to_vec();
+        expected.extend_from_slice(b"\x80\x00\x00\x00\x0fgrpc-status:0\r\n");
+        assert_eq!(body, &expected);
+    }
+
+    #[tokio::test]
+    async fn text_body_passed_through_as_text() {
+        let mut res = grpc_web_response(vec![Frame::data(Bytes::from_static(b"AAAAAAJoaQ=="))]);
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/grpc-web-text+proto"),
+        );
+
+        let res = buffer_response(res).await;
+
+        assert!(matches!(res.body(), lambda_http::Body::Text(text) if text == "AAAAAAJoaQ=="));
+    }
+
+    #[tokio::test]
+    async fn text_body_failing_partway_ends_with_text_trailers() {
+        let frames = vec![
+            Ok(Frame::data(Bytes::from_static(b"AAAAAAJoaQ=="))),
+            Err(Status::internal("boom")),
+        ];
+        let mut res = Response::new(Body::new(StreamBody::new(futures_util::stream::iter(
+            frames,
+        ))));
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/grpc-web-text+proto"),
+        );
+
+        let res = buffer_response(res).await;
+
+        let lambda_http::Body::Text(text) = res.body() else {
+            panic!("expected text body");
+        };
+        let trailers = STANDARD
+            .decode(text.strip_prefix("AAAAAAJoaQ==").unwrap())
+            .unwrap();
+        assert_eq!(trailers[0], 0x80);
+        let block = String::from_utf8(trailers[5..].to_vec()).unwrap();
+        assert!(block.contains("grpc-status:13\r\n"));
+        assert!(block.contains("grpc-message:boom\r\n"));
+    }
+
+    #[tokio::test]
+    async fn oversized_response_is_resource_exhausted() {
+        let chunk = Bytes::from(vec![0u8; 1024 * 1024]);
+        let res = grpc_web_response((0..5).map(|_| Frame::data(chunk.clone())).collect());
+
+        let res = buffer_response(res).await;
+
+        assert!(matches!(res.body(), lambda_http::Body::Empty));
+        assert_eq!(res.headers().get("grpc-status").unwrap(), "8");
+        assert_eq!(
+            res.headers().get(CONTENT_TYPE).unwrap(),
+            "application/grpc-web+proto"
+        );
+    }
+}
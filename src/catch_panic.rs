@@ -0,0 +1,333 @@
+use futures_util::FutureExt;
+use lambda_http::http::{Request, Response};
+use lambda_http::tracing::error;
+use lambda_runtime::Context as LambdaContext;
+use std::{
+    any::Any,
+    fmt,
+    future::Future,
+    panic::{AssertUnwindSafe, catch_unwind},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tonic::Status;
+use tonic::metadata::MetadataValue;
+use tower::{Layer, Service};
+
+const CORRELATION_ID_METADATA: &str = "x-correlation-id";
+
+/// Maps a panic raised while handling a request to the `Status` returned to the client.
+///
+/// The default, [`PanicHandler::message`], forwards the panic message as-is, which is convenient
+/// during development but leaks internals in production. Prefer
+/// [`PanicHandler::redact_in_release`] for deployed functions.
+#[derive(Clone)]
+pub struct PanicHandler(Arc<dyn Fn(&PanicDetails) -> Status + Send + Sync>);
+
+impl PanicHandler {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&PanicDetails) -> Status + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+
+    /// Responds with `INTERNAL` and the panic message
+    pub fn message() -> Self {
+        Self::new(|panic| {
+            Status::internal(
+                panic
+                    .message()
+                    .unwrap_or("Unknown panic message")
+                    .to_string(),
+            )
+        })
+    }
+
+    /// Responds with `INTERNAL` and the panic message in debug builds, and a generic message in
+    /// release builds
+    pub fn redact_in_release() -> Self {
+        if cfg!(debug_assertions) {
+            Self::message()
+        } else {
+            Self::new(|_| Status::internal("Internal error"))
+        }
+    }
+
+    /// Attaches the lambda request id to the status produced by this handler, both appended to the
+    /// message and as `x-correlation-id` metadata, so a client report can be matched to the logs.
+    pub fn with_correlation_id(self) -> Self {
+        Self::new(move |panic| {
+            let status = (self.0)(panic);
+
+            let Some(request_id) = panic.request_id() else {
+                return status;
+            };
+
+            let mut metadata = status.metadata().clone();
+            if let Ok(value) = MetadataValue::try_from(request_id) {
+                metadata.insert(CORRELATION_ID_METADATA, value);
+            }
+
+            Status::with_details_and_metadata(
+                status.code(),
+                format!("{} (correlation id: {request_id})", status.message()),
+                status.details().to_vec().into(),
+                metadata,
+            )
+        })
+    }
+
+    fn handle(&self, panic: &PanicDetails) -> Status {
+        (self.0)(panic)
+    }
+}
+
+impl Default for PanicHandler {
+    fn default() -> Self {
+        Self::message()
+    }
+}
+
+impl fmt::Debug for PanicHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PanicHandler").finish_non_exhaustive()
+    }
+}
+
+/// A panic caught while handling a request, passed to the [`PanicHandler`]
+pub struct PanicDetails {
+    payload: Box<dyn Any + Send + 'static>,
+    path: String,
+    request_id: Option<String>,
+}
+
+impl PanicDetails {
+    /// The value the handler panicked with
+    pub fn payload(&self) -> &(dyn Any + Send + 'static) {
+        &*self.payload
+    }
+
+    /// The panic message, if the payload is a string (i.e. from `panic!` with a message)
+    pub fn message(&self) -> Option<&str> {
+        if let Some(s) = self.payload.downcast_ref::<String>() {
+            Some(s.as_str())
+        } else {
+            self.payload.downcast_ref::<&str>().copied()
+        }
+    }
+
+    /// Request path, i.e. `/package.Service/Method`
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The lambda request id, if the lambda context was available
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+}
+
+impl fmt::Debug for PanicDetails {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PanicDetails")
+            .field("message", &self.message())
+            .field("path", &self.path)
+            .field("request_id", &self.request_id)
+            .finish()
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct CatchPanicLayer {
+    handler: PanicHandler,
+}
+
+impl CatchPanicLayer {
+    pub fn new(handler: PanicHandler) -> Self {
+        Self { handler }
+    }
+}
+
+impl<S> Layer<S> for CatchPanicLayer {
+    type Service = CatchPanicService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CatchPanicService {
+            inner,
+            handler: self.handler.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct CatchPanicService<S> {
+    inner: S,
+    handler: PanicHandler,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for CatchPanicService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+    ResBody: Default + Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let path = req.uri().path().to_string();
+        let request_id = req
+            .extensions()
+            .get::<LambdaContext>()
+            .map(|ctx| ctx.request_id.clone());
+
+        let handler = self.handler.clone();
+        let on_panic = move |payload| {
+            let panic = PanicDetails {
+                payload,
+                path,
+                request_id,
+            };
+
+            error!(
+                request_id = panic.request_id(),
+                path = panic.path(),
+                message = panic.message(),
+                "panic while handling request"
+            );
+
+            Ok(handler.handle(&panic).into_http())
+        };
+
+        match catch_unwind(AssertUnwindSafe(|| self.inner.call(req))) {
+            Ok(fut) => Box::pin(async move {
+                match AssertUnwindSafe(fut).catch_unwind().await {
+                    Ok(res) => res,
+                    Err(payload) => on_panic(payload),
+                }
+            }),
+            Err(payload) => Box::pin(async move { on_panic(payload) }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use tonic::body::Body;
+
+    fn panicking_service(
+        handler: PanicHandler,
+    ) -> CatchPanicService<
+        impl Service<
+            Request<Body>,
+            Response = Response<Body>,
+            Error = Infallible,
+            Future: Send + 'static,
+        >,
+    > {
+        let inner = tower::service_fn(|_req: Request<Body>| async move {
+            if true {
+                panic!("secret internals");
+            }
+            Ok::<_, Infallible>(Response::new(Body::empty()))
+        });
+
+        CatchPanicLayer::new(handler).layer(inner)
+    }
+
+    fn request() -> Request<Body> {
+        let mut ctx = LambdaContext::default();
+        ctx.request_id = "abc-123".to_string();
+
+        let mut req = Request::builder()
+            .uri("/test.Service/Method")
+            .body(Body::empty())
+            .unwrap();
+        req.extensions_mut().insert(ctx);
+        req
+    }
+
+    fn status(res: &Response<Body>) -> Status {
+        Status::from_header_map(res.headers()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn forwards_panic_message() {
+        let res = panicking_service(PanicHandler::message())
+            .call(request())
+            .await
+            .unwrap();
+
+        let status = status(&res);
+        assert_eq!(status.code(), tonic::Code::Internal);
+        assert_eq!(status.message(), "secret internals");
+    }
+
+    #[tokio::test]
+    async fn catches_panic_in_call() {
+        let inner = tower::service_fn(
+            |_req: Request<Body>| -> std::future::Ready<Result<Response<Body>, Infallible>> {
+                panic!("panic before future")
+            },
+        );
+        let mut svc = CatchPanicLayer::new(PanicHandler::message()).layer(inner);
+
+        let res = svc.call(request()).await.unwrap();
+
+        assert_eq!(status(&res).message(), "panic before future");
+    }
+
+    #[tokio::test]
+    async fn custom_handler_receives_path() {
+        let handler =
+            PanicHandler::new(|panic| Status::unavailable(format!("{} failed", panic.path())));
+
+        let res = panicking_service(handler).call(request()).await.unwrap();
+
+        let status = status(&res);
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert_eq!(status.message(), "/test.Service/Method failed");
+    }
+
+    #[tokio::test]
+    async fn attaches_correlation_id() {
+        let handler =
+            PanicHandler::new(|_| Status::internal("Internal error")).with_correlation_id();
+
+        let res = panicking_service(handler).call(request()).await.unwrap();
+
+        assert_eq!(
+            res.headers().get(CORRELATION_ID_METADATA).unwrap(),
+            "abc-123"
+        );
+        assert_eq!(
+            status(&res).message(),
+            "Internal error (correlation id: abc-123)"
+        );
+    }
+
+    #[tokio::test]
+    async fn redacts_in_release() {
+        let res = panicking_service(PanicHandler::redact_in_release())
+            .call(request())
+            .await
+            .unwrap();
+
+        let expected = if cfg!(debug_assertions) {
+            "secret internals"
+        } else {
+            "Internal error"
+        };
+        assert_eq!(status(&res).message(), expected);
+    }
+}
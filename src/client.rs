@@ -0,0 +1,457 @@
+//! grpc-web client transport for calling a deployed function from other Rust services, optionally
+//! signing requests with SigV4 for Function URLs using the `AWS_IAM` auth type.
+//!
+//! [`LambdaChannel`] is the transport of a generated tonic client:
+//!
+//! ```ignore
+//! let channel = LambdaChannel::new("https://abc.lambda-url.eu-west-1.on.aws".parse()?)
+//!     .sigv4(SigV4Config::new(sdk_config.credentials_provider().unwrap(), "eu-west-1"));
+//! let mut client = GreeterClient::new(channel);
+//! ```
+//!
+//! Inside AWS, [`InvokeChannel`] calls the function through the Lambda `Invoke` API instead,
+//! without any HTTP endpoint.
+
+mod invoke;
+
+pub use invoke::InvokeChannel;
+
+use aws_credential_types::provider::{ProvideCredentials, SharedCredentialsProvider};
+use aws_sigv4::http_request::{
+    PayloadChecksumKind, SignableBody, SignableRequest, SigningSettings, sign,
+};
+use aws_sigv4::sign::v4;
+use bytes::Bytes;
+use http::uri::{Parts, PathAndQuery};
+use http::{Request, Response, Uri};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::TokioExecutor;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+use tonic::body::Body;
+use tonic_web::{GrpcWebCall, GrpcWebClientLayer};
+use tower::{BoxError, Layer, Service};
+
+pub use aws_credential_types::Credentials;
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = Result<T, BoxError>> + Send>>;
+
+/// Signs requests with SigV4, for functions only invocable by IAM principals
+#[derive(Clone, Debug)]
+pub struct SigV4Config {
+    credentials: SharedCredentialsProvider,
+    region: String,
+    service: String,
+}
+
+impl SigV4Config {
+    /// Sign with credentials from the provider, i.e. fixed [`Credentials`] or the provider of an
+    /// `aws_config::SdkConfig`, which are fetched again for every request so refreshed credentials
+    /// are picked up.
+    pub fn new(credentials: impl ProvideCredentials + 'static, region: impl Into<String>) -> Self {
+        Self {
+            credentials: SharedCredentialsProvider::new(credentials),
+            region: region.into(),
+            service: "lambda".to_string(),
+        }
+    }
+
+    /// The service signed for. Defaults to `lambda`, as for Function URLs, use `execute-api` for
+    /// API Gateway routes with IAM authorization.
+    pub fn service(mut self, service: impl Into<String>) -> Self {
+        self.service = service.into();
+        self
+    }
+
+    async fn sign(&self, req: &mut Request<Full<Bytes>>, body: &[u8]) -> Result<(), BoxError> {
+        let identity = self.credentials.provide_credentials().await?.into();
+
+        let mut settings = SigningSettings::default();
+        settings.payload_checksum_kind = PayloadChecksumKind::XAmzSha256;
+
+        let params = v4::SigningParams::builder()
+            .identity(&identity)
+            .region(&self.region)
+            .name(&self.service)
+            .time(SystemTime::now())
+            .settings(settings)
+            .build()?
+            .into();
+
+        // headers that aren't valid strings are left unsigned, AWS only checks those signed
+        let uri = req.uri().to_string();
+        let headers = req
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)));
+        let signable = SignableRequest::new(
+            req.method().as_str(),
+            uri,
+            headers,
+            SignableBody::Bytes(body),
+        )?;
+
+        let (instructions, _) = sign(signable, &params)?.into_parts();
+        instructions.apply_to_request_http1x(req);
+
+        Ok(())
+    }
+}
+
+/// Transport of tonic clients calling a function over grpc-web, at its Function URL or behind
+/// API Gateway. Requests are sent to the origin regardless of the origin the client was built
+/// with, so create clients with `new` rather than `with_origin`.
+///
+/// Request bodies are buffered to be signed, which grpc-web limits to a single message anyway.
+/// Responses are streamed as they arrive, for server streaming methods.
+#[derive(Clone)]
+pub struct LambdaChannel {
+    transport: Transport,
+}
+
+impl LambdaChannel {
+    /// Call the function at the origin, i.e. `https://abc.lambda-url.eu-west-1.on.aws`
+    pub fn new(origin: Uri) -> Self {
+        Self {
+            transport: Transport {
+                origin,
+                client: https_client(),
+                signer: None,
+            },
+        }
+    }
+
+    /// Sign every request with SigV4
+    pub fn sigv4(mut self, config: SigV4Config) -> Self {
+        self.transport.signer = Some(Arc::new(config));
+        self
+    }
+}
+
+impl Service<Request<Body>> for LambdaChannel {
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = BoxFuture<Self::Response>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let fut = GrpcWebClientLayer::new()
+            .layer(self.transport.clone())
+            .call(req);
+
+        Box::pin(async move { Ok(fut.await?.map(Body::new)) })
+    }
+}
+
+type HttpsClient = Client<HttpsConnector<HttpConnector>, Full<Bytes>>;
+
+fn https_client() -> HttpsClient {
+    let connector = HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+
+    Client::builder(TokioExecutor::new()).build(connector)
+}
+
+#[derive(Clone)]
+struct Transport {
+    origin: Uri,
+    client: HttpsClient,
+    signer: Option<Arc<SigV4Config>>,
+}
+
+impl Transport {
+    /// The request path on the origin
+    fn uri(&self, uri: &Uri) -> Result<Uri, BoxError> {
+        let mut parts = Parts::from(self.origin.clone());
+        parts.path_and_query = uri
+            .path_and_query()
+            .cloned()
+            .or(Some(PathAndQuery::from_static("/")));
+        Ok(Uri::from_parts(parts)?)
+    }
+}
+
+impl Service<Request<GrpcWebCall<Body>>> for Transport {
+    type Response = Response<Incoming>;
+    type Error = BoxError;
+    type Future = BoxFuture<Self::Response>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<GrpcWebCall<Body>>) -> Self::Future {
+        let this = self.clone();
+
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+            parts.uri = this.uri(&parts.uri)?;
+
+            let body = body.collect().await?.to_bytes();
+            let mut req = Request::from_parts(parts, Full::new(body.clone()));
+            if let Some(signer) = &this.signer {
+                signer.sign(&mut req, &body).await?;
+            }
+
+            Ok(this.client.request(req).await?)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grpc_web::{FRAME_HEADER_SIZE, encode_frame, encode_trailers_frame};
+    use bytes::{Buf, BufMut};
+    use futures_util::StreamExt;
+    use hmac::{Hmac, Mac};
+    use http::{HeaderMap, HeaderValue};
+    use http_body_util::StreamBody;
+    use hyper::body::Frame;
+    use hyper::server::conn::http1;
+    use hyper_util::rt::TokioIo;
+    use sha2::{Digest, Sha256};
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+    use tokio::net::TcpListener;
+    use tonic::codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder};
+    use tonic::{Code, Status};
+
+    pub(super) const ACCESS_KEY: &str = "AKIDEXAMPLE";
+    pub(super) const SECRET_KEY: &str = "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY";
+    pub(super) const REGION: &str = "eu-west-1";
+
+    /// Passes message bytes through as is
+    #[derive(Default)]
+    pub(super) struct RawCodec;
+
+    impl Codec for RawCodec {
+        type Encode = Bytes;
+        type Decode = Bytes;
+        type Encoder = RawCodec;
+        type Decoder = RawCodec;
+
+        fn encoder(&mut self) -> Self::Encoder {
+            RawCodec
+        }
+
+        fn decoder(&mut self) -> Self::Decoder {
+            RawCodec
+        }
+    }
+
+    impl Encoder for RawCodec {
+        type Item = Bytes;
+        type Error = Status;
+
+        fn encode(&mut self, item: Bytes, dst: &mut EncodeBuf<'_>) -> Result<(), Status> {
+            dst.put(item);
+            Ok(())
+        }
+    }
+
+    impl Decoder for RawCodec {
+        type Item = Bytes;
+        type Error = Status;
+
+        fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<Bytes>, Status> {
+            Ok(Some(src.copy_to_bytes(src.remaining())))
+        }
+    }
+
+    fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+        mac.update(data.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn sha256_hex(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    /// Verifies the SigV4 signature of the request the way the Function URL would, independently
+    /// of the signing implementation
+    pub(super) fn verify_signature(parts: &http::request::Parts, body: &[u8]) -> bool {
+        let header = |name: &str| parts.headers.get(name).and_then(|v| v.to_str().ok());
+
+        let Some(auth) = header("authorization").and_then(|v| v.strip_prefix("AWS4-HMAC-SHA256 "))
+        else {
+            return false;
+        };
+        let fields: HashMap<&str, &str> = auth
+            .split(", ")
+            .filter_map(|field| field.split_once('='))
+            .collect();
+        let (Some(credential), Some(signed_headers), Some(signature), Some(amz_date)) = (
+            fields.get("Credential"),
+            fields.get("SignedHeaders"),
+            fields.get("Signature"),
+            header("x-amz-date"),
+        ) else {
+            return false;
+        };
+        let Some((ACCESS_KEY, scope)) = credential.split_once('/') else {
+            return false;
+        };
+        let [date, REGION, "lambda", "aws4_request"] = scope.split('/').collect::<Vec<_>>()[..]
+        else {
+            return false;
+        };
+        if header("x-amz-content-sha256") != Some(sha256_hex(body).as_str()) {
+            return false;
+        }
+
+        let mut canonical_headers = String::new();
+        for name in signed_headers.split(';') {
+            let Some(value) = header(name) else {
+                return false;
+            };
+            canonical_headers.push_str(&format!("{name}:{}\n", value.trim()));
+        }
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{canonical_headers}\n{signed_headers}\n{}",
+            parts.method,
+            // every service but S3 encodes the already encoded path again
+            parts.uri.path().replace('%', "%25"),
+            parts.uri.query().unwrap_or_default(),
+            sha256_hex(body)
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            sha256_hex(canonical_request.as_bytes())
+        );
+
+        let key = hmac(format!("AWS4{SECRET_KEY}").as_bytes(), date);
+        let key = hmac(&key, REGION);
+        let key = hmac(&key, "lambda");
+        let key = hmac(&key, "aws4_request");
+
+        hex::encode(hmac(&key, &string_to_sign)) == *signature
+    }
+
+    /// Stands in for an `AWS_IAM` Function URL, rejecting requests without a valid signature
+    /// with 403 and echoing the request message three times to those with one
+    async fn function_url() -> Uri {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let service = hyper::service::service_fn(|req: Request<Incoming>| async move {
+                    let (parts, body) = req.into_parts();
+                    let body = body.collect().await.unwrap().to_bytes();
+
+                    if !verify_signature(&parts, &body) {
+                        let mut res =
+                            Response::new(StreamBody::new(futures_util::stream::empty().boxed()));
+                        *res.status_mut() = http::StatusCode::FORBIDDEN;
+                        return Ok::<_, Infallible>(res);
+                    }
+
+                    let message = body.slice(FRAME_HEADER_SIZE..);
+                    let mut trailers = HeaderMap::new();
+                    trailers.insert("grpc-status", HeaderValue::from_static("0"));
+                    let frames = [
+                        encode_frame(0, &message),
+                        encode_frame(0, &message),
+                        encode_frame(0, &message),
+                        encode_trailers_frame(&trailers),
+                    ];
+                    let stream = futures_util::stream::iter(frames)
+                        .map(|frame| Ok::<_, Infallible>(Frame::data(frame)))
+                        .boxed();
+
+                    let mut res = Response::new(StreamBody::new(stream));
+                    res.headers_mut().insert(
+                        http::header::CONTENT_TYPE,
+                        HeaderValue::from_static("application/grpc-web+proto"),
+                    );
+                    Ok(res)
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+
+        origin
+    }
+
+    fn channel(origin: Uri, secret: &str) -> LambdaChannel {
+        LambdaChannel::new(origin).sigv4(SigV4Config::new(
+            Credentials::new(ACCESS_KEY, secret, None, None, "test"),
+            REGION,
+        ))
+    }
+
+    #[tokio::test]
+    async fn signs_requests_and_streams_responses() {
+        let mut client = tonic::client::Grpc::new(channel(function_url().await, SECRET_KEY));
+        client.ready().await.unwrap();
+
+        let res = client
+            .server_streaming(
+                tonic::Request::new(Bytes::from_static(b"hello")),
+                PathAndQuery::from_static("/test.Echo/Stream"),
+                RawCodec,
+            )
+            .await
+            .unwrap();
+        let messages: Vec<Bytes> = res
+            .into_inner()
+            .map(|message| message.unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(messages, vec![Bytes::from_static(b"hello"); 3]);
+    }
+
+    #[tokio::test]
+    async fn rejected_signatures_are_permission_denied() {
+        let origin = function_url().await;
+
+        for channel in [channel(origin.clone(), "wrong"), LambdaChannel::new(origin)] {
+            let mut client = tonic::client::Grpc::new(channel);
+            client.ready().await.unwrap();
+
+            let status = client
+                .unary(
+                    tonic::Request::new(Bytes::from_static(b"hello")),
+                    PathAndQuery::from_static("/test.Echo/Unary"),
+                    RawCodec,
+                )
+                .await
+                .unwrap_err();
+
+            assert_eq!(status.code(), Code::PermissionDenied);
+        }
+    }
+
+    #[test]
+    fn requests_go_to_the_origin() {
+        let channel =
+            LambdaChannel::new("https://abc.lambda-url.eu-west-1.on.aws".parse().unwrap());
+
+        assert_eq!(
+            channel
+                .transport
+                .uri(&"http://[::1]/test.Echo/Unary?a=b".parse().unwrap())
+                .unwrap(),
+            "https://abc.lambda-url.eu-west-1.on.aws/test.Echo/Unary?a=b"
+        );
+    }
+}
@@ -0,0 +1,840 @@
+//! Transport calling the function through the Lambda `Invoke` and `InvokeWithResponseStream`
+//! APIs, with each grpc-web request packed into an HTTP API event.
+
+use super::{HttpsClient, SigV4Config, https_client};
+use crate::function_url;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::stream::{self, BoxStream};
+use futures_util::{StreamExt, TryStreamExt};
+use http::header::CONTENT_TYPE;
+use http::{HeaderMap, HeaderName, HeaderValue, Request, Response, StatusCode, Uri};
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::{Frame, Incoming};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tonic::body::Body;
+use tonic::{Code, Status};
+use tonic_web::{GrpcWebCall, GrpcWebClientLayer};
+use tower::{BoxError, Layer, Service};
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = Result<T, BoxError>> + Send>>;
+type PayloadStream = BoxStream<'static, Result<Bytes, Status>>;
+
+/// Separates the JSON prelude of a streamed response from its body
+const PRELUDE_DELIMITER: [u8; 8] = [0; 8];
+
+/// Size of the total and headers lengths and the CRC prefixing every event stream message
+const EVENT_PRELUDE_SIZE: usize = 12;
+
+/// Transport of tonic clients calling a function through the Lambda API, for service to service
+/// calls within AWS. Requests are packed into the same HTTP API event a Function URL delivers, so
+/// the function needs no changes to be called either way. The events carry no caller identity, and
+/// appear to handlers as `EventSource::Invoke` in `LambdaRequestInfo`.
+///
+/// Requests are signed with the [`SigV4Config`], for a principal allowed `lambda:InvokeFunction`.
+/// Functions failing outright, i.e. timing out, fail the call with `INTERNAL`, or
+/// `DEADLINE_EXCEEDED` for timeouts, and errors of the Lambda API map to the closest status.
+#[derive(Clone)]
+pub struct InvokeChannel {
+    transport: InvokeTransport,
+}
+
+impl InvokeChannel {
+    /// Invoke the function, by name or ARN, in the region of the signing config
+    pub fn new(function_name: impl Into<String>, signer: SigV4Config) -> Self {
+        Self {
+            transport: InvokeTransport {
+                function_name: function_name.into(),
+                qualifier: None,
+                endpoint: None,
+                response_stream: false,
+                signer: Arc::new(signer),
+                client: https_client(),
+            },
+        }
+    }
+
+    /// Invoke a version or alias of the function
+    pub fn qualifier(mut self, qualifier: impl Into<String>) -> Self {
+        self.transport.qualifier = Some(qualifier.into());
+        self
+    }
+
+    /// Call the Lambda API at the endpoint rather than `https://lambda.{region}.amazonaws.com`,
+    /// i.e. a VPC endpoint
+    pub fn endpoint(mut self, endpoint: Uri) -> Self {
+        self.transport.endpoint = Some(endpoint);
+        self
+    }
+
+    /// Invoke with `InvokeWithResponseStream`, so responses of functions served with
+    /// `LambdaRouter::serve` are read as they are streamed rather than once the function returns.
+    /// Functions served with `LambdaRouter::serve_buffered` respond all at once either way.
+    pub fn response_stream(mut self) -> Self {
+        self.transport.response_stream = true;
+        self
+    }
+}
+
+impl Service<Request<Body>> for InvokeChannel {
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = BoxFuture<Self::Response>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let fut = GrpcWebClientLayer::new()
+            .layer(self.transport.clone())
+            .call(req);
+
+        Box::pin(async move { Ok(fut.await?.map(Body::new)) })
+    }
+}
+
+#[derive(Clone)]
+struct InvokeTransport {
+    function_name: String,
+    qualifier: Option<String>,
+    endpoint: Option<Uri>,
+    response_stream: bool,
+    signer: Arc<SigV4Config>,
+    client: HttpsClient,
+}
+
+impl InvokeTransport {
+    fn uri(&self) -> Result<Uri, BoxError> {
+        let endpoint = match &self.endpoint {
+            Some(endpoint) => endpoint.to_string(),
+            None => format!("https://lambda.{}.amazonaws.com", self.signer.region),
+        };
+        let path = if self.response_stream {
+            "2021-11-15/functions/{}/response-streaming-invocations"
+        } else {
+            "2015-03-31/functions/{}/invocations"
+        };
+
+        let mut uri = format!(
+            "{}/{}",
+            endpoint.trim_end_matches('/'),
+            path.replace("{}", &escape(&self.function_name))
+        );
+        if let Some(qualifier) = &self.qualifier {
+            uri.push_str("?Qualifier=");
+            uri.push_str(&escape(qualifier));
+        }
+
+        Ok(uri.parse()?)
+    }
+}
+
+impl Service<Request<GrpcWebCall<Body>>> for InvokeTransport {
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = BoxFuture<Self::Response>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<GrpcWebCall<Body>>) -> Self::Future {
+        let this = self.clone();
+
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = BodyExt::collect(body).await?.to_bytes();
+
+            let payload = Bytes::from(function_url::invoke_event(&parts, &body).to_string());
+
+            let mut req = Request::post(this.uri()?)
+                .header(CONTENT_TYPE, "application/json")
+                .body(Full::new(payload.clone()))?;
+            this.signer.sign(&mut req, &payload).await?;
+
+            let res = this.client.request(req).await?;
+            if !res.status().is_success() {
+                return Err(api_error(res).await.into());
+            }
+            if res.headers().contains_key("x-amz-function-error") {
+                let payload = res.into_body().collect().await?.to_bytes();
+                return Err(function_error(&payload).into());
+            }
+
+            let chunks = res
+                .into_body()
+                .into_data_stream()
+                .map_err(|err| Status::unavailable(err.to_string()))
+                .boxed();
+            let payload = if this.response_stream {
+                event_stream_payload(chunks)
+            } else {
+                chunks
+            };
+
+            Ok(decode_response(payload).await?)
+        })
+    }
+}
+
+/// Percent encodes everything but unreserved characters, i.e. the colons of function ARNs
+fn escape(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// An error of the Lambda API itself, i.e. the function doesn't exist or the caller may not
+/// invoke it
+async fn api_error(res: Response<Incoming>) -> Status {
+    let code = match res.status() {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Code::PermissionDenied,
+        StatusCode::NOT_FOUND => Code::NotFound,
+        StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+        status if status.is_server_error() => Code::Unavailable,
+        _ => Code::Internal,
+    };
+    let error_type = res
+        .headers()
+        .get("x-amzn-errortype")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(':').next())
+        .unwrap_or("Error")
+        .to_string();
+
+    let body = match res.into_body().collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(_) => Bytes::new(),
+    };
+    let message = serde_json::from_slice::<Value>(&body)
+        .ok()
+        .and_then(|error| {
+            let message = error.get("message").or_else(|| error.get("Message"))?;
+            message.as_str().map(str::to_string)
+        })
+        .unwrap_or_else(|| String::from_utf8_lossy(&body).into_owned());
+
+    Status::new(code, format!("Lambda {error_type}: {message}"))
+}
+
+/// The function failed rather than responding, i.e. it timed out or its runtime crashed
+fn function_error(payload: &[u8]) -> Status {
+    let error: Value = serde_json::from_slice(payload).unwrap_or_default();
+    let error_type = error["errorType"].as_str().unwrap_or("Unhandled");
+    let message = format!(
+        "{error_type}: {}",
+        error["errorMessage"].as_str().unwrap_or_default()
+    );
+
+    if error_type.ends_with("Timedout") {
+        Status::deadline_exceeded(message)
+    } else {
+        Status::internal(message)
+    }
+}
+
+fn malformed_response() -> Status {
+    Status::internal("Malformed function response")
+}
+
+/// Reads the response of the function, either streamed as a JSON prelude followed by the body, or
+/// buffered as a single JSON response
+async fn decode_response(mut payload: PayloadStream) -> Result<Response<Body>, Status> {
+    let mut buf = BytesMut::new();
+
+    loop {
+        if let Some(end) = buf
+            .windows(PRELUDE_DELIMITER.len())
+            .position(|w| w == PRELUDE_DELIMITER)
+        {
+            let prelude: Value =
+                serde_json::from_slice(&buf[..end]).map_err(|_| malformed_response())?;
+            let rest = buf.split_off(end + PRELUDE_DELIMITER.len()).freeze();
+
+            let body = stream::iter((!rest.is_empty()).then_some(Ok(rest))).chain(payload);
+            return response(&prelude, body.boxed());
+        }
+
+        match payload.next().await {
+            Some(chunk) => buf.extend_from_slice(&chunk?),
+            None => break,
+        }
+    }
+
+    let res: Value = serde_json::from_slice(&buf).map_err(|_| malformed_response())?;
+    let body = res.get("body").and_then(Value::as_str).unwrap_or_default();
+    let body = if res.get("isBase64Encoded").and_then(Value::as_bool) == Some(true) {
+        STANDARD.decode(body).map_err(|_| malformed_response())?
+    } else {
+        body.as_bytes().to_vec()
+    };
+
+    response(&res, stream::once(async { Ok(Bytes::from(body)) }).boxed())
+}
+
+/// The response with the status code and headers of the prelude or buffered response
+fn response(parts: &Value, body: PayloadStream) -> Result<Response<Body>, Status> {
+    let status = parts
+        .get("statusCode")
+        .and_then(Value::as_u64)
+        .and_then(|status| StatusCode::from_u16(status as u16).ok())
+        .ok_or_else(malformed_response)?;
+
+    let mut headers = HeaderMap::new();
+    let fields = parts
+        .get("multiValueHeaders")
+        .or_else(|| parts.get("headers"))
+        .and_then(Value::as_object);
+    for (name, value) in fields.into_iter().flatten() {
+        let Ok(name) = HeaderName::from_bytes(name.as_bytes()) else {
+            continue;
+        };
+        for value in header_values(value) {
+            if let Ok(value) = HeaderValue::from_str(value) {
+                headers.append(name.clone(), value);
+            }
+        }
+    }
+
+    // the grpc-web client only finds the trailers frame at the start of a chunk of binary bodies
+    let text = headers.get(CONTENT_TYPE).is_some_and(|content_type| {
+        content_type
+            .as_bytes()
+            .starts_with(b"application/grpc-web-text")
+    });
+    let body = if text { body } else { grpc_web_frames(body) };
+
+    let mut res = Response::new(Body::new(StreamBody::new(body.map_ok(Frame::data))));
+    *res.status_mut() = status;
+    *res.headers_mut() = headers;
+    Ok(res)
+}
+
+/// Rechunks a binary grpc-web body into one chunk per frame
+fn grpc_web_frames(chunks: PayloadStream) -> PayloadStream {
+    stream::unfold(
+        (chunks, BytesMut::new(), false),
+        |(mut chunks, mut buf, mut done)| async move {
+            loop {
+                let frame_len = (buf.len() >= 5)
+                    .then(|| 5 + u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize);
+                match frame_len {
+                    Some(len) if buf.len() >= len => {
+                        return Some((Ok(buf.split_to(len).freeze()), (chunks, buf, done)));
+                    }
+                    _ if done => {
+                        return (!buf.is_empty())
+                            .then(|| (Ok(buf.split().freeze()), (chunks, BytesMut::new(), done)));
+                    }
+                    _ => {}
+                }
+
+                match chunks.next().await {
+                    Some(Ok(chunk)) => buf.extend_from_slice(&chunk),
+                    Some(Err(status)) => {
+                        return Some((Err(status), (chunks, BytesMut::new(), true)));
+                    }
+                    None => done = true,
+                }
+            }
+        },
+    )
+    .boxed()
+}
+
+/// The values of a header, as a single string or the array of `multiValueHeaders`
+fn header_values(value: &Value) -> Vec<&str> {
+    match value {
+        Value::Array(values) => values.iter().filter_map(Value::as_str).collect(),
+        value => value.as_str().into_iter().collect(),
+    }
+}
+
+/// The response streamed by the function, from the `PayloadChunk` events of the
+/// `application/vnd.amazon.eventstream` response of `InvokeWithResponseStream`
+fn event_stream_payload(chunks: PayloadStream) -> PayloadStream {
+    struct State {
+        chunks: PayloadStream,
+        decoder: EventStreamDecoder,
+        done: bool,
+    }
+
+    let state = State {
+        chunks,
+        decoder: EventStreamDecoder::default(),
+        done: false,
+    };
+
+    stream::unfold(state, |mut state| async move {
+        if state.done {
+            return None;
+        }
+
+        loop {
+            let event = state
+                .decoder
+                .next_message()
+                .and_then(|message| message.map(Event::try_from).transpose());
+
+            match event {
+                Ok(Some(Event::PayloadChunk(chunk))) => return Some((Ok(chunk), state)),
+                Ok(Some(Event::InvokeComplete)) => return None,
+                Ok(Some(Event::Other)) => continue,
+                Ok(None) => {}
+                Err(status) => {
+                    state.done = true;
+                    return Some((Err(status), state));
+                }
+            }
+
+            match state.chunks.next().await {
+                Some(Ok(chunk)) => state.decoder.push(chunk),
+                Some(Err(status)) => {
+                    state.done = true;
+                    return Some((Err(status), state));
+                }
+                None => {
+                    state.done = true;
+                    let status = Status::unavailable("Response stream ended before completing");
+                    return Some((Err(status), state));
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+enum Event {
+    PayloadChunk(Bytes),
+    InvokeComplete,
+    Other,
+}
+
+impl TryFrom<EventMessage> for Event {
+    type Error = Status;
+
+    fn try_from(message: EventMessage) -> Result<Self, Status> {
+        let header = |name: &str| message.headers.get(name).map(String::as_str);
+
+        match (header(":message-type"), header(":event-type")) {
+            (Some("event"), Some("PayloadChunk")) => Ok(Event::PayloadChunk(message.payload)),
+            (Some("event"), Some("InvokeComplete")) => {
+                let complete: Value = serde_json::from_slice(&message.payload).unwrap_or_default();
+                match complete["ErrorCode"].as_str() {
+                    Some(code) => Err(Status::internal(format!(
+                        "{code}: {}",
+                        complete["ErrorDetails"].as_str().unwrap_or_default()
+                    ))),
+                    None => Ok(Event::InvokeComplete),
+                }
+            }
+            (Some("event"), _) => Ok(Event::Other),
+            _ => {
+                let error: Value = serde_json::from_slice(&message.payload).unwrap_or_default();
+                let error_type = header(":exception-type")
+                    .or(header(":error-code"))
+                    .unwrap_or("Error");
+                let detail = error["message"]
+                    .as_str()
+                    .or(error["Message"].as_str())
+                    .or(header(":error-message"))
+                    .unwrap_or_default();
+                Err(Status::unavailable(format!(
+                    "Lambda {error_type}: {detail}"
+                )))
+            }
+        }
+    }
+}
+
+/// A message of an `application/vnd.amazon.eventstream` stream, with its string headers
+struct EventMessage {
+    headers: HashMap<String, String>,
+    payload: Bytes,
+}
+
+/// Splits an event stream arriving in arbitrary chunks back into messages, checking their CRCs
+#[derive(Default)]
+struct EventStreamDecoder {
+    buf: BytesMut,
+}
+
+impl EventStreamDecoder {
+    fn push(&mut self, data: Bytes) {
+        self.buf.extend_from_slice(&data);
+    }
+
+    fn next_message(&mut self) -> Result<Option<EventMessage>, Status> {
+        let malformed = || Status::internal("Malformed response event stream");
+        let u32_at = |buf: &[u8], at: usize| {
+            u32::from_be_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]])
+        };
+
+        if self.buf.len() < EVENT_PRELUDE_SIZE {
+            return Ok(None);
+        }
+
+        let total_len = u32_at(&self.buf, 0) as usize;
+        let headers_len = u32_at(&self.buf, 4) as usize;
+        if crc32fast::hash(&self.buf[..8]) != u32_at(&self.buf, 8)
+            || total_len < EVENT_PRELUDE_SIZE + headers_len + 4
+        {
+            return Err(malformed());
+        }
+        if self.buf.len() < total_len {
+            return Ok(None);
+        }
+
+        let message = self.buf.split_to(total_len).freeze();
+        if crc32fast::hash(&message[..total_len - 4]) != u32_at(&message, total_len - 4) {
+            return Err(malformed());
+        }
+
+        let headers = message.slice(EVENT_PRELUDE_SIZE..EVENT_PRELUDE_SIZE + headers_len);
+        Ok(Some(EventMessage {
+            headers: decode_headers(headers).ok_or_else(malformed)?,
+            payload: message.slice(EVENT_PRELUDE_SIZE + headers_len..total_len - 4),
+        }))
+    }
+}
+
+/// Reads the string headers of an event stream message, skipping those of other types
+fn decode_headers(mut buf: Bytes) -> Option<HashMap<String, String>> {
+    let mut headers = HashMap::new();
+
+    while buf.has_remaining() {
+        let name_len = buf.get_u8() as usize;
+        if buf.remaining() < name_len + 1 {
+            return None;
+        }
+        let name = String::from_utf8(buf.split_to(name_len).to_vec()).ok()?;
+
+        let value_len = match buf.get_u8() {
+            // true & false
+            0 | 1 => 0,
+            // byte, short, integer & long
+            2 => 1,
+            3 => 2,
+            4 => 4,
+            5 => 8,
+            // byte array & string
+            6 | 7 if buf.remaining() >= 2 => buf.get_u16() as usize,
+            // timestamp & uuid
+            8 => 8,
+            9 => 16,
+            _ => return None,
+        };
+        if buf.remaining() < value_len {
+            return None;
+        }
+        let value = buf.split_to(value_len);
+
+        if let Ok(value) = String::from_utf8(value.to_vec()) {
+            headers.insert(name, value);
+        }
+    }
+
+    Some(headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Credentials;
+    use crate::client::tests::{ACCESS_KEY, REGION, RawCodec, SECRET_KEY, verify_signature};
+    use crate::grpc_web::{FRAME_HEADER_SIZE, encode_frame, encode_trailers_frame};
+    use bytes::BufMut;
+    use http::uri::PathAndQuery;
+    use hyper::server::conn::http1;
+    use hyper_util::rt::TokioIo;
+    use serde_json::json;
+    use std::convert::Infallible;
+    use tokio::net::TcpListener;
+
+    /// Encodes an event stream message, with a boolean header to be skipped
+    fn event_message(event_type: &str, payload: &[u8]) -> Bytes {
+        let mut headers = BytesMut::new();
+        for (name, value) in [(":message-type", "event"), (":event-type", event_type)] {
+            headers.put_u8(name.len() as u8);
+            headers.put_slice(name.as_bytes());
+            headers.put_u8(7);
+            headers.put_u16(value.len() as u16);
+            headers.put_slice(value.as_bytes());
+        }
+        headers.put_u8(4);
+        headers.put_slice(b"flag");
+        headers.put_u8(0);
+
+        let mut message = BytesMut::new();
+        message.put_u32((EVENT_PRELUDE_SIZE + headers.len() + payload.len() + 4) as u32);
+        message.put_u32(headers.len() as u32);
+        message.put_u32(crc32fast::hash(&message));
+        message.put_slice(&headers);
+        message.put_slice(payload);
+        message.put_u32(crc32fast::hash(&message));
+        message.freeze()
+    }
+
+    /// The response the function would stream to the runtime, echoing the message three times,
+    /// as its JSON prelude and the grpc-web frames
+    fn streamed_response(message: &[u8]) -> Vec<Bytes> {
+        let prelude = json!({
+            "statusCode": 200,
+            "headers": {"content-type": "application/grpc-web+proto"},
+        });
+        let mut prelude = prelude.to_string().into_bytes();
+        prelude.extend_from_slice(&PRELUDE_DELIMITER);
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+
+        vec![
+            Bytes::from(prelude),
+            encode_frame(0, message),
+            encode_frame(0, message),
+            encode_frame(0, message),
+            encode_trailers_frame(&trailers),
+        ]
+    }
+
+    /// The same response, as returned by a function served with `serve_buffered`
+    fn buffered_response(message: &[u8]) -> Bytes {
+        let body: Vec<u8> = streamed_response(message)[1..].concat();
+        let res = json!({
+            "statusCode": 200,
+            "headers": {"content-type": "application/grpc-web+proto"},
+            "body": STANDARD.encode(body),
+            "isBase64Encoded": true,
+        });
+        Bytes::from(res.to_string())
+    }
+
+    /// Stands in for the Lambda API, emulating the function named by the last segment of the
+    /// function ARN: `buffered` or `streaming` functions echoing the message, a `timeout` or a
+    /// `broken` stream failing after the first message
+    async fn lambda_api() -> Uri {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let service = hyper::service::service_fn(|req: Request<Incoming>| async move {
+                    let (parts, body) = req.into_parts();
+                    let body = body.collect().await.unwrap().to_bytes();
+
+                    let error = |status: StatusCode, error_type: &str| {
+                        let body = json!({"Message": "stand-in error"}).to_string();
+                        let mut res = Response::new(Full::new(Bytes::from(body)).boxed());
+                        *res.status_mut() = status;
+                        res.headers_mut().insert(
+                            "x-amzn-errortype",
+                            HeaderValue::from_str(error_type).unwrap(),
+                        );
+                        Ok::<_, Infallible>(res)
+                    };
+
+                    if !verify_signature(&parts, &body) {
+                        return error(StatusCode::FORBIDDEN, "AccessDeniedException");
+                    }
+
+                    let path = parts.uri.path().replace("%3A", ":");
+                    let segments: Vec<&str> = path.split('/').collect();
+                    let (stream_api, function) = match segments[..] {
+                        ["", "2015-03-31", "functions", name, "invocations"] => (false, name),
+                        [
+                            "",
+                            "2021-11-15",
+                            "functions",
+                            name,
+                            "response-streaming-invocations",
+                        ] => (true, name),
+                        _ => return error(StatusCode::NOT_FOUND, "UnknownOperationException"),
+                    };
+                    let function = function.rsplit(':').next().unwrap();
+
+                    let event: Value = serde_json::from_slice(&body).unwrap();
+                    assert_eq!(event["rawPath"], "/test.Echo/Stream");
+                    assert_eq!(event["requestContext"]["routeKey"], crate::INVOKE_ROUTE_KEY);
+                    assert_eq!(parts.uri.query(), Some("Qualifier=%24LATEST"));
+                    let request = STANDARD.decode(event["body"].as_str().unwrap()).unwrap();
+                    let message = &request[FRAME_HEADER_SIZE..];
+
+                    let payload: Vec<Bytes> = match function {
+                        "buffered" => vec![buffered_response(message)],
+                        "streaming" => streamed_response(message),
+                        "broken" => streamed_response(message)[..2].to_vec(),
+                        "timeout" => {
+                            let error = json!({
+                                "errorType": "Sandbox.Timedout",
+                                "errorMessage": "Task timed out after 3.00 seconds",
+                            });
+                            let mut res =
+                                Response::new(Full::new(Bytes::from(error.to_string())).boxed());
+                            res.headers_mut().insert(
+                                "x-amz-function-error",
+                                HeaderValue::from_static("Unhandled"),
+                            );
+                            return Ok(res);
+                        }
+                        _ => {
+                            return error(StatusCode::NOT_FOUND, "ResourceNotFoundException");
+                        }
+                    };
+
+                    if !stream_api {
+                        return Ok(Response::new(
+                            Full::new(Bytes::from(payload.concat())).boxed(),
+                        ));
+                    }
+
+                    let complete = match function {
+                        "broken" => json!({"ErrorCode": "Unhandled", "ErrorDetails": "boom"}),
+                        _ => json!({}),
+                    };
+                    let mut events: Vec<Bytes> = payload
+                        .iter()
+                        .map(|chunk| event_message("PayloadChunk", chunk))
+                        .collect();
+                    events.push(event_message(
+                        "InvokeComplete",
+                        complete.to_string().as_bytes(),
+                    ));
+
+                    // split every message across two chunks
+                    let chunks: Vec<Result<Frame<Bytes>, Infallible>> = events
+                        .into_iter()
+                        .flat_map(|event| {
+                            let (head, tail) = event.split_at(event.len() / 2);
+                            [
+                                Ok(Frame::data(Bytes::copy_from_slice(head))),
+                                Ok(Frame::data(Bytes::copy_from_slice(tail))),
+                            ]
+                        })
+                        .collect();
+
+                    Ok(Response::new(BodyExt::boxed(StreamBody::new(
+                        stream::iter(chunks),
+                    ))))
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+
+        endpoint
+    }
+
+    fn channel(endpoint: &Uri, function: &str, secret: &str) -> InvokeChannel {
+        let credentials = Credentials::new(ACCESS_KEY, secret, None, None, "test");
+        InvokeChannel::new(
+            format!("arn:aws:lambda:eu-west-1:123456789012:function:{function}"),
+            SigV4Config::new(credentials, REGION),
+        )
+        .qualifier("$LATEST")
+        .endpoint(endpoint.clone())
+    }
+
+    /// Calls the streaming echo method, returning the messages received before any error
+    async fn call(channel: InvokeChannel) -> (Vec<Bytes>, Option<Status>) {
+        let mut client = tonic::client::Grpc::new(channel);
+        client.ready().await.unwrap();
+
+        let res = client
+            .server_streaming(
+                tonic::Request::new(Bytes::from_static(b"hello")),
+                PathAndQuery::from_static("/test.Echo/Stream"),
+                RawCodec,
+            )
+            .await;
+        let mut stream = match res {
+            Ok(res) => res.into_inner(),
+            Err(status) => return (Vec::new(), Some(status)),
+        };
+
+        let mut messages = Vec::new();
+        while let Some(message) = stream.next().await {
+            match message {
+                Ok(message) => messages.push(message),
+                Err(status) => return (messages, Some(status)),
+            }
+        }
+        (messages, None)
+    }
+
+    #[tokio::test]
+    async fn decodes_buffered_and_streamed_responses() {
+        let endpoint = lambda_api().await;
+
+        for function in ["buffered", "streaming"] {
+            for channel in [
+                channel(&endpoint, function, SECRET_KEY),
+                channel(&endpoint, function, SECRET_KEY).response_stream(),
+            ] {
+                let (messages, status) = call(channel).await;
+
+                assert!(status.is_none(), "{function}: {status:?}");
+                assert_eq!(messages, vec![Bytes::from_static(b"hello"); 3]);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn maps_failures_to_status() {
+        let endpoint = lambda_api().await;
+
+        let (_, status) = call(channel(&endpoint, "timeout", SECRET_KEY)).await;
+        assert_eq!(status.unwrap().code(), Code::DeadlineExceeded);
+
+        let (_, status) = call(channel(&endpoint, "missing", SECRET_KEY)).await;
+        assert_eq!(status.unwrap().code(), Code::NotFound);
+
+        let (_, status) = call(channel(&endpoint, "streaming", "wrong")).await;
+        assert_eq!(status.unwrap().code(), Code::PermissionDenied);
+
+        let (messages, status) =
+            call(channel(&endpoint, "broken", SECRET_KEY).response_stream()).await;
+        assert_eq!(messages, vec![Bytes::from_static(b"hello")]);
+        assert_eq!(status.unwrap().code(), Code::Internal);
+    }
+
+    #[test]
+    fn rejects_corrupted_event_messages() {
+        let mut message = event_message("PayloadChunk", b"payload").to_vec();
+        let mut decoder = EventStreamDecoder::default();
+        decoder.push(Bytes::copy_from_slice(&message));
+        assert_eq!(
+            decoder.next_message().unwrap().unwrap().payload,
+            Bytes::from_static(b"payload")
+        );
+
+        let last = message.len() - 5;
+        message[last] ^= 1;
+        decoder.push(Bytes::from(message));
+        assert!(decoder.next_message().is_err());
+    }
+
+    #[test]
+    fn escapes_function_arns() {
+        let channel = channel(&"https://lambda.example.com/".parse().unwrap(), "f", "");
+
+        assert_eq!(
+            channel.transport.uri().unwrap(),
+            "https://lambda.example.com/2015-03-31/functions/arn%3Aaws%3Alambda%3Aeu-west-1%3A123456789012%3Afunction%3Af/invocations?Qualifier=%24LATEST"
+        );
+    }
+}
@@ -0,0 +1,816 @@
+//! [Connect protocol](https://connectrpc.com/docs/protocol) support, for clients such as
+//! Connect-ES. Connect requests are translated into binary grpc-web requests ahead of the grpc-web
+//! layer, and the grpc-web responses translated back, so the same routes serve both protocols.
+//!
+//! Unary calls are plain POSTs of a single `application/proto` or `application/json` message.
+//! Server streams use `application/connect+proto` or `application/connect+json`, with envelopes
+//! framed exactly as grpc-web frames, ending with an end-of-stream message carrying the status
+//! and trailers as JSON.
+
+use crate::grpc_web::{FrameDecoder, encode_frame};
+use crate::translate::{
+    GrpcWebEvent, GrpcWebMessages, UnaryResponse, grpc_web_request, http_status, json_to_message,
+    message_to_json, metadata, status_details, trailers_only,
+};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use bytes::Bytes;
+use http::header::{CONTENT_ENCODING, CONTENT_TYPE, HeaderName};
+use http::{HeaderMap, HeaderValue, Request, Response, StatusCode};
+use http_body::Frame;
+use http_body_util::{BodyExt, Full};
+use prost::Message;
+use prost_reflect::{DescriptorError, DescriptorPool, MessageDescriptor, MethodDescriptor};
+use serde_json::{Map, Value, json};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use tonic::body::Body;
+use tonic::{Code, Status};
+use tower::{BoxError, Layer, Service};
+
+const CONNECT_TIMEOUT_MS: &str = "connect-timeout-ms";
+const CONNECT_PROTOCOL_VERSION: &str = "connect-protocol-version";
+const CONNECT_CONTENT_ENCODING: &str = "connect-content-encoding";
+const CONNECT_ACCEPT_ENCODING: &str = "connect-accept-encoding";
+const STREAMING_CONTENT_TYPE_PREFIX: &str = "application/connect+";
+
+/// Flag set on envelopes with a compressed message
+const COMPRESSED_FLAG: u8 = 0x01;
+/// Flag set on the end-of-stream envelope, which must be the final envelope of a stream
+const END_STREAM_FLAG: u8 = 0x02;
+
+/// Connect protocol configuration, see [`LambdaServer::connect`](crate::LambdaServer::connect).
+///
+/// The protobuf codec needs no configuration. The JSON codec transcodes messages, so needs the
+/// descriptors of the services, as generated by `tonic_prost_build` with
+/// `file_descriptor_set_path`:
+///
+/// ```ignore
+/// ConnectConfig::new().json_descriptors(tonic::include_file_descriptor_set!("descriptors"))?
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ConnectConfig {
+    descriptors: Option<DescriptorPool>,
+}
+
+impl ConnectConfig {
+    /// A configuration accepting the protobuf codec only
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept the JSON codec for the services described by an encoded `FileDescriptorSet`
+    pub fn json_descriptors(mut self, file_descriptor_set: &[u8]) -> Result<Self, DescriptorError> {
+        self.descriptors = Some(DescriptorPool::decode(file_descriptor_set)?);
+        Ok(self)
+    }
+
+    pub(crate) fn layer(&self) -> ConnectLayer {
+        ConnectLayer {
+            descriptors: self.descriptors.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ConnectLayer {
+    descriptors: Option<DescriptorPool>,
+}
+
+impl<S> Layer<S> for ConnectLayer {
+    type Service = ConnectService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ConnectService {
+            inner,
+            descriptors: self.descriptors.clone(),
+        }
+    }
+}
+
+/// Translates Connect requests to grpc-web, passing any other request through untouched
+#[derive(Clone)]
+pub(crate) struct ConnectService<S> {
+    inner: S,
+    descriptors: Option<DescriptorPool>,
+}
+
+impl<S, ResBody> Service<Request<Body>> for ConnectService<S>
+where
+    S: Service<Request<Body>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+    ResBody: http_body::Body<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let call = match ConnectCall::detect(&req, self.descriptors.as_ref()) {
+            Some(Ok(call)) => call,
+            Some(Err(UnsupportedCodec)) => {
+                return Box::pin(async move { Ok(unsupported_media_type()) });
+            }
+            None => {
+                let fut = self.inner.call(req);
+                return Box::pin(async move { Ok(fut.await?.map(Body::new)) });
+            }
+        };
+
+        // the request body has to be collected before the inner service is called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let req = match call.to_grpc_web(req).await {
+                Ok(req) => req,
+                Err(status) => return Ok(call.error_response(&status)),
+            };
+
+            let res = inner.call(req).await?;
+            Ok(call.to_connect(res.map(Body::new)).await)
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Codec {
+    Proto,
+    Json,
+}
+
+impl Codec {
+    fn name(self) -> &'static str {
+        match self {
+            Codec::Proto => "proto",
+            Codec::Json => "json",
+        }
+    }
+}
+
+/// A Connect request for a codec that isn't supported
+struct UnsupportedCodec;
+
+/// A single Connect call, carrying what's needed to translate its request and response
+struct ConnectCall {
+    streaming: bool,
+    codec: Codec,
+    /// Request and response descriptors, for transcoding JSON messages
+    messages: Option<(MessageDescriptor, MessageDescriptor)>,
+}
+
+impl ConnectCall {
+    /// Recognises Connect requests by their content type. Requests for a codec that isn't
+    /// supported are answered with `415 Unsupported Media Type`, as the protocol requires.
+    fn detect(
+        req: &Request<Body>,
+        descriptors: Option<&DescriptorPool>,
+    ) -> Option<Result<Self, UnsupportedCodec>> {
+        let content_type = req.headers().get(CONTENT_TYPE)?.to_str().ok()?;
+        let content_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+
+        let (streaming, codec) = match content_type.as_str() {
+            "application/proto" => (false, Codec::Proto),
+            "application/json" => (false, Codec::Json),
+            "application/connect+proto" => (true, Codec::Proto),
+            "application/connect+json" => (true, Codec::Json),
+            other if other.starts_with(STREAMING_CONTENT_TYPE_PREFIX) => {
+                return Some(Err(UnsupportedCodec));
+            }
+            _ => return None,
+        };
+
+        let messages = match codec {
+            Codec::Proto => None,
+            Codec::Json => {
+                let Some(method) = descriptors.and_then(|pool| find_method(pool, req.uri().path()))
+                else {
+                    // JSON for a method we can't transcode may still be destined elsewhere
+                    return streaming.then_some(Err(UnsupportedCodec));
+                };
+                Some((method.input(), method.output()))
+            }
+        };
+
+        Some(Ok(Self {
+            streaming,
+            codec,
+            messages,
+        }))
+    }
+
+    /// Rewrites the request as a binary grpc-web request of the same method
+    async fn to_grpc_web(&self, req: Request<Body>) -> Result<Request<Body>, Status> {
+        let (mut parts, body) = req.into_parts();
+
+        let encoding = if self.streaming {
+            parts.headers.get(CONNECT_CONTENT_ENCODING)
+        } else {
+            parts.headers.get(CONTENT_ENCODING)
+        };
+        if encoding.is_some_and(|encoding| encoding != "identity") {
+            return Err(Status::unimplemented(
+                "Compressed requests are not supported",
+            ));
+        }
+
+        let body = body
+            .collect()
+            .await
+            .map_err(|err| Status::invalid_argument(format!("Failed to read request: {err}")))?
+            .to_bytes();
+
+        let messages = if self.streaming {
+            let mut decoder = FrameDecoder::default();
+            decoder.push(body);
+
+            let mut messages = Vec::new();
+            while let Some((flags, message)) = decoder.next_frame() {
+                if flags & COMPRESSED_FLAG != 0 {
+                    return Err(Status::unimplemented(
+                        "Compressed requests are not supported",
+                    ));
+                }
+                messages.push(self.decode_request(message)?);
+            }
+
+            if !decoder.is_empty() {
+                return Err(Status::invalid_argument("Incomplete envelope in request"));
+            }
+            messages
+        } else {
+            vec![self.decode_request(body)?]
+        };
+
+        if let Some(timeout) = parts.headers.remove(CONNECT_TIMEOUT_MS) {
+            let millis = timeout
+                .to_str()
+                .ok()
+                .filter(|millis| millis.len() <= 10)
+                .and_then(|millis| millis.parse::<u64>().ok())
+                .ok_or_else(|| Status::invalid_argument("Invalid connect-timeout-ms"))?;
+            // gRPC timeouts carry their unit, Connect timeouts are always milliseconds
+            parts.headers.insert(
+                HeaderName::from_static("grpc-timeout"),
+                HeaderValue::try_from(format!("{millis}m")).expect("valid header value"),
+            );
+        }
+
+        for name in [
+            CONNECT_PROTOCOL_VERSION,
+            CONNECT_CONTENT_ENCODING,
+            CONNECT_ACCEPT_ENCODING,
+        ] {
+            parts.headers.remove(name);
+        }
+
+        Ok(grpc_web_request(parts, messages))
+    }
+
+    /// Rewrites the grpc-web response in the Connect protocol
+    async fn to_connect(&self, res: Response<Body>) -> Response<Body> {
+        if self.streaming {
+            return self.streaming_response(res);
+        }
+
+        let unary = UnaryResponse::read(res).await;
+
+        let mut res = match unary
+            .result
+            .and_then(|message| self.encode_response(message))
+        {
+            Ok(body) => {
+                let mut res = Response::new(Body::new(Full::new(body)));
+                let content_type = format!("application/{}", self.codec.name());
+                res.headers_mut()
+                    .insert(CONTENT_TYPE, HeaderValue::try_from(content_type).unwrap());
+                res
+            }
+            Err(status) => unary_error(&status),
+        };
+
+        let headers = res.headers_mut();
+        headers.extend(unary.headers);
+        for (name, value) in unary.trailers.iter() {
+            if let Ok(name) = HeaderName::try_from(format!("trailer-{name}")) {
+                headers.append(name, value.clone());
+            }
+        }
+        res
+    }
+
+    fn streaming_response(&self, res: Response<Body>) -> Response<Body> {
+        let (parts, body) = res.into_parts();
+
+        let mut res = match trailers_only(&parts) {
+            Some(status) => {
+                let end = end_stream(&status, &metadata(&parts.headers));
+                Response::new(Body::new(Full::new(end)))
+            }
+            None => {
+                let body = ConnectStreamBody {
+                    messages: GrpcWebMessages::new(body),
+                    output: self.messages.as_ref().map(|(_, output)| output.clone()),
+                    done: false,
+                };
+                let mut res = Response::new(Body::new(body));
+                *res.headers_mut() = metadata(&parts.headers);
+                res
+            }
+        };
+
+        res.headers_mut()
+            .insert(CONTENT_TYPE, self.streaming_content_type());
+        res
+    }
+
+    /// Response to a call that failed before reaching the routes
+    fn error_response(&self, status: &Status) -> Response<Body> {
+        if self.streaming {
+            let mut res =
+                Response::new(Body::new(Full::new(end_stream(status, &HeaderMap::new()))));
+            res.headers_mut()
+                .insert(CONTENT_TYPE, self.streaming_content_type());
+            res
+        } else {
+            unary_error(status)
+        }
+    }
+
+    fn streaming_content_type(&self) -> HeaderValue {
+        HeaderValue::try_from(format!(
+            "{STREAMING_CONTENT_TYPE_PREFIX}{}",
+            self.codec.name()
+        ))
+        .unwrap()
+    }
+
+    fn decode_request(&self, message: Bytes) -> Result<Bytes, Status> {
+        match &self.messages {
+            Some((input, _)) => Ok(json_to_message(input, &message)?.encode_to_vec().into()),
+            None => Ok(message),
+        }
+    }
+
+    fn encode_response(&self, message: Bytes) -> Result<Bytes, Status> {
+        match &self.messages {
+            Some((_, output)) => encode_json(output, message),
+            None => Ok(message),
+        }
+    }
+}
+
+/// Server stream body, re-enveloping each grpc-web message and replacing the trailers frame with
+/// the end-of-stream envelope
+struct ConnectStreamBody {
+    messages: GrpcWebMessages,
+    /// Response descriptor, when transcoding to JSON
+    output: Option<MessageDescriptor>,
+    done: bool,
+}
+
+impl http_body::Body for ConnectStreamBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        let envelope = match ready!(this.messages.poll_next(cx)) {
+            Some(GrpcWebEvent::Message(message)) => {
+                let message = match &this.output {
+                    Some(output) => encode_json(output, message),
+                    None => Ok(message),
+                };
+
+                match message {
+                    Ok(message) => encode_frame(0, &message),
+                    Err(status) => {
+                        this.done = true;
+                        end_stream(&status, &HeaderMap::new())
+                    }
+                }
+            }
+            Some(GrpcWebEvent::End(status, trailers)) => {
+                this.done = true;
+                end_stream(&status, &trailers)
+            }
+            None => {
+                this.done = true;
+                return Poll::Ready(None);
+            }
+        };
+
+        Poll::Ready(Some(Ok(Frame::data(envelope))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done
+    }
+}
+
+fn find_method(pool: &DescriptorPool, path: &str) -> Option<MethodDescriptor> {
+    let (service, method) = path.strip_prefix('/')?.split_once('/')?;
+    pool.get_service_by_name(service)?
+        .methods()
+        .find(|m| m.name() == method)
+}
+
+fn encode_json(descriptor: &MessageDescriptor, message: Bytes) -> Result<Bytes, Status> {
+    let json = message_to_json(descriptor, message)?;
+    Ok(serde_json::to_vec(&json)
+        .expect("JSON values serialize")
+        .into())
+}
+
+fn unsupported_media_type() -> Response<Body> {
+    let mut res = Response::new(Body::empty());
+    *res.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+    res
+}
+
+fn unary_error(status: &Status) -> Response<Body> {
+    let body = serde_json::to_vec(&error_json(status)).expect("JSON values serialize");
+
+    let mut res = Response::new(Body::new(Full::new(Bytes::from(body))));
+    *res.status_mut() = http_status(status.code());
+    res.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    res
+}
+
+/// End-of-stream message, carrying the error, if any, and the trailers
+fn end_stream(status: &Status, trailers: &HeaderMap) -> Bytes {
+    let mut end = Map::new();
+
+    if status.code() != Code::Ok {
+        end.insert("error".to_string(), error_json(status));
+    }
+
+    let mut values = Map::new();
+    for (name, value) in trailers.iter() {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+        let entry = values
+            .entry(name.as_str())
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if let Value::Array(entry) = entry {
+            entry.push(value.into());
+        }
+    }
+    if !values.is_empty() {
+        end.insert("metadata".to_string(), Value::Object(values));
+    }
+
+    let end = serde_json::to_vec(&Value::Object(end)).expect("JSON values serialize");
+    encode_frame(END_STREAM_FLAG, &end)
+}
+
+fn error_json(status: &Status) -> Value {
+    let mut error = json!({ "code": code_name(status.code()) });
+
+    if !status.message().is_empty() {
+        error["message"] = status.message().into();
+    }
+
+    let details = status_details(status);
+    if !details.is_empty() {
+        error["details"] = details
+            .iter()
+            .map(|any| {
+                json!({
+                    "type": any.type_url.rsplit('/').next().unwrap_or_default(),
+                    "value": STANDARD_NO_PAD.encode(&any.value),
+                })
+            })
+            .collect();
+    }
+
+    error
+}
+
+fn code_name(code: Code) -> &'static str {
+    match code {
+        Code::Ok => "ok",
+        Code::Cancelled => "canceled",
+        Code::Unknown => "unknown",
+        Code::InvalidArgument => "invalid_argument",
+        Code::DeadlineExceeded => "deadline_exceeded",
+        Code::NotFound => "not_found",
+        Code::AlreadyExists => "already_exists",
+        Code::PermissionDenied => "permission_denied",
+        Code::ResourceExhausted => "resource_exhausted",
+        Code::FailedPrecondition => "failed_precondition",
+        Code::Aborted => "aborted",
+        Code::OutOfRange => "out_of_range",
+        Code::Unimplemented => "unimplemented",
+        Code::Internal => "internal",
+        Code::Unavailable => "unavailable",
+        Code::DataLoss => "data_loss",
+        Code::Unauthenticated => "unauthenticated",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grpc_web::encode_trailers_frame;
+    use crate::translate::{GRPC_WEB_PROTO, ProtoAny, RpcStatus};
+    use prost_reflect::prost_types::field_descriptor_proto::{Label, Type};
+    use prost_reflect::prost_types::{
+        DescriptorProto, FieldDescriptorProto, FileDescriptorProto, FileDescriptorSet,
+        MethodDescriptorProto, ServiceDescriptorProto,
+    };
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    const ECHO_PATH: &str = "/test.EchoService/Say";
+
+    /// Descriptors of `test.EchoService`, echoing `test.Echo { string text = 1; }`
+    fn descriptors() -> Vec<u8> {
+        let echo = DescriptorProto {
+            name: Some("Echo".to_string()),
+            field: vec![FieldDescriptorProto {
+                name: Some("text".to_string()),
+                json_name: Some("text".to_string()),
+                number: Some(1),
+                label: Some(Label::Optional as i32),
+                r#type: Some(Type::String as i32),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let service = ServiceDescriptorProto {
+            name: Some("EchoService".to_string()),
+            method: vec![MethodDescriptorProto {
+                name: Some("Say".to_string()),
+                input_type: Some(".test.Echo".to_string()),
+                output_type: Some(".test.Echo".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        FileDescriptorSet {
+            file: vec![FileDescriptorProto {
+                name: Some("test.proto".to_string()),
+                package: Some("test".to_string()),
+                syntax: Some("proto3".to_string()),
+                message_type: vec![echo],
+                service: vec![service],
+                ..Default::default()
+            }],
+        }
+        .encode_to_vec()
+    }
+
+    fn grpc_web_response(body: Vec<u8>, trailers: &HeaderMap) -> Response<Body> {
+        let mut body = body;
+        body.extend_from_slice(&encode_trailers_frame(trailers));
+
+        let mut res = Response::new(Body::new(Full::new(Bytes::from(body))));
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(GRPC_WEB_PROTO));
+        res
+    }
+
+    fn trailers(status: &str) -> HeaderMap {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::try_from(status).unwrap());
+        trailers.insert("x-trailer", HeaderValue::from_static("t"));
+        trailers
+    }
+
+    /// Responds with the grpc-web request frames, reporting the translated request headers as
+    /// response metadata
+    async fn echo(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+        let headers = req.headers().clone();
+        let body = req.into_body().collect().await.unwrap().to_bytes();
+
+        let mut res = grpc_web_response(body.to_vec(), &trailers("0"));
+        for (from, to) in [
+            (CONTENT_TYPE.as_str(), "x-content-type"),
+            ("grpc-timeout", "x-grpc-timeout"),
+        ] {
+            if let Some(value) = headers.get(from) {
+                res.headers_mut().insert(to, value.clone());
+            }
+        }
+        Ok(res)
+    }
+
+    async fn call<F, Fut>(config: ConnectConfig, inner: F, req: Request<Body>) -> Response<Body>
+    where
+        F: FnMut(Request<Body>) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = Result<Response<Body>, Infallible>> + Send + 'static,
+    {
+        config
+            .layer()
+            .layer(tower::service_fn(inner))
+            .oneshot(req)
+            .await
+            .unwrap()
+    }
+
+    fn request(content_type: &str, body: impl Into<Bytes>) -> Request<Body> {
+        Request::post(ECHO_PATH)
+            .header(CONTENT_TYPE, content_type)
+            .header(CONNECT_PROTOCOL_VERSION, "1")
+            .body(Body::new(Full::new(body.into())))
+            .unwrap()
+    }
+
+    async fn body(res: Response<Body>) -> Bytes {
+        res.into_body().collect().await.unwrap().to_bytes()
+    }
+
+    #[tokio::test]
+    async fn unary_proto_roundtrip() {
+        let mut req = request("application/proto", "hello");
+        req.headers_mut()
+            .insert(CONNECT_TIMEOUT_MS, HeaderValue::from_static("1500"));
+
+        let res = call(ConnectConfig::new(), echo, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/proto");
+        assert_eq!(res.headers()["x-content-type"], GRPC_WEB_PROTO);
+        assert_eq!(res.headers()["x-grpc-timeout"], "1500m");
+        assert_eq!(res.headers()["trailer-x-trailer"], "t");
+        assert!(!res.headers().contains_key("grpc-status"));
+        assert_eq!(body(res).await, "hello");
+    }
+
+    #[tokio::test]
+    async fn unary_json_is_transcoded() {
+        let config = ConnectConfig::new()
+            .json_descriptors(&descriptors())
+            .unwrap();
+
+        let res = call(
+            config,
+            echo,
+            request("application/json", r#"{"text":"hi"}"#),
+        )
+        .await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(body(res).await, r#"{"text":"hi"}"#);
+    }
+
+    #[tokio::test]
+    async fn unary_error_as_json_with_details() {
+        let details = RpcStatus {
+            code: Code::NotFound as i32,
+            message: "missing".to_string(),
+            details: vec![ProtoAny {
+                type_url: "type.googleapis.com/google.rpc.ErrorInfo".to_string(),
+                value: vec![1, 2, 3],
+            }],
+        };
+
+        let inner = move |_req: Request<Body>| {
+            let status =
+                Status::with_details(Code::NotFound, "missing", details.encode_to_vec().into());
+            async move { Ok(status.into_http()) }
+        };
+
+        let res = call(
+            ConnectConfig::new(),
+            inner,
+            request("application/proto", ""),
+        )
+        .await;
+
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/json");
+        assert!(!res.headers().contains_key("grpc-status"));
+
+        let error: Value = serde_json::from_slice(&body(res).await).unwrap();
+        assert_eq!(
+            error,
+            json!({
+                "code": "not_found",
+                "message": "missing",
+                "details": [{ "type": "google.rpc.ErrorInfo", "value": "AQID" }],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_ends_with_end_stream_envelope() {
+        let inner = |_req: Request<Body>| async {
+            let mut frames = encode_frame(0, b"a").to_vec();
+            frames.extend_from_slice(&encode_frame(0, b"b"));
+
+            let mut trailers = trailers("10");
+            trailers.insert("grpc-message", HeaderValue::from_static("stopped"));
+            Ok(grpc_web_response(frames, &trailers))
+        };
+
+        let req = request("application/connect+proto", encode_frame(0, b"req"));
+        let res = call(ConnectConfig::new(), inner, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/connect+proto");
+
+        let mut decoder = FrameDecoder::default();
+        decoder.push(body(res).await);
+
+        assert_eq!(decoder.next_frame(), Some((0, Bytes::from_static(b"a"))));
+        assert_eq!(decoder.next_frame(), Some((0, Bytes::from_static(b"b"))));
+
+        let (flags, end) = decoder.next_frame().unwrap();
+        assert_eq!(flags, END_STREAM_FLAG);
+        assert_eq!(
+            serde_json::from_slice::<Value>(&end).unwrap(),
+            json!({
+                "error": { "code": "aborted", "message": "stopped" },
+                "metadata": { "x-trailer": ["t"] },
+            })
+        );
+        assert!(decoder.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stream_body_ends_with_the_end_stream_envelope() {
+        let mut frames = encode_frame(0, b"a").to_vec();
+        frames.extend_from_slice(&encode_trailers_frame(&trailers("0")));
+        let mut body = ConnectStreamBody {
+            messages: GrpcWebMessages::new(Body::new(Full::new(Bytes::from(frames)))),
+            output: None,
+            done: false,
+        };
+
+        assert!(body.frame().await.unwrap().unwrap().is_data());
+        assert!(!http_body::Body::is_end_stream(&body));
+
+        assert!(body.frame().await.unwrap().unwrap().is_data());
+        assert!(http_body::Body::is_end_stream(&body));
+        assert!(body.frame().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn trailers_only_stream_responds_with_end_stream() {
+        let inner = |_req: Request<Body>| async { Ok(Status::permission_denied("no").into_http()) };
+
+        let req = request("application/connect+proto", encode_frame(0, b"req"));
+        let res = call(ConnectConfig::new(), inner, req).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut decoder = FrameDecoder::default();
+        decoder.push(body(res).await);
+
+        let (flags, end) = decoder.next_frame().unwrap();
+        assert_eq!(flags, END_STREAM_FLAG);
+        assert_eq!(
+            serde_json::from_slice::<Value>(&end).unwrap(),
+            json!({ "error": { "code": "permission_denied", "message": "no" } })
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_unsupported_codec() {
+        let res = call(
+            ConnectConfig::new(),
+            echo,
+            request("application/connect+cbor", ""),
+        )
+        .await;
+
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn passes_grpc_web_through() {
+        let req = request(GRPC_WEB_PROTO, encode_frame(0, b"hello"));
+
+        let res = call(ConnectConfig::new(), echo, req).await;
+
+        assert_eq!(res.headers()[CONTENT_TYPE], GRPC_WEB_PROTO);
+        assert!(body(res).await.starts_with(&encode_frame(0, b"hello")));
+    }
+}
@@ -0,0 +1,488 @@
+//! CORS for browser grpc-web clients. Preflights are answered before requests reach the grpc-web
+//! layer, which would otherwise reject them as non grpc-web requests.
+
+use http::header::{self, HeaderName};
+use http::{HeaderValue, Method};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// Request headers grpc-web clients send, always allowed
+const GRPC_WEB_REQUEST_HEADERS: [HeaderName; 4] = [
+    header::CONTENT_TYPE,
+    HeaderName::from_static("x-grpc-web"),
+    HeaderName::from_static("x-user-agent"),
+    HeaderName::from_static("grpc-timeout"),
+];
+
+/// Request headers Connect clients send in addition, always allowed
+#[cfg(feature = "connect")]
+const CONNECT_REQUEST_HEADERS: [HeaderName; 2] = [
+    HeaderName::from_static("connect-protocol-version"),
+    HeaderName::from_static("connect-timeout-ms"),
+];
+#[cfg(not(feature = "connect"))]
+const CONNECT_REQUEST_HEADERS: [HeaderName; 0] = [];
+
+/// Response headers grpc-web clients must read, always exposed. Trailers-only responses carry the
+/// status in these headers rather than the body.
+const GRPC_WEB_RESPONSE_HEADERS: [HeaderName; 3] = [
+    HeaderName::from_static("grpc-status"),
+    HeaderName::from_static("grpc-message"),
+    HeaderName::from_static("grpc-status-details-bin"),
+];
+
+/// Methods clients use, POST for grpc-web and Connect, and the methods of transcoded HTTP rules
+#[cfg(feature = "transcoding")]
+const ALLOWED_METHODS: [Method; 5] = [
+    Method::GET,
+    Method::POST,
+    Method::PUT,
+    Method::PATCH,
+    Method::DELETE,
+];
+#[cfg(not(feature = "transcoding"))]
+const ALLOWED_METHODS: [Method; 1] = [Method::POST];
+
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// CORS configuration for browser clients, see [`LambdaServer::cors`](crate::LambdaServer::cors).
+///
+/// The headers grpc-web needs are always allowed and exposed, only custom metadata has to be
+/// listed:
+///
+/// ```ignore
+/// CorsConfig::new()
+///     .allow_origins(["https://app.example.com"])
+///     .allow_origin_pattern("https://*.preview.example.com")
+///     .allow_credentials(true)
+///     .allow_metadata(["authorization"])
+///     .expose_metadata(["x-request-id"])
+/// ```
+#[derive(Clone, Debug)]
+pub struct CorsConfig {
+    any_origin: bool,
+    origins: Vec<HeaderValue>,
+    patterns: Vec<OriginPattern>,
+    credentials: bool,
+    allow_metadata: Vec<HeaderName>,
+    expose_metadata: Vec<HeaderName>,
+    max_age: Duration,
+}
+
+impl CorsConfig {
+    /// A configuration allowing no origins, to be extended with the `allow_*` methods
+    pub fn new() -> Self {
+        Self {
+            any_origin: false,
+            origins: Vec::new(),
+            patterns: Vec::new(),
+            credentials: false,
+            allow_metadata: Vec::new(),
+            expose_metadata: Vec::new(),
+            max_age: DEFAULT_MAX_AGE,
+        }
+    }
+
+    /// Allow requests from any origin. Can't be combined with credentials.
+    pub fn allow_any_origin(mut self) -> Self {
+        self.any_origin = true;
+        self
+    }
+
+    /// Allow requests from these exact origins, i.e. `https://app.example.com`
+    ///
+    /// Panics if an origin is not a valid header value.
+    pub fn allow_origins<I>(mut self, origins: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.origins.extend(origins.into_iter().map(|origin| {
+            HeaderValue::from_str(origin.as_ref())
+                .expect("CORS origin must be a valid header value")
+        }));
+        self
+    }
+
+    /// Allow requests from origins matching a pattern with a single `*` wildcard, i.e.
+    /// `https://*.example.com`. The wildcard matches at least one character, not including `/`.
+    ///
+    /// Panics if the pattern does not contain exactly one `*`.
+    pub fn allow_origin_pattern(mut self, pattern: &str) -> Self {
+        self.patterns.push(OriginPattern::new(pattern));
+        self
+    }
+
+    /// Send `Access-Control-Allow-Credentials: true`, allowing cookies and HTTP auth. Can't be
+    /// combined with [`CorsConfig::allow_any_origin`].
+    pub fn allow_credentials(mut self, allow: bool) -> Self {
+        self.credentials = allow;
+        self
+    }
+
+    /// Custom request metadata keys to allow in addition to the grpc-web headers
+    ///
+    /// Panics if a key is not a valid header name.
+    pub fn allow_metadata<I>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.allow_metadata
+            .extend(keys.into_iter().map(header_name));
+        self
+    }
+
+    /// Custom response metadata keys to expose in addition to the grpc-web status headers
+    ///
+    /// Panics if a key is not a valid header name.
+    pub fn expose_metadata<I>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.expose_metadata
+            .extend(keys.into_iter().map(header_name));
+        self
+    }
+
+    /// How long browsers may cache a preflight response. Defaults to 24 hours.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Checks this configuration against the CORS settings of the Function URL serving the
+    /// lambda. When CORS is enabled on the Function URL, it adds the CORS headers itself, so
+    /// responses would carry both sets, which browsers reject. The conflict lists every header
+    /// both would send, those of preflight responses included.
+    pub fn check_function_url(&self, function_url: &FunctionUrlCors) -> Result<(), CorsConflict> {
+        if function_url.allow_origins.is_empty() {
+            return Ok(());
+        }
+
+        let mut headers = vec![header::ACCESS_CONTROL_ALLOW_ORIGIN];
+        if function_url.allow_credentials && self.credentials {
+            headers.push(header::ACCESS_CONTROL_ALLOW_CREDENTIALS);
+        }
+        // the layer answers every preflight with the methods, headers and max age
+        if !function_url.allow_methods.is_empty() {
+            headers.push(header::ACCESS_CONTROL_ALLOW_METHODS);
+        }
+        if !function_url.allow_headers.is_empty() {
+            headers.push(header::ACCESS_CONTROL_ALLOW_HEADERS);
+        }
+        if !function_url.expose_headers.is_empty() {
+            headers.push(header::ACCESS_CONTROL_EXPOSE_HEADERS);
+        }
+        if function_url.max_age.is_some() {
+            headers.push(header::ACCESS_CONTROL_MAX_AGE);
+        }
+
+        Err(CorsConflict { headers })
+    }
+
+    /// Panics on configurations browsers would reject
+    pub(crate) fn validate(&self) {
+        assert!(
+            !(self.any_origin && self.credentials),
+            "Invalid CORS configuration: credentials can't be allowed for any origin"
+        );
+    }
+
+    pub(crate) fn layer(&self) -> CorsLayer {
+        let allow_origin = if self.any_origin {
+            AllowOrigin::any()
+        } else {
+            let origins = self.origins.clone();
+            let patterns = Arc::new(self.patterns.clone());
+            AllowOrigin::predicate(move |origin, _| {
+                origins.contains(origin)
+                    || origin
+                        .to_str()
+                        .is_ok_and(|origin| patterns.iter().any(|p| p.matches(origin)))
+            })
+        };
+
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods(ALLOWED_METHODS)
+            .allow_headers(
+                GRPC_WEB_REQUEST_HEADERS
+                    .into_iter()
+                    .chain(CONNECT_REQUEST_HEADERS)
+                    .chain(self.allow_metadata.iter().cloned())
+                    .collect::<Vec<_>>(),
+            )
+            .expose_headers(
+                GRPC_WEB_RESPONSE_HEADERS
+                    .into_iter()
+                    .chain(self.expose_metadata.iter().cloned())
+                    .collect::<Vec<_>>(),
+            )
+            .allow_credentials(self.credentials)
+            .max_age(self.max_age)
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn header_name(key: impl AsRef<str>) -> HeaderName {
+    HeaderName::try_from(key.as_ref()).expect("metadata key must be a valid header name")
+}
+
+#[derive(Clone, Debug)]
+struct OriginPattern {
+    prefix: String,
+    suffix: String,
+}
+
+impl OriginPattern {
+    fn new(pattern: &str) -> Self {
+        let (prefix, suffix) = pattern
+            .split_once('*')
+            .filter(|(_, suffix)| !suffix.contains('*'))
+            .expect("CORS origin pattern must contain exactly one `*`");
+
+        Self {
+            prefix: prefix.to_string(),
+            suffix: suffix.to_string(),
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        origin
+            .strip_prefix(&self.prefix)
+            .and_then(|rest| rest.strip_suffix(&self.suffix))
+            .is_some_and(|wildcard| !wildcard.is_empty() && !wildcard.contains('/'))
+    }
+}
+
+/// CORS settings of a Function URL, as configured on `AWS::Lambda::Url` or returned by
+/// `aws lambda get-function-url-config`. CORS is disabled when no origins are allowed.
+#[derive(Clone, Debug, Default)]
+pub struct FunctionUrlCors {
+    pub allow_origins: Vec<String>,
+    pub allow_methods: Vec<String>,
+    pub allow_headers: Vec<String>,
+    pub expose_headers: Vec<String>,
+    pub allow_credentials: bool,
+    pub max_age: Option<Duration>,
+}
+
+/// Both the Function URL and [`CorsConfig`] would send CORS headers
+#[derive(Debug)]
+pub struct CorsConflict {
+    headers: Vec<HeaderName>,
+}
+
+impl CorsConflict {
+    /// Headers both would send
+    pub fn headers(&self) -> &[HeaderName] {
+        &self.headers
+    }
+}
+
+impl fmt::Display for CorsConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let headers: Vec<&str> = self.headers.iter().map(HeaderName::as_str).collect();
+        write!(
+            f,
+            "CORS is configured on both the Function URL and the server, both would send {}. \
+             Disable CORS on the Function URL, or remove `.cors(..)`",
+            headers.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for CorsConflict {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::{Request, Response, StatusCode};
+    use std::convert::Infallible;
+    use tonic::body::Body;
+    use tower::{Layer, ServiceExt};
+
+    async fn call(config: CorsConfig, req: Request<Body>) -> Response<Body> {
+        let inner = tower::service_fn(|_req: Request<Body>| async {
+            Ok::<_, Infallible>(Response::new(Body::empty()))
+        });
+
+        config.layer().layer(inner).oneshot(req).await.unwrap()
+    }
+
+    fn preflight(origin: &str) -> Request<Body> {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/test.Service/Method")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "content-type,x-grpc-web,x-user-agent,grpc-timeout",
+            )
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn allowed_origin(res: &Response<Body>) -> Option<&str> {
+        res.headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .map(|v| v.to_str().unwrap())
+    }
+
+    #[tokio::test]
+    async fn answers_preflight_with_grpc_web_headers() {
+        let config = CorsConfig::new()
+            .allow_origins(["https://app.example.com"])
+            .allow_metadata(["authorization"]);
+
+        let res = call(config, preflight("https://app.example.com")).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(allowed_origin(&res), Some("https://app.example.com"));
+
+        let allowed_headers = res
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_HEADERS)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        for name in [
+            "x-grpc-web",
+            "x-user-agent",
+            "grpc-timeout",
+            "authorization",
+        ] {
+            assert!(allowed_headers.contains(name), "{name} not allowed");
+        }
+    }
+
+    #[tokio::test]
+    async fn exposes_grpc_web_response_headers() {
+        let config = CorsConfig::new()
+            .allow_origins(["https://app.example.com"])
+            .expose_metadata(["x-request-id"]);
+
+        let req = Request::post("/test.Service/Method")
+            .header(header::ORIGIN, "https://app.example.com")
+            .body(Body::empty())
+            .unwrap();
+
+        let res = call(config, req).await;
+
+        let exposed = res
+            .headers()
+            .get(header::ACCESS_CONTROL_EXPOSE_HEADERS)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        for name in [
+            "grpc-status",
+            "grpc-message",
+            "grpc-status-details-bin",
+            "x-request-id",
+        ] {
+            assert!(exposed.contains(name), "{name} not exposed");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_unlisted_origin() {
+        let config = CorsConfig::new().allow_origins(["https://app.example.com"]);
+
+        let res = call(config, preflight("https://evil.example.net")).await;
+
+        assert_eq!(allowed_origin(&res), None);
+    }
+
+    #[tokio::test]
+    async fn matches_origin_patterns() {
+        let config = CorsConfig::new()
+            .allow_origin_pattern("https://*.example.com")
+            .allow_credentials(true);
+
+        let res = call(config.clone(), preflight("https://pr-12.example.com")).await;
+        assert_eq!(allowed_origin(&res), Some("https://pr-12.example.com"));
+        assert_eq!(
+            res.headers()
+                .get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+                .unwrap(),
+            "true"
+        );
+
+        for origin in [
+            "https://example.com",
+            "https://.example.com",
+            "http://a.example.com",
+        ] {
+            let res = call(config.clone(), preflight(origin)).await;
+            assert_eq!(allowed_origin(&res), None, "{origin} should not match");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "credentials can't be allowed for any origin")]
+    fn rejects_credentials_for_any_origin() {
+        CorsConfig::new()
+            .allow_any_origin()
+            .allow_credentials(true)
+            .validate();
+    }
+
+    #[test]
+    fn conflicts_with_function_url_cors() {
+        let config = CorsConfig::new().allow_origins(["https://app.example.com"]);
+
+        assert!(
+            config
+                .check_function_url(&FunctionUrlCors::default())
+                .is_ok()
+        );
+
+        let conflict = config
+            .check_function_url(&FunctionUrlCors {
+                allow_origins: vec!["*".to_string()],
+                expose_headers: vec!["grpc-status".to_string()],
+                ..Default::default()
+            })
+            .unwrap_err();
+
+        assert_eq!(
+            conflict.headers(),
+            [
+                header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                header::ACCESS_CONTROL_EXPOSE_HEADERS
+            ]
+        );
+
+        // the Function URL answers preflights itself too
+        let conflict = config
+            .check_function_url(&FunctionUrlCors {
+                allow_origins: vec!["https://app.example.com".to_string()],
+                allow_methods: vec!["POST".to_string()],
+                allow_headers: vec!["content-type".to_string()],
+                max_age: Some(Duration::from_secs(300)),
+                ..Default::default()
+            })
+            .unwrap_err();
+
+        assert_eq!(
+            conflict.headers(),
+            [
+                header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                header::ACCESS_CONTROL_ALLOW_METHODS,
+                header::ACCESS_CONTROL_ALLOW_HEADERS,
+                header::ACCESS_CONTROL_MAX_AGE,
+            ]
+        );
+    }
+}
@@ -1,24 +1,241 @@
-use lambda_http::http::{Request, Response};
+use bytes::Bytes;
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use lambda_http::http::{HeaderMap, Request, Response};
 use lambda_http::tracing::log::{error, info, warn};
 use lambda_runtime::Context as LambdaContext;
 use std::{
+    fmt,
     future::Future,
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
     time::{Duration, SystemTime},
 };
-use tokio::time::{Instant, sleep_until};
+use tokio::time::{Instant, Sleep, sleep_until};
+use tokio_util::sync::CancellationToken;
 use tonic::Status;
 use tower::{Layer, Service};
 
+type OnExceeded = Arc<dyn Fn(&DeadlineExceeded) -> Status + Send + Sync>;
+
+/// Controls how far ahead of the lambda invocation deadline an in-flight request is terminated
+/// with `DEADLINE_EXCEEDED`, giving the function enough time to flush the response before the
+/// lambda runtime kills the invocation.
+///
+/// The default is a fixed margin of 500ms.
+#[derive(Clone)]
+pub struct DeadlinePolicy {
+    margin: DeadlineMargin,
+    on_exceeded: Option<OnExceeded>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DeadlineMargin {
+    Fixed(Duration),
+    Percentage(f64),
+    Disabled,
+}
+
+impl DeadlinePolicy {
+    /// Terminate requests a fixed duration before the lambda deadline.
+    pub fn fixed(margin: Duration) -> Self {
+        Self {
+            margin: DeadlineMargin::Fixed(margin),
+            on_exceeded: None,
+        }
+    }
+
+    /// Terminate requests when only `percent` of the time remaining at the start of the request
+    /// is left, e.g. `percentage(10.0)` on a request received with 3s remaining terminates it
+    /// 300ms before the lambda deadline.
+    ///
+    /// # Panics
+    /// If `percent` is not within `0.0..=100.0`
+    pub fn percentage(percent: f64) -> Self {
+        assert!(
+            (0.0..=100.0).contains(&percent),
+            "deadline percentage must be within 0..=100, got {percent}"
+        );
+
+        Self {
+            margin: DeadlineMargin::Percentage(percent),
+            on_exceeded: None,
+        }
+    }
+
+    /// Never terminate requests early, leaving the lambda runtime to kill the invocation when
+    /// the timeout is reached. A `grpc-timeout` sent by the client is still honoured.
+    pub fn disabled() -> Self {
+        Self {
+            margin: DeadlineMargin::Disabled,
+            on_exceeded: None,
+        }
+    }
+
+    /// Shape the `Status` returned to the client when the deadline fires. The default is
+    /// `Status::deadline_exceeded` with a message naming the [`DeadlineSource`] that fired.
+    pub fn on_exceeded<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&DeadlineExceeded) -> Status + Send + Sync + 'static,
+    {
+        self.on_exceeded = Some(Arc::new(f));
+        self
+    }
+
+    pub(crate) fn is_disabled(&self) -> bool {
+        self.margin == DeadlineMargin::Disabled
+    }
+
+    /// Resolves the point in time at which a request should be terminated, given the lambda
+    /// deadline and the current time.
+    fn effective_deadline(&self, deadline: SystemTime, now: SystemTime) -> Option<SystemTime> {
+        match self.margin {
+            DeadlineMargin::Fixed(margin) => deadline.checked_sub(margin),
+            DeadlineMargin::Percentage(percent) => {
+                let remaining = deadline.duration_since(now).unwrap_or_default();
+                deadline.checked_sub(remaining.mul_f64(percent / 100.0))
+            }
+            DeadlineMargin::Disabled => None,
+        }
+    }
+
+    fn exceeded_status(&self, exceeded: &DeadlineExceeded) -> Status {
+        match &self.on_exceeded {
+            Some(f) => f(exceeded),
+            None => match exceeded.source {
+                DeadlineSource::Lambda => Status::deadline_exceeded("Lambda deadline exceeded"),
+                DeadlineSource::Client => {
+                    Status::deadline_exceeded("Client deadline (grpc-timeout) exceeded")
+                }
+            },
+        }
+    }
+}
+
+impl Default for DeadlinePolicy {
+    fn default() -> Self {
+        Self::fixed(Duration::from_millis(500))
+    }
+}
+
+impl fmt::Debug for DeadlinePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeadlinePolicy")
+            .field("margin", &self.margin)
+            .field("on_exceeded", &self.on_exceeded.is_some())
+            .finish()
+    }
+}
+
+/// Details of a request terminated by the deadline layer, passed to
+/// [`DeadlinePolicy::on_exceeded`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct DeadlineExceeded {
+    /// Request path, i.e. `/package.Service/Method`
+    pub path: String,
+    /// Which of the deadlines fired
+    pub source: DeadlineSource,
+    /// The lambda invocation deadline, if the lambda context was available
+    pub lambda_deadline: Option<SystemTime>,
+    /// The point in time the request was terminated at
+    pub deadline: SystemTime,
+}
+
+/// The deadline that caused a request to be terminated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadlineSource {
+    /// The lambda invocation deadline, less the [`DeadlinePolicy`] margin
+    Lambda,
+    /// The deadline requested by the client with the `grpc-timeout` header
+    Client,
+}
+
+const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
+/// Parses a `grpc-timeout` header value as defined by the
+/// [gRPC over HTTP2 spec](https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md#requests),
+/// i.e. at most 8 ascii digits followed by a single unit character.
+fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    if value.len() < 2 {
+        return None;
+    }
+
+    let (digits, unit) = value.split_at(value.len() - 1);
+
+    if digits.len() > 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let amount: u64 = digits.parse().ok()?;
+
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 60 * 60)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+/// The deadline of the current request, inserted into the request extensions by the deadline
+/// layer so handlers can stop long running work early rather than being cut off. Retrieve it
+/// with `request.extensions().get::<LambdaDeadline>()`.
+///
+/// When the [`DeadlinePolicy`] is disabled and the client sent no `grpc-timeout`, this is the raw
+/// lambda invocation deadline and the cancellation token is never cancelled by the layer.
+#[derive(Debug, Clone)]
+pub struct LambdaDeadline {
+    deadline: Instant,
+    source: DeadlineSource,
+    token: CancellationToken,
+}
+
+impl LambdaDeadline {
+    /// The point in time the request will be terminated at
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Time left until the request is terminated, zero once expired
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// Which deadline applies to this request
+    pub fn source(&self) -> DeadlineSource {
+        self.source
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.token.is_cancelled() || self.remaining().is_zero()
+    }
+
+    /// Resolves when the deadline is reached, or the request has been terminated by the layer
+    pub async fn expired(&self) {
+        tokio::select! {
+            _ = sleep_until(self.deadline) => {},
+            _ = self.token.cancelled() => {},
+        }
+    }
+
+    /// Token cancelled when the deadline layer terminates the request. Clone it into spawned
+    /// tasks (e.g. the producer of a server stream) to have them stop with the request.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+}
+
 #[derive(Clone, Default)]
 pub(crate) struct LambdaDeadlineLayer {
-    margin: Duration,
+    policy: DeadlinePolicy,
 }
 
 impl LambdaDeadlineLayer {
-    pub fn new(margin: Duration) -> Self {
-        Self { margin }
+    pub fn new(policy: DeadlinePolicy) -> Self {
+        Self { policy }
     }
 }
 
@@ -28,7 +245,7 @@ impl<S> Layer<S> for LambdaDeadlineLayer {
     fn layer(&self, inner: S) -> Self::Service {
         LambdaDeadlineService {
             inner,
-            margin: self.margin,
+            policy: self.policy.clone(),
         }
     }
 }
@@ -36,7 +253,7 @@ impl<S> Layer<S> for LambdaDeadlineLayer {
 #[derive(Clone)]
 pub(crate) struct LambdaDeadlineService<S> {
     inner: S,
-    margin: Duration,
+    policy: DeadlinePolicy,
 }
 
 impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for LambdaDeadlineService<S>
@@ -45,9 +262,9 @@ where
     S::Future: Send + 'static,
     S::Error: Send + 'static,
     ReqBody: Send + 'static,
-    ResBody: Default + Send + 'static,
+    ResBody: HttpBody<Data = Bytes> + Default + Send + 'static,
 {
-    type Response = Response<ResBody>;
+    type Response = Response<DeadlineBody<ResBody>>;
     type Error = S::Error;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
@@ -55,43 +272,579 @@ where
         self.inner.poll_ready(cx)
     }
 
-    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
         let ctx = req.extensions().get::<LambdaContext>();
 
-        let deadline: Option<SystemTime> = ctx.map(|c| c.deadline());
+        let lambda_deadline: Option<SystemTime> = ctx.map(|c| c.deadline());
+        let path = req.uri().path().to_string();
 
-        let fut = self.inner.call(req);
-        let margin = self.margin;
+        let client_timeout = req.headers().get(GRPC_TIMEOUT_HEADER).and_then(|value| {
+            let timeout = value.to_str().ok().and_then(parse_grpc_timeout);
+            if timeout.is_none() {
+                warn!("Ignoring invalid grpc-timeout header {value:?}");
+            }
+            timeout
+        });
 
-        Box::pin(async move {
-            let now = SystemTime::now();
+        let policy = self.policy.clone();
+        let now = SystemTime::now();
 
-            let Some(deadline) = deadline else {
+        let lambda = match lambda_deadline {
+            _ if policy.is_disabled() => None,
+            None => {
                 warn!(
                     "lambda Context missing from request extension. Deadline cannot be determined, continuing..."
                 );
-                return fut.await;
-            };
+                None
+            }
+            Some(lambda_deadline) => {
+                let deadline = policy.effective_deadline(lambda_deadline, now);
+                if deadline.is_none() {
+                    error!("Unexpected time offset failure. Continuing request...");
+                }
+                deadline.map(|deadline| (deadline, DeadlineSource::Lambda))
+            }
+        };
+
+        let client = client_timeout
+            .and_then(|timeout| now.checked_add(timeout))
+            .map(|deadline| (deadline, DeadlineSource::Client));
+
+        let enforced = lambda.into_iter().chain(client).min_by_key(|(d, _)| *d);
+        let token = CancellationToken::new();
 
-            let Some(deadline) = deadline.checked_sub(margin) else {
-                error!("Unexpected time offset failure. Continuing request...");
-                return fut.await;
+        // handlers get to see the deadline even when this layer won't enforce it
+        let visible = enforced.or(lambda_deadline.map(|d| (d, DeadlineSource::Lambda)));
+        if let Some((deadline, source)) = visible {
+            let remaining = deadline.duration_since(now).unwrap_or_default();
+            req.extensions_mut().insert(LambdaDeadline {
+                deadline: Instant::now() + remaining,
+                source,
+                token: token.clone(),
+            });
+        }
+
+        let fut = self.inner.call(req);
+
+        Box::pin(async move {
+            let Some((deadline, source)) = enforced else {
+                return fut.await.map(|res| res.map(DeadlineBody::unbounded));
             };
 
             let Ok(remaining) = deadline.duration_since(now) else {
                 error!("Clock may have gone backwards. Continuing request...");
-                return fut.await;
+                return fut.await.map(|res| res.map(DeadlineBody::unbounded));
             };
 
-            let sleep = sleep_until(Instant::now() + remaining);
+            let mut expiry = Expiry {
+                sleep: Box::pin(sleep_until(Instant::now() + remaining)),
+                policy,
+                exceeded: DeadlineExceeded {
+                    path,
+                    source,
+                    lambda_deadline,
+                    deadline,
+                },
+                token,
+            };
 
             tokio::select! {
-                res = fut => res,
-                _ = sleep => {
-                    info!("Lambda request deadline imminent, terminating request with `deadline_exceeded`");
-                    Ok(Status::deadline_exceeded("Lambda deadline exceeded").into_http())
+                res = fut => res.map(|res| res.map(|body| DeadlineBody::new(body, expiry))),
+                _ = &mut expiry.sleep => {
+                    info!("Request deadline ({source:?}) imminent, terminating request with `deadline_exceeded`");
+                    Ok(expiry.fire().into_http())
                 }
             }
         })
     }
 }
+
+/// The pending deadline of a request, carried from the response future into the response body
+struct Expiry {
+    sleep: Pin<Box<Sleep>>,
+    policy: DeadlinePolicy,
+    exceeded: DeadlineExceeded,
+    token: CancellationToken,
+}
+
+impl Expiry {
+    /// Cancels the handler's [`LambdaDeadline`] token and resolves the status to respond with
+    fn fire(&self) -> Status {
+        self.token.cancel();
+        self.policy.exceeded_status(&self.exceeded)
+    }
+}
+
+/// Response body that keeps watching the request deadline while frames are flowing. For
+/// server-streaming calls the response future resolves as soon as headers are ready, so without
+/// this the deadline would not protect the stream. When the deadline fires the inner body is
+/// dropped and a trailers frame carrying the `DEADLINE_EXCEEDED` status is emitted in its place,
+/// which the grpc-web layer encodes as the final grpc-web frame.
+pub(crate) struct DeadlineBody<B> {
+    inner: Option<Pin<Box<B>>>,
+    expiry: Option<Expiry>,
+    trailers: Option<HeaderMap>,
+}
+
+impl<B> DeadlineBody<B> {
+    fn new(inner: B, expiry: Expiry) -> Self {
+        Self {
+            inner: Some(Box::pin(inner)),
+            expiry: Some(expiry),
+            trailers: None,
+        }
+    }
+
+    fn unbounded(inner: B) -> Self {
+        Self {
+            inner: Some(Box::pin(inner)),
+            expiry: None,
+            trailers: None,
+        }
+    }
+}
+
+impl<B: Default> Default for DeadlineBody<B> {
+    fn default() -> Self {
+        Self::unbounded(B::default())
+    }
+}
+
+impl<B> HttpBody for DeadlineBody<B>
+where
+    B: HttpBody<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+
+        if let Some(expiry) = &mut this.expiry
+            && expiry.sleep.as_mut().poll(cx).is_ready()
+        {
+            info!(
+                "Request deadline ({:?}) imminent, terminating response stream with `deadline_exceeded`",
+                expiry.exceeded.source
+            );
+
+            let mut trailers = HeaderMap::new();
+            if let Err(status) = expiry.fire().add_header(&mut trailers) {
+                error!("Failed to encode deadline status {status:?} into trailers");
+            }
+
+            this.inner = None;
+            this.expiry = None;
+            this.trailers = Some(trailers);
+        }
+
+        let Some(inner) = &mut this.inner else {
+            return Poll::Ready(this.trailers.take().map(|t| Ok(Frame::trailers(t))));
+        };
+
+        let result = inner.as_mut().poll_frame(cx);
+
+        match &result {
+            Poll::Ready(None) => this.expiry = None,
+            Poll::Ready(Some(Ok(frame))) if frame.is_trailers() => this.expiry = None,
+            _ => {}
+        }
+
+        result
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match &self.inner {
+            Some(inner) => inner.is_end_stream(),
+            None => self.trailers.is_none(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match &self.inner {
+            Some(inner) if self.expiry.is_none() => inner.size_hint(),
+            _ => SizeHint::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use http_body_util::{BodyExt, StreamBody};
+    use std::convert::Infallible;
+    use std::time::UNIX_EPOCH;
+    use tonic::body::Body;
+
+    fn request_with_timeouts(remaining: Duration, grpc_timeout: &str) -> Request<Body> {
+        let mut req = request_with_deadline(remaining);
+        req.headers_mut()
+            .insert(GRPC_TIMEOUT_HEADER, grpc_timeout.parse().unwrap());
+        req
+    }
+
+    fn request_with_deadline(remaining: Duration) -> Request<Body> {
+        let deadline = SystemTime::now() + remaining;
+
+        let mut ctx = LambdaContext::default();
+        ctx.deadline = deadline.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+
+        let mut req = Request::builder()
+            .uri("/test.Service/Method")
+            .body(Body::empty())
+            .unwrap();
+        req.extensions_mut().insert(ctx);
+        req
+    }
+
+    fn service(
+        policy: DeadlinePolicy,
+        handler_duration: Duration,
+    ) -> LambdaDeadlineService<
+        impl Service<
+            Request<Body>,
+            Response = Response<Body>,
+            Error = Infallible,
+            Future: Send + 'static,
+        > + Send
+        + 'static,
+    > {
+        let inner = tower::service_fn(move |_req: Request<Body>| async move {
+            tokio::time::sleep(handler_duration).await;
+            Ok::<_, Infallible>(Response::new(Body::empty()))
+        });
+
+        LambdaDeadlineLayer::new(policy).layer(inner)
+    }
+
+    /// Streams a single message, then never completes
+    fn streaming_service(
+        policy: DeadlinePolicy,
+    ) -> LambdaDeadlineService<
+        impl Service<
+            Request<Body>,
+            Response = Response<Body>,
+            Error = Infallible,
+            Future: Send + 'static,
+        > + Send
+        + 'static,
+    > {
+        let inner = tower::service_fn(move |_req: Request<Body>| async move {
+            let frames = futures_util::stream::iter([Ok::<_, Status>(Frame::data(
+                Bytes::from_static(b"first message"),
+            ))])
+            .chain(futures_util::stream::pending());
+
+            Ok::<_, Infallible>(Response::new(Body::new(StreamBody::new(frames))))
+        });
+
+        LambdaDeadlineLayer::new(policy).layer(inner)
+    }
+
+    fn grpc_status<B>(res: &Response<B>) -> Option<&str> {
+        res.headers()
+            .get("grpc-status")
+            .map(|v| v.to_str().unwrap())
+    }
+
+    #[tokio::test]
+    async fn completes_before_deadline() {
+        let mut svc = service(DeadlinePolicy::default(), Duration::from_millis(10));
+
+        let res = svc
+            .call(request_with_deadline(Duration::from_secs(3)))
+            .await
+            .unwrap();
+
+        assert_eq!(grpc_status(&res), None);
+    }
+
+    #[tokio::test]
+    async fn fixed_margin_terminates_before_lambda_deadline() {
+        let mut svc = service(
+            DeadlinePolicy::fixed(Duration::from_millis(900)),
+            Duration::from_secs(5),
+        );
+
+        let started = std::time::Instant::now();
+        let res = svc
+            .call(request_with_deadline(Duration::from_secs(1)))
+            .await
+            .unwrap();
+
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert_eq!(grpc_status(&res), Some("4"));
+        assert_eq!(
+            res.headers().get("grpc-message").unwrap(),
+            "Lambda%20deadline%20exceeded"
+        );
+    }
+
+    #[tokio::test]
+    async fn percentage_margin_terminates_before_lambda_deadline() {
+        let mut svc = service(DeadlinePolicy::percentage(90.0), Duration::from_secs(5));
+
+        let started = std::time::Instant::now();
+        let res = svc
+            .call(request_with_deadline(Duration::from_secs(1)))
+            .await
+            .unwrap();
+
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert_eq!(grpc_status(&res), Some("4"));
+    }
+
+    #[tokio::test]
+    async fn disabled_policy_does_not_terminate() {
+        let mut svc = service(DeadlinePolicy::disabled(), Duration::from_millis(200));
+
+        let res = svc
+            .call(request_with_deadline(Duration::from_millis(100)))
+            .await
+            .unwrap();
+
+        assert_eq!(grpc_status(&res), None);
+    }
+
+    #[tokio::test]
+    async fn missing_context_does_not_terminate() {
+        let mut svc = service(DeadlinePolicy::default(), Duration::from_millis(10));
+
+        let req = Request::builder()
+            .uri("/test.Service/Method")
+            .body(Body::empty())
+            .unwrap();
+
+        let res = svc.call(req).await.unwrap();
+
+        assert_eq!(grpc_status(&res), None);
+    }
+
+    #[tokio::test]
+    async fn on_exceeded_shapes_status() {
+        let policy = DeadlinePolicy::fixed(Duration::from_millis(900))
+            .on_exceeded(|exceeded| Status::unavailable(format!("retry {}", exceeded.path)));
+
+        let mut svc = service(policy, Duration::from_secs(5));
+
+        let res = svc
+            .call(request_with_deadline(Duration::from_secs(1)))
+            .await
+            .unwrap();
+
+        assert_eq!(grpc_status(&res), Some("14"));
+        assert_eq!(
+            res.headers().get("grpc-message").unwrap(),
+            "retry%20/test.Service/Method"
+        );
+    }
+
+    #[tokio::test]
+    async fn client_timeout_earlier_than_lambda_deadline() {
+        let mut svc = service(DeadlinePolicy::default(), Duration::from_secs(5));
+
+        let started = std::time::Instant::now();
+        let res = svc
+            .call(request_with_timeouts(Duration::from_secs(10), "100m"))
+            .await
+            .unwrap();
+
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert_eq!(grpc_status(&res), Some("4"));
+        assert_eq!(
+            res.headers().get("grpc-message").unwrap(),
+            "Client%20deadline%20(grpc-timeout)%20exceeded"
+        );
+    }
+
+    #[tokio::test]
+    async fn lambda_deadline_earlier_than_client_timeout() {
+        let policy = DeadlinePolicy::fixed(Duration::from_millis(900)).on_exceeded(|exceeded| {
+            assert_eq!(exceeded.source, DeadlineSource::Lambda);
+            Status::deadline_exceeded("lambda")
+        });
+        let mut svc = service(policy, Duration::from_secs(5));
+
+        let res = svc
+            .call(request_with_timeouts(Duration::from_secs(1), "10S"))
+            .await
+            .unwrap();
+
+        assert_eq!(grpc_status(&res), Some("4"));
+        assert_eq!(res.headers().get("grpc-message").unwrap(), "lambda");
+    }
+
+    #[tokio::test]
+    async fn client_timeout_honoured_when_policy_disabled() {
+        let mut svc = service(DeadlinePolicy::disabled(), Duration::from_secs(5));
+
+        let res = svc
+            .call(request_with_timeouts(Duration::from_secs(10), "50m"))
+            .await
+            .unwrap();
+
+        assert_eq!(grpc_status(&res), Some("4"));
+    }
+
+    #[tokio::test]
+    async fn terminates_stream_with_trailers() {
+        let mut svc = streaming_service(DeadlinePolicy::fixed(Duration::from_millis(800)));
+
+        let res = svc
+            .call(request_with_deadline(Duration::from_secs(1)))
+            .await
+            .unwrap();
+
+        assert_eq!(grpc_status(&res), None);
+
+        let mut body = res.into_body();
+
+        let frame = body.frame().await.unwrap().unwrap();
+        assert_eq!(frame.into_data().unwrap(), "first message");
+
+        let frame = tokio::time::timeout(Duration::from_secs(1), body.frame())
+            .await
+            .expect("deadline should terminate the stream")
+            .unwrap()
+            .unwrap();
+        let trailers = frame.into_trailers().unwrap();
+        assert_eq!(trailers.get("grpc-status").unwrap(), "4");
+        assert_eq!(
+            trailers.get("grpc-message").unwrap(),
+            "Lambda%20deadline%20exceeded"
+        );
+
+        assert!(body.frame().await.is_none());
+        assert!(body.is_end_stream());
+    }
+
+    #[tokio::test]
+    async fn stream_without_deadline_is_unbounded() {
+        let mut svc = streaming_service(DeadlinePolicy::disabled());
+
+        let res = svc
+            .call(request_with_deadline(Duration::from_millis(100)))
+            .await
+            .unwrap();
+
+        let mut body = res.into_body();
+        body.frame().await.unwrap().unwrap();
+
+        let next = tokio::time::timeout(Duration::from_millis(300), body.frame()).await;
+        assert!(next.is_err(), "stream should remain open");
+    }
+
+    #[tokio::test]
+    async fn deadline_extension_visible_to_handler() {
+        let inner = tower::service_fn(|req: Request<Body>| async move {
+            let deadline = req.extensions().get::<LambdaDeadline>().unwrap().clone();
+            assert_eq!(deadline.source(), DeadlineSource::Lambda);
+            assert!(deadline.remaining() <= Duration::from_millis(500));
+            assert!(!deadline.is_expired());
+
+            deadline.expired().await;
+            assert!(deadline.is_expired());
+
+            // the layer responds in place of the handler
+            futures_util::future::pending::<Result<Response<Body>, Infallible>>().await
+        });
+        let mut svc = LambdaDeadlineLayer::new(DeadlinePolicy::fixed(Duration::from_millis(500)))
+            .layer(inner);
+
+        let res = tokio::time::timeout(
+            Duration::from_secs(1),
+            svc.call(request_with_deadline(Duration::from_millis(600))),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(grpc_status(&res), Some("4"));
+    }
+
+    #[tokio::test]
+    async fn cancellation_token_cancelled_when_stream_terminated() {
+        let (token_tx, token_rx) = tokio::sync::oneshot::channel();
+        let token_tx = std::sync::Mutex::new(Some(token_tx));
+
+        let inner = tower::service_fn(move |req: Request<Body>| {
+            let deadline = req.extensions().get::<LambdaDeadline>().unwrap();
+            let sender = token_tx.lock().unwrap().take().unwrap();
+            sender.send(deadline.cancellation_token()).unwrap();
+
+            async move {
+                let pending = futures_util::stream::pending::<Result<Frame<Bytes>, Status>>();
+                Ok::<_, Infallible>(Response::new(Body::new(StreamBody::new(pending))))
+            }
+        });
+        let mut svc = LambdaDeadlineLayer::new(DeadlinePolicy::fixed(Duration::from_millis(900)))
+            .layer(inner);
+
+        let res = svc
+            .call(request_with_deadline(Duration::from_secs(1)))
+            .await
+            .unwrap();
+        let token = token_rx.await.unwrap();
+        assert!(!token.is_cancelled());
+
+        let trailers = res.into_body().frame().await.unwrap().unwrap();
+        assert!(trailers.is_trailers());
+        assert!(token.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn deadline_extension_present_when_policy_disabled() {
+        let inner = tower::service_fn(|req: Request<Body>| async move {
+            let deadline = req.extensions().get::<LambdaDeadline>().unwrap();
+            assert!(deadline.remaining() > Duration::from_millis(1500));
+            Ok::<_, Infallible>(Response::new(Body::empty()))
+        });
+        let mut svc = LambdaDeadlineLayer::new(DeadlinePolicy::disabled()).layer(inner);
+
+        svc.call(request_with_deadline(Duration::from_secs(2)))
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn parses_grpc_timeout() {
+        assert_eq!(parse_grpc_timeout("1H"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_grpc_timeout("2M"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_grpc_timeout("3S"), Some(Duration::from_secs(3)));
+        assert_eq!(parse_grpc_timeout("100m"), Some(Duration::from_millis(100)));
+        assert_eq!(parse_grpc_timeout("5u"), Some(Duration::from_micros(5)));
+        assert_eq!(
+            parse_grpc_timeout("99999999n"),
+            Some(Duration::from_nanos(99999999))
+        );
+
+        assert_eq!(parse_grpc_timeout(""), None);
+        assert_eq!(parse_grpc_timeout("m"), None);
+        assert_eq!(parse_grpc_timeout("10"), None);
+        assert_eq!(parse_grpc_timeout("10x"), None);
+        assert_eq!(parse_grpc_timeout("-1S"), None);
+        assert_eq!(parse_grpc_timeout("123456789S"), None);
+    }
+
+    #[test]
+    fn percentage_is_of_remaining_time() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        let deadline = now + Duration::from_secs(10);
+
+        let effective = DeadlinePolicy::percentage(25.0)
+            .effective_deadline(deadline, now)
+            .unwrap();
+
+        assert_eq!(effective, now + Duration::from_millis(7_500));
+    }
+
+    #[test]
+    #[should_panic]
+    fn percentage_out_of_range_panics() {
+        DeadlinePolicy::percentage(120.0);
+    }
+}
@@ -0,0 +1,95 @@
+//! Synthetic Function URL events, for invoking the lambda with a HTTP request without going
+//! through a Function URL, and the events of direct `Invoke` API calls
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use bytes::Bytes;
+use http::request::Parts;
+use serde_json::{Map, Value, json};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The Function URL the events appear to arrive through
+#[cfg(feature = "testing")]
+pub(crate) struct FunctionUrl<'a> {
+    pub(crate) domain_prefix: &'a str,
+    pub(crate) region: &'a str,
+}
+
+#[cfg(feature = "testing")]
+impl FunctionUrl<'_> {
+    /// Builds the API Gateway v2 payload the Function URL would invoke the lambda with for the
+    /// request
+    pub(crate) fn event(&self, parts: &Parts, body: &Bytes, base64_encoded: bool) -> Value {
+        let domain_name = format!("{}.lambda-url.{}.on.aws", self.domain_prefix, self.region);
+        let mut event = http_event(parts, body, base64_encoded, "$default");
+
+        event["headers"]["host"] = json!(domain_name);
+        let ctx = &mut event["requestContext"];
+        ctx["requestId"] = json!(format!("{}-{}", self.domain_prefix, ctx["timeEpoch"]));
+        ctx["accountId"] = json!("anonymous");
+        ctx["apiId"] = json!(self.domain_prefix);
+        ctx["domainName"] = json!(domain_name);
+        ctx["domainPrefix"] = json!(self.domain_prefix);
+        ctx["stage"] = json!("$default");
+        ctx["http"]["sourceIp"] = json!("127.0.0.1");
+
+        event
+    }
+}
+
+/// Builds the API Gateway v2 payload for a call through the Lambda `Invoke` API, marked with the
+/// `$invoke` route key. Lambda authorizes the caller for `lambda:InvokeFunction` but doesn't pass
+/// their identity on, so the event carries no account, source IP, domain or request id.
+#[cfg(feature = "client")]
+pub(crate) fn invoke_event(parts: &Parts, body: &Bytes) -> Value {
+    http_event(parts, body, true, crate::INVOKE_ROUTE_KEY)
+}
+
+fn http_event(parts: &Parts, body: &Bytes, base64_encoded: bool, route_key: &str) -> Value {
+    let mut headers = Map::new();
+    for name in parts.headers.keys() {
+        let values: Vec<&str> = parts
+            .headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .collect();
+        headers.insert(name.to_string(), Value::String(values.join(",")));
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+
+    let path = parts.uri.path();
+
+    json!({
+        "version": "2.0",
+        "routeKey": route_key,
+        "rawPath": path,
+        "rawQueryString": parts.uri.query().unwrap_or_default(),
+        "headers": headers,
+        "requestContext": {
+            "http": {
+                "method": parts.method.as_str(),
+                "path": path,
+                "protocol": "HTTP/1.1",
+                "userAgent": parts
+                    .headers
+                    .get(http::header::USER_AGENT)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default(),
+            },
+            "routeKey": route_key,
+            "time": "01/Jan/2025:00:00:00 +0000",
+            "timeEpoch": now,
+        },
+        "body": if base64_encoded {
+            STANDARD.encode(body)
+        } else {
+            String::from_utf8_lossy(body).into_owned()
+        },
+        "isBase64Encoded": base64_encoded,
+    })
+}
@@ -0,0 +1,126 @@
+//! The service, method and final status of gRPC calls, shared by the layers reporting on them.
+
+#[cfg(any(feature = "tracing", feature = "metrics-emf"))]
+pub(crate) use observe::{CallObserver, ObservedBody};
+
+/// The `/package.Service/Method` request path split into its service and method
+pub(crate) fn service_method(path: &str) -> Option<(&str, &str)> {
+    let path = path.strip_prefix('/')?;
+    let (service, method) = path.split_once('/')?;
+    (!service.is_empty() && !method.contains('/')).then_some((service, method))
+}
+
+/// The status of a trailers-only response, or of the trailers
+#[cfg(any(feature = "tracing", feature = "metrics-emf"))]
+pub(crate) fn grpc_status(headers: &http::HeaderMap) -> Option<tonic::Code> {
+    headers
+        .get("grpc-status")
+        .map(|status| tonic::Code::from_bytes(status.as_bytes()))
+}
+
+#[cfg(any(feature = "tracing", feature = "metrics-emf"))]
+mod observe {
+    use super::grpc_status;
+    use bytes::Bytes;
+    use http::Response;
+    use http_body::{Body as HttpBody, Frame, SizeHint};
+    use std::pin::Pin;
+    use std::task::{Context, Poll, ready};
+    use tonic::body::Body;
+    use tonic::{Code, Status};
+    use tower::BoxError;
+
+    /// Reports on a call as its response body streams
+    pub(crate) trait CallObserver: Send + Unpin + 'static {
+        /// Polls the inner body, i.e. within a span
+        fn poll<T>(&mut self, poll: impl FnOnce() -> T) -> T {
+            poll()
+        }
+
+        /// Sees every data frame of the response
+        fn data(&mut self, _data: &Bytes) {}
+
+        /// The call completed with the status, called once
+        fn complete(&mut self, status: Code);
+    }
+
+    /// Response body completing its [`CallObserver`] once the trailers are sent, with the status
+    /// of the trailers or headers, or `UNKNOWN` when neither carried one. Bodies dropped before
+    /// then complete as `CANCELLED`, as the client went away.
+    pub(crate) struct ObservedBody<O: CallObserver> {
+        inner: Body,
+        observer: O,
+        status: Option<Code>,
+        completed: bool,
+    }
+
+    impl<O: CallObserver> ObservedBody<O> {
+        pub(crate) fn wrap<B>(res: Response<B>, observer: O) -> Response<Body>
+        where
+            B: HttpBody<Data = Bytes> + Send + 'static,
+            B::Error: Into<BoxError>,
+        {
+            let status = grpc_status(res.headers());
+            res.map(|body| {
+                Body::new(Self {
+                    inner: Body::new(body),
+                    observer,
+                    status,
+                    completed: false,
+                })
+            })
+        }
+
+        fn complete(&mut self, missing: Code) {
+            if !std::mem::replace(&mut self.completed, true) {
+                self.observer.complete(self.status.unwrap_or(missing));
+            }
+        }
+    }
+
+    impl<O: CallObserver> HttpBody for ObservedBody<O> {
+        type Data = Bytes;
+        type Error = Status;
+
+        fn poll_frame(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+            let this = self.get_mut();
+            let inner = Pin::new(&mut this.inner);
+            let frame = ready!(this.observer.poll(|| inner.poll_frame(cx)));
+
+            match &frame {
+                Some(Ok(frame)) => {
+                    if let Some(data) = frame.data_ref() {
+                        this.observer.data(data);
+                    } else if let Some(trailers) = frame.trailers_ref() {
+                        this.status = grpc_status(trailers).or(this.status);
+                        this.complete(Code::Unknown);
+                    }
+                }
+                Some(Err(status)) => {
+                    this.status = Some(status.code());
+                    this.complete(Code::Unknown);
+                }
+                None => this.complete(Code::Unknown),
+            }
+
+            Poll::Ready(frame)
+        }
+
+        fn is_end_stream(&self) -> bool {
+            self.inner.is_end_stream()
+        }
+
+        fn size_hint(&self) -> SizeHint {
+            self.inner.size_hint()
+        }
+    }
+
+    impl<O: CallObserver> Drop for ObservedBody<O> {
+        fn drop(&mut self) {
+            self.complete(Code::Cancelled);
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Helpers for the grpc-web message framing, for the places this crate handles grpc-web bodies
+//! directly rather than through `tonic_web`.
+
+#[cfg(any(feature = "connect", feature = "transcoding"))]
+use bytes::Buf;
+use bytes::{BufMut, Bytes, BytesMut};
+use http::header::{ACCEPT, CONTENT_TYPE};
+use http::{HeaderMap, Request};
+#[cfg(any(feature = "connect", feature = "transcoding"))]
+use http::{HeaderName, HeaderValue};
+
+/// Content type of base64 encoded grpc-web bodies, as used by browser clients unable to read
+/// binary streams
+pub(crate) const GRPC_WEB_TEXT: &str = "application/grpc-web-text";
+
+/// Size of the flags byte and the big endian `u32` length prefixing every frame
+pub(crate) const FRAME_HEADER_SIZE: usize = 5;
+
+/// Flag set on the frame carrying the trailers, which must be the final frame of the body
+pub(crate) const TRAILERS_FLAG: u8 = 0x80;
+
+/// Encodes trailers as a grpc-web trailers frame, i.e. a HTTP/1 style header block prefixed with
+/// the frame header.
+pub(crate) fn encode_trailers_frame(trailers: &HeaderMap) -> Bytes {
+    let block = trailers.iter().fold(Vec::new(), |mut acc, (key, value)| {
+        acc.put_slice(key.as_ref());
+        acc.put_slice(b":");
+        acc.put_slice(value.as_bytes());
+        acc.put_slice(b"\r\n");
+        acc
+    });
+
+    encode_frame(TRAILERS_FLAG, &block)
+}
+
+/// Encodes a message as a grpc-web data frame
+pub(crate) fn encode_frame(flags: u8, payload: &[u8]) -> Bytes {
+    let mut frame = BytesMut::with_capacity(FRAME_HEADER_SIZE + payload.len());
+    frame.put_u8(flags);
+    frame.put_u32(payload.len() as u32);
+    frame.put_slice(payload);
+    frame.freeze()
+}
+
+/// Parses the header block of a trailers frame, the inverse of [`encode_trailers_frame`].
+/// Malformed lines are skipped.
+#[cfg(any(feature = "connect", feature = "transcoding"))]
+pub(crate) fn decode_trailers(block: &[u8]) -> HeaderMap {
+    let mut trailers = HeaderMap::new();
+
+    for line in block.split(|b| *b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let Some(colon) = line.iter().position(|b| *b == b':') else {
+            continue;
+        };
+
+        let name = HeaderName::from_bytes(line[..colon].trim_ascii());
+        let value = HeaderValue::from_bytes(line[colon + 1..].trim_ascii());
+        if let (Ok(name), Ok(value)) = (name, value) {
+            trailers.append(name, value);
+        }
+    }
+
+    trailers
+}
+
+/// Splits a body arriving in arbitrary chunks back into grpc-web frames
+#[cfg(any(feature = "connect", feature = "transcoding"))]
+#[derive(Default)]
+pub(crate) struct FrameDecoder {
+    buf: BytesMut,
+}
+
+#[cfg(any(feature = "connect", feature = "transcoding"))]
+impl FrameDecoder {
+    pub(crate) fn push(&mut self, data: impl Buf) {
+        self.buf.put(data);
+    }
+
+    /// The next complete frame, as its flags and payload
+    pub(crate) fn next_frame(&mut self) -> Option<(u8, Bytes)> {
+        if self.buf.len() < FRAME_HEADER_SIZE {
+            return None;
+        }
+
+        let len = u32::from_be_bytes(self.buf[1..FRAME_HEADER_SIZE].try_into().ok()?) as usize;
+        if self.buf.len() < FRAME_HEADER_SIZE + len {
+            return None;
+        }
+
+        let flags = self.buf.get_u8();
+        self.buf.advance(FRAME_HEADER_SIZE - 1);
+        Some((flags, self.buf.split_to(len).freeze()))
+    }
+
+    /// Whether a partial frame is left over
+    #[cfg(feature = "connect")]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
+
+/// Whether the headers declare a grpc-web-text body
+pub(crate) fn is_text(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with(GRPC_WEB_TEXT))
+}
+
+/// `tonic_web` picks the response encoding from the `accept` header alone, so a grpc-web-text
+/// request accepting anything (e.g. `*/*`) would get a binary response the client can't read.
+/// Unless a grpc-web content type is explicitly accepted, respond in the encoding of the request.
+pub(crate) fn negotiate_response_encoding<B>(mut req: Request<B>) -> Request<B> {
+    let accepts_grpc_web = req
+        .headers()
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/grpc-web"));
+
+    if is_text(req.headers())
+        && !accepts_grpc_web
+        && let Some(content_type) = req.headers().get(CONTENT_TYPE).cloned()
+    {
+        req.headers_mut().insert(ACCEPT, content_type);
+    }
+
+    req
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(content_type: &str, accept: Option<&str>) -> Request<()> {
+        let mut req = Request::post("/test.Service/Method").header(CONTENT_TYPE, content_type);
+        if let Some(accept) = accept {
+            req = req.header(ACCEPT, accept);
+        }
+        req.body(()).unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "connect")]
+    fn decodes_frames_split_across_chunks() {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        trailers.insert("grpc-message", HeaderValue::from_static("ok"));
+
+        let mut body = encode_frame(0, b"hello").to_vec();
+        body.extend_from_slice(&encode_trailers_frame(&trailers));
+
+        let mut decoder = FrameDecoder::default();
+        let mut frames = Vec::new();
+        for chunk in body.chunks(3) {
+            decoder.push(chunk);
+            while let Some(frame) = decoder.next_frame() {
+                frames.push(frame);
+            }
+        }
+
+        assert!(decoder.is_empty());
+        assert_eq!(frames[0], (0, Bytes::from_static(b"hello")));
+        assert_eq!(frames[1].0, TRAILERS_FLAG);
+        assert_eq!(decode_trailers(&frames[1].1), trailers);
+    }
+
+    #[test]
+    fn text_request_accepting_anything_responds_as_text() {
+        let req =
+            negotiate_response_encoding(request("application/grpc-web-text+proto", Some("*/*")));
+
+        assert_eq!(
+            req.headers().get(ACCEPT).unwrap(),
+            "application/grpc-web-text+proto"
+        );
+    }
+
+    #[test]
+    fn explicit_accept_is_kept() {
+        let req = negotiate_response_encoding(request(
+            "application/grpc-web-text",
+            Some("application/grpc-web+proto"),
+        ));
+
+        assert_eq!(
+            req.headers().get(ACCEPT).unwrap(),
+            "application/grpc-web+proto"
+        );
+    }
+
+    #[test]
+    fn binary_request_is_untouched() {
+        let req = negotiate_response_encoding(request("application/grpc-web+proto", None));
+
+        assert!(req.headers().get(ACCEPT).is_none());
+    }
+}
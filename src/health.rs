@@ -0,0 +1,486 @@
+//! `grpc.health.v1.Health`, reporting the status of the services added to the router.
+
+use futures_util::future::BoxFuture;
+use futures_util::stream;
+use http::{Request, Response};
+use lambda_http::tracing::log::warn;
+use lambda_runtime::Context as LambdaContext;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::future::{Future, ready};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
+use tokio::time::{Instant, sleep, sleep_until};
+use tonic::Status;
+use tonic::body::Body;
+use tonic::server::{Grpc, NamedService};
+use tonic_prost::ProstCodec;
+use tower::{BoxError, Service};
+
+const CHECK: &str = "/grpc.health.v1.Health/Check";
+const WATCH: &str = "/grpc.health.v1.Health/Watch";
+
+/// Paths of the methods served
+#[cfg(any(feature = "auth-jwt", feature = "metrics-emf"))]
+pub(crate) const METHODS: [&str; 2] = [CHECK, WATCH];
+
+/// How long before the invocation deadline a `Watch` stream is ended, so it completes with an `OK`
+/// status rather than being terminated.
+const WATCH_MARGIN: Duration = Duration::from_millis(100);
+
+type ReadinessCheck = Arc<dyn Fn() -> BoxFuture<'static, Result<(), BoxError>> + Send + Sync>;
+
+/// Serving status of a service, as reported by `grpc.health.v1.Health`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServingStatus {
+    Unknown = 0,
+    Serving = 1,
+    NotServing = 2,
+}
+
+/// Configures the built-in `grpc.health.v1.Health` service. Every service passed to
+/// `add_service` is reported as `SERVING` until set otherwise with the [`HealthReporter`], as is
+/// the server as a whole, under the empty service name.
+#[derive(Clone)]
+pub struct HealthConfig {
+    statuses: Arc<watch::Sender<HashMap<String, ServingStatus>>>,
+    readiness: Option<ReadinessCheck>,
+    recheck_interval: Duration,
+}
+
+impl HealthConfig {
+    pub fn new() -> Self {
+        let statuses = HashMap::from([
+            (String::new(), ServingStatus::Serving),
+            (HealthService::NAME.to_string(), ServingStatus::Serving),
+        ]);
+
+        Self {
+            statuses: Arc::new(watch::Sender::new(statuses)),
+            readiness: None,
+            recheck_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Handle to set the status of services, e.g. from the lambda init phase ahead of
+    /// `LambdaRouter::serve`, or from a background task. Reporters share the statuses of the
+    /// config they were taken from.
+    pub fn reporter(&self) -> HealthReporter {
+        HealthReporter {
+            statuses: self.statuses.clone(),
+        }
+    }
+
+    /// Checked on every `Check` and periodically during a `Watch`, e.g. pinging a database pool.
+    /// While it fails, services reported as `SERVING` are reported as `NOT_SERVING` instead.
+    pub fn readiness_check<F, Fut, E>(mut self, check: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Into<BoxError> + 'static,
+    {
+        self.readiness = Some(Arc::new(move || {
+            let check = check();
+            Box::pin(async move { check.await.map_err(Into::into) })
+        }));
+        self
+    }
+
+    /// How often a `Watch` reruns the readiness check. Defaults to 1s.
+    pub fn recheck_interval(mut self, interval: Duration) -> Self {
+        self.recheck_interval = interval;
+        self
+    }
+
+    /// Reports a service added to the router as serving, unless its status was already set
+    pub(crate) fn register(&self, service: &str) {
+        self.statuses.send_if_modified(|statuses| {
+            if statuses.contains_key(service) {
+                return false;
+            }
+            statuses.insert(service.to_string(), ServingStatus::Serving);
+            true
+        });
+    }
+
+    pub(crate) fn service(&self) -> HealthService {
+        HealthService {
+            config: self.clone(),
+        }
+    }
+
+    /// The status of a service, if it's known, with the readiness check applied
+    async fn status(&self, service: &str) -> Option<ServingStatus> {
+        let status = *self.statuses.borrow().get(service)?;
+        if status == ServingStatus::Serving && !self.is_ready().await {
+            return Some(ServingStatus::NotServing);
+        }
+        Some(status)
+    }
+
+    async fn is_ready(&self) -> bool {
+        let Some(readiness) = &self.readiness else {
+            return true;
+        };
+        match readiness().await {
+            Ok(()) => true,
+            Err(err) => {
+                warn!("Readiness check failed: {err}");
+                false
+            }
+        }
+    }
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for HealthConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HealthConfig")
+            .field("statuses", &*self.statuses.borrow())
+            .field("readiness", &self.readiness.is_some())
+            .field("recheck_interval", &self.recheck_interval)
+            .finish()
+    }
+}
+
+/// Sets the status reported for services, see [`HealthConfig::reporter`]
+#[derive(Clone, Debug)]
+pub struct HealthReporter {
+    statuses: Arc<watch::Sender<HashMap<String, ServingStatus>>>,
+}
+
+impl HealthReporter {
+    pub fn set_serving<S: NamedService>(&self) {
+        self.set_service_status(S::NAME, ServingStatus::Serving);
+    }
+
+    pub fn set_not_serving<S: NamedService>(&self) {
+        self.set_service_status(S::NAME, ServingStatus::NotServing);
+    }
+
+    /// Set the status of a service by name, or of the server as a whole with `""`
+    pub fn set_service_status(&self, service: &str, status: ServingStatus) {
+        self.statuses.send_if_modified(|statuses| {
+            statuses.insert(service.to_string(), status) != Some(status)
+        });
+    }
+}
+
+/// Serves `grpc.health.v1.Health`. `Watch` streams status changes until shortly before the
+/// invocation deadline, then ends with `OK` so the client can resume watching with a new request.
+#[derive(Clone)]
+pub(crate) struct HealthService {
+    config: HealthConfig,
+}
+
+impl NamedService for HealthService {
+    const NAME: &'static str = "grpc.health.v1.Health";
+}
+
+impl Service<Request<Body>> for HealthService {
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let config = self.config.clone();
+        match req.uri().path() {
+            CHECK => {
+                let check =
+                    tower::service_fn(move |request: tonic::Request<HealthCheckRequest>| {
+                        let config = config.clone();
+                        async move {
+                            let service = request.into_inner().service;
+                            match config.status(&service).await {
+                                Some(status) => {
+                                    Ok(tonic::Response::new(HealthCheckResponse::from(status)))
+                                }
+                                None => Err(Status::not_found("service not registered")),
+                            }
+                        }
+                    });
+                Box::pin(
+                    async move { Ok(Grpc::new(ProstCodec::default()).unary(check, req).await) },
+                )
+            }
+            WATCH => {
+                let watch =
+                    tower::service_fn(move |request: tonic::Request<HealthCheckRequest>| {
+                        let end = watch_end(&request);
+                        let watcher = Watcher {
+                            statuses: config.statuses.subscribe(),
+                            service: request.into_inner().service,
+                            last: None,
+                            end,
+                            config: config.clone(),
+                        };
+                        let responses = stream::unfold(watcher, |mut watcher| async move {
+                            let status = watcher.next_change().await?;
+                            Some((Ok::<_, Status>(status), watcher))
+                        });
+                        ready(Ok::<_, Status>(tonic::Response::new(Box::pin(responses))))
+                    });
+                Box::pin(async move {
+                    Ok(Grpc::new(ProstCodec::default())
+                        .server_streaming(watch, req)
+                        .await)
+                })
+            }
+            _ => {
+                let status = Status::unimplemented("Unknown health method");
+                Box::pin(ready(Ok(status.into_http())))
+            }
+        }
+    }
+}
+
+/// When a `Watch` ends: ahead of the deadline the deadline layer enforces, or of the invocation
+/// deadline when it's disabled
+fn watch_end<T>(request: &tonic::Request<T>) -> Option<Instant> {
+    #[cfg(feature = "deadline")]
+    if let Some(deadline) = request.extensions().get::<crate::LambdaDeadline>() {
+        return Some(deadline.deadline() - WATCH_MARGIN.min(deadline.remaining()));
+    }
+
+    let ctx = request.extensions().get::<LambdaContext>()?;
+    let remaining = ctx
+        .deadline()
+        .duration_since(SystemTime::now())
+        .unwrap_or_default();
+    Some(Instant::now() + remaining.saturating_sub(WATCH_MARGIN))
+}
+
+struct Watcher {
+    config: HealthConfig,
+    statuses: watch::Receiver<HashMap<String, ServingStatus>>,
+    service: String,
+    /// The status last sent
+    last: Option<i32>,
+    end: Option<Instant>,
+}
+
+impl Watcher {
+    /// Waits for the status to differ from the one last sent, `None` once the watch has ended
+    async fn next_change(&mut self) -> Option<HealthCheckResponse> {
+        loop {
+            self.statuses.mark_unchanged();
+            let response = match self.config.status(&self.service).await {
+                Some(status) => HealthCheckResponse::from(status),
+                None => HealthCheckResponse {
+                    status: SERVICE_UNKNOWN,
+                },
+            };
+
+            if self.last != Some(response.status) {
+                self.last = Some(response.status);
+                return Some(response);
+            }
+
+            let end = async {
+                match self.end {
+                    Some(end) => sleep_until(end).await,
+                    None => std::future::pending().await,
+                }
+            };
+            let recheck = async {
+                match self.config.readiness {
+                    Some(_) => sleep(self.config.recheck_interval).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                _ = end => return None,
+                changed = self.statuses.changed() => changed.ok()?,
+                _ = recheck => {}
+            }
+        }
+    }
+}
+
+/// `SERVICE_UNKNOWN`, only reported by `Watch`, as `Check` fails with `NOT_FOUND` instead
+const SERVICE_UNKNOWN: i32 = 3;
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct HealthCheckRequest {
+    #[prost(string, tag = "1")]
+    service: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct HealthCheckResponse {
+    #[prost(int32, tag = "1")]
+    status: i32,
+}
+
+impl From<ServingStatus> for HealthCheckResponse {
+    fn from(status: ServingStatus) -> Self {
+        Self {
+            status: status as i32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::{Buf, Bytes};
+    use http::header::CONTENT_TYPE;
+    use http_body_util::{BodyExt, Full};
+    use prost::Message;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::UNIX_EPOCH;
+    use tower::ServiceExt;
+
+    /// Calls the health service, returning the statuses it responded with and the `grpc-status`
+    async fn call(
+        config: &HealthConfig,
+        path: &str,
+        service: &str,
+        ctx: Option<LambdaContext>,
+    ) -> (Vec<i32>, String) {
+        let request = HealthCheckRequest {
+            service: service.to_string(),
+        }
+        .encode_to_vec();
+        let mut body = vec![0];
+        body.extend_from_slice(&(request.len() as u32).to_be_bytes());
+        body.extend_from_slice(&request);
+
+        let mut req = Request::post(path)
+            .header(CONTENT_TYPE, "application/grpc")
+            .body(Body::new(Full::new(Bytes::from(body))))
+            .unwrap();
+        if let Some(ctx) = ctx {
+            req.extensions_mut().insert(ctx);
+        }
+
+        let res = config.service().oneshot(req).await.unwrap();
+        let (parts, body) = res.into_parts();
+        let body = body.collect().await.unwrap();
+        let status = parts
+            .headers
+            .get("grpc-status")
+            .or_else(|| body.trailers()?.get("grpc-status"))
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let mut body = body.to_bytes();
+        let mut statuses = Vec::new();
+        while body.has_remaining() {
+            body.advance(1);
+            let len = body.get_u32() as usize;
+            statuses.push(
+                HealthCheckResponse::decode(body.split_to(len))
+                    .unwrap()
+                    .status,
+            );
+        }
+        (statuses, status)
+    }
+
+    #[tokio::test]
+    async fn checks_registered_services() {
+        let config = HealthConfig::new();
+        config.register("test.Library");
+        config
+            .reporter()
+            .set_service_status("test.Archive", ServingStatus::NotServing);
+
+        assert_eq!(call(&config, CHECK, "", None).await, (vec![1], "0".into()));
+        assert_eq!(
+            call(&config, CHECK, "test.Library", None).await,
+            (vec![1], "0".into())
+        );
+        assert_eq!(
+            call(&config, CHECK, "test.Archive", None).await,
+            (vec![2], "0".into())
+        );
+        assert_eq!(
+            call(&config, CHECK, "test.Missing", None).await,
+            (vec![], "5".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn registering_keeps_status_set_at_init() {
+        let config = HealthConfig::new();
+        config
+            .reporter()
+            .set_service_status("test.Library", ServingStatus::NotServing);
+        config.register("test.Library");
+
+        assert_eq!(
+            call(&config, CHECK, "test.Library", None).await,
+            (vec![2], "0".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_not_serving_while_not_ready() {
+        let ready = Arc::new(AtomicBool::new(false));
+        let config = HealthConfig::new().readiness_check({
+            let ready = ready.clone();
+            move || {
+                let ready = ready.load(Ordering::SeqCst);
+                async move { if ready { Ok(()) } else { Err("pool exhausted") } }
+            }
+        });
+
+        assert_eq!(call(&config, CHECK, "", None).await, (vec![2], "0".into()));
+
+        ready.store(true, Ordering::SeqCst);
+        assert_eq!(call(&config, CHECK, "", None).await, (vec![1], "0".into()));
+    }
+
+    #[tokio::test]
+    async fn watch_streams_changes_until_the_deadline() {
+        let config = HealthConfig::new();
+        config.register("test.Library");
+
+        let deadline = SystemTime::now() + Duration::from_millis(400);
+        let mut ctx = LambdaContext::default();
+        ctx.deadline = deadline.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+
+        let reporter = config.reporter();
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(50)).await;
+            reporter.set_not_serving::<HealthService>();
+            reporter.set_service_status("test.Library", ServingStatus::NotServing);
+        });
+
+        let watch = call(&config, WATCH, "test.Library", Some(ctx)).await;
+
+        assert_eq!(watch, (vec![1, 2], "0".into()));
+        assert!(SystemTime::now() < deadline);
+    }
+
+    #[tokio::test]
+    async fn watch_reports_unknown_services() {
+        let config = HealthConfig::new();
+
+        let mut ctx = LambdaContext::default();
+        let deadline = SystemTime::now() + Duration::from_millis(150);
+        ctx.deadline = deadline.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+
+        assert_eq!(
+            call(&config, WATCH, "test.Missing", Some(ctx)).await,
+            (vec![SERVICE_UNKNOWN], "0".into())
+        );
+    }
+}
@@ -1,33 +1,105 @@
+#[cfg(feature = "auth-jwt")]
+use crate::auth_jwt::JwtAuthConfig;
+#[cfg(feature = "auth-jwt")]
+use crate::auth_policy::AuthorizationPolicy;
+use crate::buffered::buffer_response;
+#[cfg(feature = "catch-panic")]
+use crate::catch_panic::{CatchPanicLayer, PanicHandler};
+#[cfg(feature = "connect")]
+use crate::connect::ConnectConfig;
+#[cfg(feature = "cors")]
+use crate::cors::CorsConfig;
 #[cfg(feature = "deadline")]
-use crate::deadline_layer::LambdaDeadlineLayer;
+use crate::deadline_layer::{DeadlinePolicy, LambdaDeadlineLayer};
+use crate::grpc_web::negotiate_response_encoding;
+#[cfg(feature = "health")]
+use crate::health::HealthConfig;
+use crate::lifecycle::{self, ColdStart, LifecycleHook};
+#[cfg(feature = "local")]
+use crate::local;
+#[cfg(feature = "metrics-emf")]
+use crate::metrics_emf::EmfConfig;
+#[cfg(feature = "reflection")]
+use crate::reflection::{ReflectionService, ServiceRegistry};
+#[cfg(feature = "request-info")]
+use crate::request_info::LambdaRequestInfo;
+#[cfg(feature = "tracing")]
+use crate::rpc_span::RpcSpanLayer;
+#[cfg(feature = "graceful-shutdown")]
+use crate::shutdown::GracefulShutdown;
+#[cfg(any(feature = "local", feature = "testing"))]
+use crate::synthetic;
+#[cfg(feature = "transcoding")]
+use crate::transcoding::TranscodingConfig;
 #[cfg(feature = "wire-log")]
 use crate::wire_log::WireLogLayer;
+use bytes::Bytes;
 use http::{Request, Response};
 use lambda_runtime::Error;
-use std::any::Any;
+#[cfg(feature = "reflection")]
+use prost_reflect::DescriptorError;
+#[cfg(any(feature = "auth-jwt", feature = "metrics-emf"))]
+use std::collections::HashSet;
 use std::convert::Infallible;
+use std::future::Future;
+#[cfg(any(feature = "local", feature = "testing", feature = "graceful-shutdown"))]
 use std::time::Duration;
+#[cfg(feature = "local")]
+use tokio::net::{TcpListener, ToSocketAddrs};
 use tonic::body::Body;
 use tonic::server::NamedService;
 use tonic::service::Routes;
-use tonic::Status;
 use tonic_web::GrpcWebLayer;
 use tower::layer::util::{Identity, Stack};
-use tower::{Layer, Service, ServiceBuilder};
-use tower_http::catch_panic::CatchPanicLayer;
+use tower::util::BoxCloneService;
+use tower::{BoxError, Layer, Service, ServiceBuilder};
 
 type GrpcRequest = Request<Body>;
 type GrpcResponse = Response<Body>;
+pub(crate) type LambdaService = BoxCloneService<GrpcRequest, GrpcResponse, Infallible>;
 
 #[derive(Clone)]
 pub struct LambdaServer<L = Identity> {
     service_builder: ServiceBuilder<L>,
+    options: ServerOptions,
+}
+
+/// Configuration applied by the server around the user supplied layers, carried from the
+/// [`LambdaServer`] builder through to the [`LambdaRouter`].
+#[derive(Clone, Default)]
+struct ServerOptions {
+    init: Vec<LifecycleHook>,
+    #[cfg(feature = "graceful-shutdown")]
+    shutdown: GracefulShutdown,
+    #[cfg(feature = "catch-panic")]
+    panic_handler: PanicHandler,
+    #[cfg(feature = "deadline")]
+    deadline: DeadlinePolicy,
+    #[cfg(feature = "cors")]
+    cors: Option<CorsConfig>,
+    #[cfg(feature = "connect")]
+    connect: Option<ConnectConfig>,
+    #[cfg(feature = "transcoding")]
+    transcoding: Option<TranscodingConfig>,
+    #[cfg(feature = "health")]
+    health: Option<HealthConfig>,
+    #[cfg(feature = "metrics-emf")]
+    metrics: Option<EmfConfig>,
+    #[cfg(feature = "tracing")]
+    rpc_span_disabled: bool,
+    #[cfg(feature = "auth-jwt")]
+    jwt_auth: Option<JwtAuthConfig>,
+    #[cfg(feature = "auth-jwt")]
+    authorization: Option<AuthorizationPolicy>,
+    #[cfg(any(feature = "local", feature = "testing"))]
+    local_timeout: Option<Duration>,
 }
 
 impl LambdaServer {
     pub fn builder() -> Self {
         Self {
             service_builder: ServiceBuilder::new(),
+            options: ServerOptions::default(),
         }
     }
 }
@@ -35,15 +107,169 @@ impl LambdaServer {
 pub struct LambdaRouter<L> {
     routes: Routes,
     service_builder: ServiceBuilder<L>,
+    options: ServerOptions,
+    #[cfg(feature = "reflection")]
+    registry: Option<ServiceRegistry>,
+    /// Names of the services added, to validate an [`AuthorizationPolicy`] against and to record
+    /// metrics of
+    #[cfg(any(feature = "auth-jwt", feature = "metrics-emf"))]
+    services: Vec<&'static str>,
 }
 
 impl<L> LambdaServer<L> {
     pub fn layer<NewLayer>(self, new_layer: NewLayer) -> LambdaServer<Stack<NewLayer, L>> {
         LambdaServer {
             service_builder: self.service_builder.layer(new_layer),
+            options: self.options,
         }
     }
 
+    /// Configure how panics in handlers are mapped to the `Status` returned to the client. Defaults
+    /// to [`PanicHandler::message`], which exposes the panic message.
+    #[cfg(feature = "catch-panic")]
+    pub fn panic_handler(mut self, handler: PanicHandler) -> Self {
+        self.options.panic_handler = handler;
+        self
+    }
+
+    /// Configure how requests are terminated ahead of the lambda invocation deadline. Defaults to
+    /// [`DeadlinePolicy::default`], a fixed 500ms margin.
+    #[cfg(feature = "deadline")]
+    pub fn deadline(mut self, policy: DeadlinePolicy) -> Self {
+        self.options.deadline = policy;
+        self
+    }
+
+    /// Answer CORS preflights and add CORS headers to responses for browser clients. Preflights
+    /// are answered before requests reach the grpc-web layer and routes.
+    ///
+    /// Panics if the configuration allows credentials for any origin.
+    #[cfg(feature = "cors")]
+    pub fn cors(mut self, config: CorsConfig) -> Self {
+        config.validate();
+        self.options.cors = Some(config);
+        self
+    }
+
+    /// Also serve clients speaking the Connect protocol, such as Connect-ES. Connect requests are
+    /// recognised by their content type and translated to grpc-web, other requests are unaffected.
+    #[cfg(feature = "connect")]
+    pub fn connect(mut self, config: ConnectConfig) -> Self {
+        self.options.connect = Some(config);
+        self
+    }
+
+    /// Also serve plain REST clients, transcoding JSON requests matching the `google.api.http`
+    /// rules of the services to gRPC, and the responses back to JSON. Requests not matching a rule
+    /// are unaffected.
+    #[cfg(feature = "transcoding")]
+    pub fn transcoding(mut self, config: TranscodingConfig) -> Self {
+        self.options.transcoding = Some(config);
+        self
+    }
+
+    /// Serve `grpc.health.v1.Health`, reporting every service passed to `add_service`. Take a
+    /// [`HealthConfig::reporter`] before passing the config to change the reported statuses.
+    ///
+    /// The routes conflict with any other `grpc.health.v1.Health` service, so don't add one too.
+    #[cfg(feature = "health")]
+    pub fn health(mut self, config: HealthConfig) -> Self {
+        self.options.health = Some(config);
+        self
+    }
+
+    /// Write CloudWatch Embedded Metric Format metrics of every call to stdout once its trailers
+    /// are sent, for CloudWatch to extract from the function logs.
+    #[cfg(feature = "metrics-emf")]
+    pub fn metrics(mut self, config: EmfConfig) -> Self {
+        self.options.metrics = Some(config);
+        self
+    }
+
+    /// Don't open a `grpc` span around every call, nor read the [`crate::TraceContext`] of calls,
+    /// i.e. when the calls are traced by a layer of your own.
+    #[cfg(feature = "tracing")]
+    pub fn disable_rpc_span(mut self) -> Self {
+        self.options.rpc_span_disabled = true;
+        self
+    }
+
+    /// Require a valid bearer token in the `authorization` metadata of every request, except the
+    /// paths allowed by [`JwtAuthConfig::allow_unauthenticated`]. The verified claims are inserted
+    /// into the request extensions as [`crate::JwtClaims`].
+    ///
+    /// Authentication runs inside the deadline, ahead of the user supplied layers.
+    #[cfg(feature = "auth-jwt")]
+    pub fn jwt_auth(mut self, config: JwtAuthConfig) -> Self {
+        self.options.jwt_auth = Some(config);
+        self
+    }
+
+    /// Run the hook once per function instance, before the runtime polls for the first event, i.e.
+    /// to build database pools or load configuration. Hooks run in the order added, and the time
+    /// they took is logged.
+    ///
+    /// A failing hook fails the init of the function, reported to the Runtime API so the error
+    /// shows in the function logs. `serve_local` runs the hooks too, but the testing harness
+    /// doesn't.
+    pub fn on_init<F, Fut, E>(mut self, hook: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Into<Error>,
+    {
+        self.options.init.push(lifecycle::hook(hook));
+        self
+    }
+
+    /// Run the hook when Lambda shuts the function instance down, i.e. to flush telemetry or close
+    /// pools. Adding a hook registers an internal extension with the Extensions API, without which
+    /// Lambda doesn't signal the runtime before reclaiming the execution environment. Hooks run in
+    /// the order added within [`LambdaServer::shutdown_timeout`], and failures are logged.
+    ///
+    /// Only `serve` and `serve_buffered` shut down gracefully.
+    #[cfg(feature = "graceful-shutdown")]
+    pub fn on_shutdown<F, Fut, E>(mut self, hook: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Into<Error>,
+    {
+        self.options.shutdown.push(lifecycle::hook(hook));
+        self
+    }
+
+    /// Time the shutdown hooks get in total, after which they are abandoned. Defaults to 500ms,
+    /// what Lambda allows once an internal extension is registered. Lambda allows 2s when an
+    /// external extension is registered too.
+    #[cfg(feature = "graceful-shutdown")]
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.options.shutdown.timeout(timeout);
+        self
+    }
+
+    /// Shut down once the future returned by `signal` resolves, rather than on the `SIGTERM` from
+    /// Lambda, i.e. for tests standing in for Lambda shutting the function instance down. The
+    /// extension is registered all the same. Called on every `serve`.
+    #[cfg(feature = "graceful-shutdown")]
+    pub fn shutdown_signal<F, Fut>(mut self, signal: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.options.shutdown.signal(signal);
+        self
+    }
+
+    /// Timeout of the synthetic lambda invocation each request runs in when served with
+    /// `LambdaRouter::serve_local` or the `testing` harness. Defaults to 3s, lambda's default
+    /// function timeout.
+    #[cfg(any(feature = "local", feature = "testing"))]
+    pub fn local_timeout(mut self, timeout: Duration) -> Self {
+        self.options.local_timeout = Some(timeout);
+        self
+    }
+
     pub fn add_service<S>(self, svc: S) -> LambdaRouter<L>
     where
         S: Service<Request<Body>, Error = Infallible>
@@ -56,9 +282,19 @@ impl<L> LambdaServer<L> {
         S::Future: Send + 'static,
         L: Clone,
     {
+        #[cfg(feature = "health")]
+        if let Some(health) = &self.options.health {
+            health.register(S::NAME);
+        }
+
         LambdaRouter {
             routes: Routes::new(svc),
             service_builder: self.service_builder,
+            options: self.options,
+            #[cfg(feature = "reflection")]
+            registry: None,
+            #[cfg(any(feature = "auth-jwt", feature = "metrics-emf"))]
+            services: vec![S::NAME],
         }
     }
 }
@@ -75,10 +311,118 @@ impl<L> LambdaRouter<L> {
         S::Response: axum::response::IntoResponse,
         S::Future: Send + 'static,
     {
+        #[cfg(feature = "health")]
+        if let Some(health) = &self.options.health {
+            health.register(S::NAME);
+        }
+
+        #[cfg(any(feature = "auth-jwt", feature = "metrics-emf"))]
+        self.services.push(S::NAME);
+
         self.routes = self.routes.add_service(svc);
         self
     }
 
+    /// Authorize each request by the policy of its method, after authentication by
+    /// [`LambdaServer::jwt_auth`]. Requests without a token are let through the authentication
+    /// to methods allowing anonymous callers.
+    ///
+    /// Serving fails with a [`PolicyError`](crate::PolicyError) if the policy names a service that
+    /// isn't served, or a method missing from the descriptors passed to
+    /// [`LambdaRouter::with_reflection`]. Without reflection the methods served aren't known, so
+    /// policies can only name whole services.
+    #[cfg(feature = "auth-jwt")]
+    pub fn authorize(mut self, policy: AuthorizationPolicy) -> Self {
+        self.options.authorization = Some(policy);
+        self
+    }
+
+    /// The services served, including the built in ones, and the paths of the methods served when
+    /// known from the descriptors passed to [`LambdaRouter::with_reflection`]
+    #[cfg(any(feature = "auth-jwt", feature = "metrics-emf"))]
+    fn served(&self) -> (Vec<&str>, Option<HashSet<String>>) {
+        let builtin: &[Option<(&str, &[&str])>] = &[
+            #[cfg(feature = "health")]
+            self.options
+                .health
+                .as_ref()
+                .map(|_| ("grpc.health.v1.Health", &crate::health::METHODS[..])),
+            #[cfg(feature = "reflection")]
+            self.registry.as_ref().map(|_| {
+                (
+                    "grpc.reflection.v1.ServerReflection",
+                    &[crate::reflection::SERVER_REFLECTION_INFO][..],
+                )
+            }),
+        ];
+        let builtin = builtin.iter().flatten();
+
+        let services = self
+            .services
+            .iter()
+            .copied()
+            .chain(builtin.clone().map(|(service, _)| *service))
+            .collect();
+
+        #[cfg(feature = "reflection")]
+        let methods = self.registry.as_ref().map(|registry| {
+            registry
+                .methods()
+                .map(|(path, _)| path)
+                .chain(builtin.flat_map(|(_, methods)| methods.iter().copied()))
+                .map(String::from)
+                .collect()
+        });
+        #[cfg(not(feature = "reflection"))]
+        let methods = None;
+
+        (services, methods)
+    }
+
+    /// Checks the configuration that can only be validated once the router is complete
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        #[cfg(feature = "auth-jwt")]
+        if let Some(policy) = &self.options.authorization {
+            let (services, methods) = self.served();
+            policy.validate(&services, methods.as_ref())?;
+        }
+        Ok(())
+    }
+
+    /// Validates the router and runs the init hooks, reporting a failure of either to the
+    /// Runtime API
+    async fn init_runtime(&self) -> Result<(), Error> {
+        if let Err(err) = self.validate() {
+            return Err(lifecycle::fail_init(err).await);
+        }
+        lifecycle::init_runtime(&self.options.init).await
+    }
+
+    /// Serve `grpc.reflection.v1` from the encoded `FileDescriptorSet` the services were generated
+    /// from, as emitted by `tonic_prost_build::configure().file_descriptor_set_path(..)`, so tools
+    /// such as grpcurl and Postman can list them. The registry of the described methods is then
+    /// available from [`LambdaRouter::registry`].
+    ///
+    /// grpc-web has no client streaming, so each reflection request is answered once the request
+    /// body has arrived in full.
+    #[cfg(feature = "reflection")]
+    pub fn with_reflection(mut self, file_descriptor_set: &[u8]) -> Result<Self, DescriptorError> {
+        let registry = ServiceRegistry::new(file_descriptor_set)?;
+        self.routes = self
+            .routes
+            .add_service(ReflectionService::new(registry.clone()));
+        self.registry = Some(registry);
+        Ok(self)
+    }
+
+    /// The services and methods described to [`LambdaRouter::with_reflection`]
+    #[cfg(feature = "reflection")]
+    pub fn registry(&self) -> Option<&ServiceRegistry> {
+        self.registry.as_ref()
+    }
+
+    /// Serve with the lambda `RESPONSE_STREAM` invoke mode, required for server streaming. This
+    /// is the mode to use with Function URLs.
     pub async fn serve(self) -> Result<(), Error>
     where
         L: Layer<Routes>,
@@ -91,46 +435,224 @@ impl<L> LambdaRouter<L> {
             + Send
             + 'static,
     {
-        let service_builder = ServiceBuilder::new();
+        self.init_runtime().await?;
+        #[cfg(feature = "graceful-shutdown")]
+        let shutdown = self.options.shutdown.clone();
+        let svc = self.into_service();
+
+        let handler = tower::service_fn(move |req: lambda_http::Request| {
+            let mut svc = svc.clone();
+            async move {
+                let res = svc.call(into_grpc_request(req)).await.expect("infallible");
+                let (parts, body) = res.into_parts();
+                let body =
+                    lambda_runtime::streaming::Body::new(body);
+                Ok::<_, Error>(Response::from_parts(parts, body))
+            }
+        });
+
+        let runtime = lambda_http::run_with_streaming_response(handler);
+        #[cfg(feature = "graceful-shutdown")]
+        let runtime = shutdown.run(runtime);
+        runtime.await
+    }
+
+    /// Serve with the lambda `BUFFERED` invoke mode, as required behind API Gateway REST & HTTP
+    /// APIs and ALBs. The full grpc-web response, including trailers, is collected before
+    /// returning, so this suits unary calls only. Responses exceeding the 6MB lambda payload limit
+    /// are replaced with `RESOURCE_EXHAUSTED`.
+    ///
+    /// Binary grpc-web responses are base64 encoded by the runtime, so API Gateway must be
+    /// configured to treat `application/grpc-web*` as binary media types.
+    pub async fn serve_buffered(self) -> Result<(), Error>
+    where
+        L: Layer<Routes>,
+        L::Service: Service<
+                GrpcRequest,
+                Response = GrpcResponse,
+                Error = Infallible,
+                Future: Send + 'static,
+            > + Clone
+            + Send
+            + 'static,
+    {
+        self.init_runtime().await?;
+        #[cfg(feature = "graceful-shutdown")]
+        let shutdown = self.options.shutdown.clone();
+        let svc = self.into_service();
+
+        let handler = tower::service_fn(move |req: lambda_http::Request| {
+            let mut svc = svc.clone();
+            async move {
+                let res = svc.call(into_grpc_request(req)).await.expect("infallible");
+                Ok::<_, Error>(buffer_response(res).await)
+            }
+        });
+
+        let runtime = lambda_http::run(handler);
+        #[cfg(feature = "graceful-shutdown")]
+        let runtime = shutdown.run(runtime);
+        runtime.await
+    }
+
+    /// Serve on a standalone HTTP/1.1 listener for local development, without `cargo lambda`.
+    /// Each request runs with a synthetic lambda `Context`, see [`LambdaServer::local_timeout`].
+    ///
+    /// Only the lambda `Context` is emulated, request extensions added by `lambda_http` from the
+    /// invocation event are not available.
+    #[cfg(feature = "local")]
+    pub async fn serve_local(self, addr: impl ToSocketAddrs) -> Result<(), Error>
+    where
+        L: Layer<Routes>,
+        L::Service: Service<
+                GrpcRequest,
+                Response = GrpcResponse,
+                Error = Infallible,
+                Future: Send + 'static,
+            > + Clone
+            + Send
+            + 'static,
+    {
+        let listener = TcpListener::bind(addr).await?;
+        self.serve_local_with_listener(listener).await
+    }
+
+    /// As [`LambdaRouter::serve_local`], with an already bound listener
+    #[cfg(feature = "local")]
+    pub async fn serve_local_with_listener(self, listener: TcpListener) -> Result<(), Error>
+    where
+        L: Layer<Routes>,
+        L::Service: Service<
+                GrpcRequest,
+                Response = GrpcResponse,
+                Error = Infallible,
+                Future: Send + 'static,
+            > + Clone
+            + Send
+            + 'static,
+    {
+        self.validate()?;
+        lifecycle::run_init(&self.options.init).await?;
+        let timeout = self.synthetic_timeout();
+        local::serve(self.into_service(), listener, timeout).await
+    }
+
+    #[cfg(any(feature = "local", feature = "testing"))]
+    pub(crate) fn synthetic_timeout(&self) -> Duration {
+        self.options
+            .local_timeout
+            .unwrap_or(synthetic::DEFAULT_TIMEOUT)
+    }
+
+    /// Composes the user layers and routes with the layers this crate applies around them
+    pub(crate) fn into_service(self) -> LambdaService
+    where
+        L: Layer<Routes>,
+        L::Service: Service<
+                GrpcRequest,
+                Response = GrpcResponse,
+                Error = Infallible,
+                Future: Send + 'static,
+            > + Clone
+            + Send
+            + 'static,
+    {
+        let cold_start = ColdStart::new();
+        let service_builder = ServiceBuilder::new()
+            .map_response(into_grpc_response)
+            .map_request(move |req: GrpcRequest| cold_start.mark(req));
 
         #[cfg(feature = "wire-log")]
         let service_builder = service_builder.layer(WireLogLayer);
 
-        let service_builder = service_builder.layer(GrpcWebLayer::new());
+        #[cfg(feature = "cors")]
+        let service_builder =
+            service_builder.option_layer(self.options.cors.as_ref().map(CorsConfig::layer));
+
+        #[cfg(feature = "connect")]
+        let service_builder =
+            service_builder.option_layer(self.options.connect.as_ref().map(ConnectConfig::layer));
+
+        #[cfg(feature = "transcoding")]
+        let service_builder = service_builder.option_layer(
+            self.options
+                .transcoding
+                .as_ref()
+                .map(TranscodingConfig::layer),
+        );
+
+        let service_builder = service_builder
+            .map_request(negotiate_response_encoding)
+            .layer(GrpcWebLayer::new());
+
+        #[cfg(feature = "metrics-emf")]
+        let service_builder = {
+            let (services, methods) = self.served();
+            service_builder.option_layer(
+                self.options
+                    .metrics
+                    .as_ref()
+                    .map(|config| config.layer(&services, methods)),
+            )
+        };
+
+        #[cfg(feature = "tracing")]
+        let service_builder = service_builder
+            .option_layer((!self.options.rpc_span_disabled).then_some(RpcSpanLayer))
+            .map_response(into_grpc_response);
 
         #[cfg(feature = "catch-panic")]
-        let service_builder = service_builder.layer(CatchPanicLayer::custom(
-            |err: Box<dyn Any + Send + 'static>| {
-                let details = if let Some(s) = err.downcast_ref::<String>() {
-                    s.clone()
-                } else if let Some(s) = err.downcast_ref::<&str>() {
-                    s.to_string()
-                } else {
-                    "Unknown panic message".to_string()
-                };
-
-                Status::internal(details).into_http::<Body>()
-            },
-        ));
+        let service_builder =
+            service_builder.layer(CatchPanicLayer::new(self.options.panic_handler));
 
         #[cfg(feature = "deadline")]
         let service_builder =
-            service_builder.layer(LambdaDeadlineLayer::new(Duration::from_millis(500)));
+            service_builder.layer(LambdaDeadlineLayer::new(self.options.deadline));
 
-        let svc = service_builder.service(self.service_builder.service(self.routes));
+        #[cfg(feature = "auth-jwt")]
+        let service_builder = {
+            let authorization = self.options.authorization.as_ref();
+            service_builder
+                .option_layer(self.options.jwt_auth.as_ref().map(|config| {
+                    match authorization {
+                        Some(_) => config.layer().token_optional(),
+                        None => config.layer(),
+                    }
+                }))
+                .option_layer(authorization.map(AuthorizationPolicy::layer))
+        };
 
-        let handler = tower::service_fn(move |req: lambda_http::Request| {
-            let mut svc = svc.clone();
-            async move {
-                let req = req.map(|body| Body::new(tonic::service::AxumBody::new(body)));
-                let res = svc.call(req).await.expect("infallible");
-                let (parts, body) = res.into_parts();
-                let body =
-                    lambda_runtime::streaming::Body::new(body);
-                Ok::<_, Error>(Response::from_parts(parts, body))
-            }
-        });
+        #[cfg(feature = "health")]
+        let routes = match &self.options.health {
+            Some(health) => self.routes.add_service(health.service()),
+            None => self.routes,
+        };
+        #[cfg(not(feature = "health"))]
+        let routes = self.routes;
 
-        lambda_http::run_with_streaming_response(handler).await
+        let svc = service_builder.service(self.service_builder.service(routes));
+
+        BoxCloneService::new(svc)
     }
 }
+
+pub(crate) fn into_grpc_request(req: lambda_http::Request) -> GrpcRequest {
+    #[cfg(feature = "request-info")]
+    let req = {
+        let mut req = req;
+        if let Some(info) = LambdaRequestInfo::from_request(&req) {
+            req.extensions_mut().insert(info);
+        }
+        req
+    };
+
+    req.map(|body| Body::new(tonic::service::AxumBody::new(body)))
+}
+
+fn into_grpc_response<B>(res: Response<B>) -> GrpcResponse
+where
+    B: http_body::Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    res.map(Body::new)
+}
@@ -1,9 +1,85 @@
+#[cfg(feature = "auth-jwt")]
+mod auth_jwt;
+#[cfg(feature = "auth-jwt")]
+mod auth_policy;
+mod buffered;
+#[cfg(feature = "catch-panic")]
+mod catch_panic;
+#[cfg(feature = "connect")]
+mod connect;
+#[cfg(feature = "cors")]
+mod cors;
+#[cfg(feature = "deadline")]
 mod deadline_layer;
+#[cfg(any(feature = "testing", feature = "client"))]
+mod function_url;
+#[cfg(any(feature = "tracing", feature = "metrics-emf", feature = "auth-jwt"))]
+mod grpc_call;
+mod grpc_web;
+#[cfg(feature = "health")]
+mod health;
 mod lambda_server_builder;
-
+mod lifecycle;
+#[cfg(feature = "local")]
+mod local;
+#[cfg(feature = "metrics-emf")]
+mod metrics_emf;
+#[cfg(feature = "reflection")]
+mod reflection;
+#[cfg(feature = "request-info")]
+mod request_info;
+#[cfg(feature = "tracing")]
+mod rpc_span;
+#[cfg(feature = "graceful-shutdown")]
+mod shutdown;
+#[cfg(any(feature = "local", feature = "testing"))]
+mod synthetic;
+#[cfg(feature = "tracing")]
+mod trace_context;
+#[cfg(feature = "transcoding")]
+mod transcoding;
+#[cfg(any(feature = "connect", feature = "transcoding"))]
+mod translate;
 
 pub use lambda_runtime;
-pub use lambda_server_builder::LambdaServer;
+pub use lambda_server_builder::{LambdaRouter, LambdaServer};
+pub use lifecycle::IsColdStart;
+
+#[cfg(feature = "auth-jwt")]
+pub use auth_jwt::{JwtAuthConfig, JwtClaims};
+#[cfg(feature = "auth-jwt")]
+pub use auth_policy::{AuthorizationPolicy, MethodPolicy, PolicyError};
+#[cfg(feature = "catch-panic")]
+pub use catch_panic::{PanicDetails, PanicHandler};
+#[cfg(feature = "connect")]
+pub use connect::ConnectConfig;
+#[cfg(feature = "cors")]
+pub use cors::{CorsConfig, CorsConflict, FunctionUrlCors};
+#[cfg(feature = "deadline")]
+pub use deadline_layer::{DeadlineExceeded, DeadlinePolicy, DeadlineSource, LambdaDeadline};
+#[cfg(feature = "health")]
+pub use health::{HealthConfig, HealthReporter, ServingStatus};
+#[cfg(feature = "metrics-emf")]
+pub use metrics_emf::{EmfConfig, EmfDimension};
+#[cfg(feature = "reflection")]
+pub use reflection::{MethodKind, ServiceRegistry};
+#[cfg(feature = "request-info")]
+pub use request_info::{EventSource, IamIdentity, LambdaRequestExt, LambdaRequestInfo};
+#[cfg(feature = "tracing")]
+pub use trace_context::{TraceContext, TraceContextLayer, TraceContextService};
+#[cfg(feature = "transcoding")]
+pub use transcoding::{TranscodingConfig, TranscodingError};
+
+/// Route key of the events `InvokeChannel` sends through the Lambda `Invoke` API. No Function URL
+/// or API Gateway route uses it, so the function can tell direct invocations apart.
+#[cfg(any(feature = "client", feature = "request-info"))]
+const INVOKE_ROUTE_KEY: &str = "$invoke";
+
+#[cfg(feature = "client")]
+pub mod client;
+
+#[cfg(feature = "testing")]
+pub mod testing;
 
 #[cfg(feature = "wire-log")]
 mod wire_log;
@@ -0,0 +1,181 @@
+//! The lifecycle of the function instance: init hooks run once ahead of the first invocation, the
+//! cold start marker of the first request, and the hooks run on shutdown with the
+//! `graceful-shutdown` feature.
+
+use http::header::CONTENT_TYPE;
+use http::{Method, Request};
+use lambda_http::tracing::{info, warn};
+use lambda_runtime::Error;
+use lambda_runtime_api_client::body::Body;
+use lambda_runtime_api_client::{Client, build_request};
+use std::fmt::Write;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+const INIT_ERROR_PATH: &str = "/2018-06-01/runtime/init/error";
+const INIT_ERROR_TYPE: &str = "Runtime.InitError";
+
+pub(crate) type LifecycleHook =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send>> + Send + Sync>;
+
+pub(crate) fn hook<F, Fut, E>(hook: F) -> LifecycleHook
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), E>> + Send + 'static,
+    E: Into<Error>,
+{
+    Arc::new(move || {
+        let fut = hook();
+        Box::pin(async move { fut.await.map_err(Into::into) })
+    })
+}
+
+/// Inserted into the request extensions of the first request served by the function instance,
+/// the one that waited on the cold start. Retrieve it from a `tonic::Request` with
+/// `request.extensions().get::<IsColdStart>()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IsColdStart;
+
+/// Marks the first request it sees with [`IsColdStart`]
+#[derive(Clone)]
+pub(crate) struct ColdStart(Arc<AtomicBool>);
+
+impl ColdStart {
+    pub(crate) fn new() -> Self {
+        Self(Arc::new(AtomicBool::new(true)))
+    }
+
+    pub(crate) fn mark<B>(&self, mut req: Request<B>) -> Request<B> {
+        if self.0.swap(false, Ordering::Relaxed) {
+            req.extensions_mut().insert(IsColdStart);
+        }
+        req
+    }
+}
+
+/// Runs the hooks in order, stopping at the first to fail
+pub(crate) async fn run_init(hooks: &[LifecycleHook]) -> Result<(), Error> {
+    if hooks.is_empty() {
+        return Ok(());
+    }
+
+    let start = Instant::now();
+    for hook in hooks {
+        hook().await?;
+    }
+
+    info!(
+        duration_ms = start.elapsed().as_millis() as u64,
+        "init hooks completed"
+    );
+    Ok(())
+}
+
+/// Runs the hooks ahead of the lambda runtime, reporting a failure to the Runtime API so the
+/// init error shows in the function logs and metrics
+pub(crate) async fn init_runtime(hooks: &[LifecycleHook]) -> Result<(), Error> {
+    let Err(err) = run_init(hooks).await else {
+        return Ok(());
+    };
+
+    Err(fail_init(err).await)
+}
+
+/// Reports the error to the Runtime API as the init error of the function, returning it
+pub(crate) async fn fail_init(err: Error) -> Error {
+    if let Err(report) = report_init_error(&err).await {
+        warn!(error = %report, "failed to report init error to the runtime api");
+    }
+    err
+}
+
+async fn report_init_error(err: &Error) -> Result<(), Error> {
+    let client = Client::builder().build()?;
+
+    let mut body = String::from("{\"errorType\":");
+    json_string(&mut body, INIT_ERROR_TYPE);
+    body.push_str(",\"errorMessage\":");
+    json_string(&mut body, &err.to_string());
+    body.push('}');
+
+    let req = build_request()
+        .method(Method::POST)
+        .uri(INIT_ERROR_PATH)
+        .header("lambda-runtime-function-error-type", INIT_ERROR_TYPE)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))?;
+
+    let res = client.call(req).await?;
+    if !res.status().is_success() {
+        return Err(format!("runtime api responded {}", res.status()).into());
+    }
+    Ok(())
+}
+
+/// Appends the value as a JSON string
+fn json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn marks_only_the_first_request() {
+        let cold_start = ColdStart::new();
+
+        let first = cold_start.clone().mark(Request::new(()));
+        let second = cold_start.mark(Request::new(()));
+
+        assert_eq!(first.extensions().get::<IsColdStart>(), Some(&IsColdStart));
+        assert_eq!(second.extensions().get::<IsColdStart>(), None);
+    }
+
+    #[tokio::test]
+    async fn runs_hooks_in_order_until_one_fails() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let hook = |name: &'static str, ok: bool| -> LifecycleHook {
+            let calls = calls.clone();
+            Arc::new(move || {
+                calls.lock().unwrap().push(name);
+                Box::pin(async move { if ok { Ok(()) } else { Err(name.into()) } })
+            })
+        };
+
+        let hooks = [
+            hook("config", true),
+            hook("pool", false),
+            hook("cache", true),
+        ];
+        let err = run_init(&hooks).await.unwrap_err();
+
+        assert_eq!(err.to_string(), "pool");
+        assert_eq!(*calls.lock().unwrap(), ["config", "pool"]);
+    }
+
+    #[test]
+    fn escapes_json_strings() {
+        let mut out = String::new();
+        json_string(&mut out, "missing \"db\\url\"\n\u{1}");
+        assert_eq!(out, r#""missing \"db\\url\"\n\u0001""#);
+    }
+}
@@ -0,0 +1,58 @@
+//! Standalone HTTP/1.1 server for local development, serving the same layered stack as the lambda
+//! handlers without needing `cargo lambda`. A synthetic lambda `Context` is inserted into every
+//! request so handlers and layers relying on it behave the same as when deployed.
+
+use crate::lambda_server_builder::LambdaService;
+use crate::synthetic;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper_util::rt::TokioIo;
+use lambda_http::tracing::log::{info, warn};
+use lambda_runtime::Error;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tonic::body::Body;
+use tower::Service;
+
+/// Pause after a failed accept before the next, rather than spinning on a persistent error
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+
+pub(crate) async fn serve(
+    svc: LambdaService,
+    listener: TcpListener,
+    timeout: Duration,
+) -> Result<(), Error> {
+    info!("Serving locally on http://{}", listener.local_addr()?);
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            // i.e. out of file descriptors or the connection reset while queued, neither of which
+            // should stop the server
+            Err(err) => {
+                warn!("Error accepting local connection: {err:?}");
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        let svc = svc.clone();
+
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |req: http::Request<Incoming>| {
+                let mut svc = svc.clone();
+                async move {
+                    let mut req = req.map(Body::new);
+                    req.extensions_mut().insert(synthetic::context(timeout));
+                    svc.call(req).await
+                }
+            });
+
+            if let Err(err) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                warn!("Error serving local connection from {peer}: {err:?}");
+            }
+        });
+    }
+}
@@ -0,0 +1,566 @@
+//! Per-call metrics written as CloudWatch Embedded Metric Format log lines, which CloudWatch
+//! extracts into metrics without an agent.
+
+use crate::grpc_call::{CallObserver, ObservedBody, service_method};
+use crate::lifecycle::IsColdStart;
+use bytes::{Buf, Bytes};
+use http::{Request, Response};
+use http_body::Body as HttpBody;
+use lambda_runtime::Context as LambdaContext;
+use serde_json::{Map, Value, json};
+use std::collections::HashSet;
+use std::fmt;
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tonic::Code;
+use tonic::body::Body;
+use tower::{BoxError, Layer, Service};
+
+type Writer = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// `Service` and `Method` of calls to routes that aren't served, so unknown paths can't add metrics
+const UNKNOWN: &str = "Unknown";
+
+/// Metrics of each call, with their units
+const METRICS: [(&str, &str); 5] = [
+    ("Requests", "Count"),
+    ("Latency", "Milliseconds"),
+    ("ResponseBytes", "Bytes"),
+    ("ResponseMessages", "Count"),
+    ("ColdStart", "Count"),
+];
+
+/// Dimension of a call's metrics, taking its value from the call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmfDimension {
+    /// The gRPC service, i.e. `package.Service`
+    Service,
+    /// The method name, without the service
+    Method,
+    /// Name of the final status code, i.e. `OK` or `NOT_FOUND`
+    StatusCode,
+}
+
+impl EmfDimension {
+    fn name(self) -> &'static str {
+        match self {
+            EmfDimension::Service => "Service",
+            EmfDimension::Method => "Method",
+            EmfDimension::StatusCode => "StatusCode",
+        }
+    }
+}
+
+/// Writes a line of CloudWatch Embedded Metric Format JSON for every call once its trailers are
+/// sent, to stdout unless changed with [`EmfConfig::writer`]. Each line records the metrics
+///
+/// * `Requests`, always 1
+/// * `Latency`, in milliseconds until the trailers are sent
+/// * `ResponseBytes` and `ResponseMessages`, of the gRPC encoded response messages
+/// * `ColdStart`, 1 on the call marked [`IsColdStart`](crate::IsColdStart)
+///
+/// under the dimension sets, by default `[Service, Method]` and `[Service, Method, StatusCode]`.
+/// Calls to services that aren't served are recorded with `Unknown` as their `Service` and
+/// `Method`, as are calls to methods that aren't served, known from the descriptors passed to
+/// `LambdaRouter::with_reflection`. Without them, calls failing with `UNIMPLEMENTED` are recorded
+/// with an `Unknown` method. The lambda request id is included as the `requestId` property, for finding the call in the
+/// logs from the metric.
+#[derive(Clone)]
+pub struct EmfConfig {
+    namespace: String,
+    dimension_sets: Vec<Vec<EmfDimension>>,
+    dimensions: Vec<(String, String)>,
+    writer: Writer,
+}
+
+impl EmfConfig {
+    pub fn new(namespace: impl Into<String>) -> Self {
+        Self {
+            namespace: namespace.into(),
+            dimension_sets: vec![
+                vec![EmfDimension::Service, EmfDimension::Method],
+                vec![
+                    EmfDimension::Service,
+                    EmfDimension::Method,
+                    EmfDimension::StatusCode,
+                ],
+            ],
+            dimensions: Vec::new(),
+            writer: Arc::new(|line| {
+                let _ = writeln!(std::io::stdout().lock(), "{line}");
+            }),
+        }
+    }
+
+    /// Replace the default dimension sets. Every set is recorded as a separate metric, so keep
+    /// them few, and leave `StatusCode` out of sets where the breakdown isn't needed.
+    pub fn dimension_sets<I>(mut self, sets: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: IntoIterator<Item = EmfDimension>,
+    {
+        self.dimension_sets = sets
+            .into_iter()
+            .map(|set| set.into_iter().collect())
+            .collect();
+        self
+    }
+
+    /// Add a dimension of fixed value to every dimension set, i.e. the deployment environment
+    pub fn dimension(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.dimensions.push((name.into(), value.into()));
+        self
+    }
+
+    /// Write the lines with the function instead, i.e. to capture them in tests
+    pub fn writer<F>(mut self, writer: F) -> Self
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        self.writer = Arc::new(writer);
+        self
+    }
+
+    /// The layer recording calls to the services, and the method paths when known
+    pub(crate) fn layer(&self, services: &[&str], methods: Option<HashSet<String>>) -> EmfLayer {
+        EmfLayer {
+            config: Arc::new(self.clone()),
+            served: Arc::new(Served {
+                services: services.iter().map(|service| service.to_string()).collect(),
+                methods,
+            }),
+        }
+    }
+
+    fn line(&self, call: &CallMetrics, status: Code) -> String {
+        let dimensions: Vec<Vec<&str>> = self
+            .dimension_sets
+            .iter()
+            .map(|set| {
+                self.dimensions
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .chain(set.iter().map(|dimension| dimension.name()))
+                    .collect()
+            })
+            .collect();
+        let metrics: Vec<Value> = METRICS
+            .iter()
+            .map(|(name, unit)| json!({ "Name": name, "Unit": unit }))
+            .collect();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+
+        let mut line = Map::new();
+        line.insert(
+            "_aws".to_string(),
+            json!({
+                "Timestamp": timestamp,
+                "CloudWatchMetrics": [{
+                    "Namespace": self.namespace,
+                    "Dimensions": dimensions,
+                    "Metrics": metrics,
+                }],
+            }),
+        );
+        for (name, value) in &self.dimensions {
+            line.insert(name.clone(), value.clone().into());
+        }
+        // unknown methods of a service are answered `UNIMPLEMENTED` by its generated server
+        let method = match status {
+            Code::Unimplemented if !call.method_served => UNKNOWN,
+            _ => call.method.as_str(),
+        };
+        line.insert("Service".to_string(), call.service.clone().into());
+        line.insert("Method".to_string(), method.into());
+        line.insert("StatusCode".to_string(), code_name(status).into());
+        line.insert("Requests".to_string(), 1.into());
+        line.insert(
+            "Latency".to_string(),
+            json!(call.start.elapsed().as_secs_f64() * 1000.0),
+        );
+        line.insert("ResponseBytes".to_string(), call.bytes.into());
+        line.insert("ResponseMessages".to_string(), call.messages.count.into());
+        line.insert("ColdStart".to_string(), u8::from(call.cold_start).into());
+        if let Some(request_id) = &call.request_id {
+            line.insert("requestId".to_string(), request_id.clone().into());
+        }
+
+        Value::Object(line).to_string()
+    }
+}
+
+impl fmt::Debug for EmfConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EmfConfig")
+            .field("namespace", &self.namespace)
+            .field("dimension_sets", &self.dimension_sets)
+            .field("dimensions", &self.dimensions)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The canonical name of the code, as in the gRPC specification
+fn code_name(code: Code) -> &'static str {
+    match code {
+        Code::Ok => "OK",
+        Code::Cancelled => "CANCELLED",
+        Code::Unknown => "UNKNOWN",
+        Code::InvalidArgument => "INVALID_ARGUMENT",
+        Code::DeadlineExceeded => "DEADLINE_EXCEEDED",
+        Code::NotFound => "NOT_FOUND",
+        Code::AlreadyExists => "ALREADY_EXISTS",
+        Code::PermissionDenied => "PERMISSION_DENIED",
+        Code::ResourceExhausted => "RESOURCE_EXHAUSTED",
+        Code::FailedPrecondition => "FAILED_PRECONDITION",
+        Code::Aborted => "ABORTED",
+        Code::OutOfRange => "OUT_OF_RANGE",
+        Code::Unimplemented => "UNIMPLEMENTED",
+        Code::Internal => "INTERNAL",
+        Code::Unavailable => "UNAVAILABLE",
+        Code::DataLoss => "DATA_LOSS",
+        Code::Unauthenticated => "UNAUTHENTICATED",
+    }
+}
+
+/// The routes served, the only ones recorded under their own `Service` and `Method`
+struct Served {
+    services: HashSet<String>,
+    methods: Option<HashSet<String>>,
+}
+
+impl Served {
+    /// The service and method of the call, and whether the method is known to be served
+    fn call<'a>(&self, path: &'a str) -> (&'a str, &'a str, bool) {
+        match service_method(path) {
+            Some((service, method)) if self.services.contains(service) => match &self.methods {
+                Some(methods) if methods.contains(path) => (service, method, true),
+                Some(_) => (service, UNKNOWN, true),
+                None => (service, method, false),
+            },
+            _ => (UNKNOWN, UNKNOWN, true),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct EmfLayer {
+    config: Arc<EmfConfig>,
+    served: Arc<Served>,
+}
+
+impl<S> Layer<S> for EmfLayer {
+    type Service = EmfService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        EmfService {
+            inner,
+            config: self.config.clone(),
+            served: self.served.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct EmfService<S> {
+    inner: S,
+    config: Arc<EmfConfig>,
+    served: Arc<Served>,
+}
+
+impl<S, ResBody> Service<Request<Body>> for EmfService<S>
+where
+    S: Service<Request<Body>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+    ResBody: HttpBody<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let (service, method, method_served) = self.served.call(req.uri().path());
+
+        let call = CallMetrics {
+            service: service.to_string(),
+            method: method.to_string(),
+            method_served,
+            request_id: req
+                .extensions()
+                .get::<LambdaContext>()
+                .map(|ctx| ctx.request_id.clone()),
+            cold_start: req.extensions().get::<IsColdStart>().is_some(),
+            start: Instant::now(),
+            bytes: 0,
+            messages: MessageCounter::default(),
+        };
+        let config = self.config.clone();
+        let fut = self.inner.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?;
+
+            Ok(ObservedBody::wrap(res, EmfObserver { config, call }))
+        })
+    }
+}
+
+struct CallMetrics {
+    service: String,
+    method: String,
+    /// Whether the method is known to be served, rather than only its service
+    method_served: bool,
+    request_id: Option<String>,
+    cold_start: bool,
+    start: Instant,
+    bytes: u64,
+    messages: MessageCounter,
+}
+
+/// Counts the length prefixed gRPC messages of a response, across data frames
+#[derive(Default)]
+struct MessageCounter {
+    header: [u8; 5],
+    header_len: usize,
+    remaining: usize,
+    count: u64,
+}
+
+impl MessageCounter {
+    fn push(&mut self, mut data: &[u8]) {
+        while data.has_remaining() {
+            if self.remaining > 0 {
+                let len = self.remaining.min(data.len());
+                self.remaining -= len;
+                data.advance(len);
+                continue;
+            }
+
+            let len = (self.header.len() - self.header_len).min(data.len());
+            self.header[self.header_len..self.header_len + len].copy_from_slice(&data[..len]);
+            self.header_len += len;
+            data.advance(len);
+
+            if self.header_len == self.header.len() {
+                let [_, length @ ..] = self.header;
+                self.remaining = u32::from_be_bytes(length) as usize;
+                self.header_len = 0;
+                self.count += 1;
+            }
+        }
+    }
+}
+
+/// Writes the metrics of the call once it completes
+struct EmfObserver {
+    config: Arc<EmfConfig>,
+    call: CallMetrics,
+}
+
+impl CallObserver for EmfObserver {
+    fn data(&mut self, data: &Bytes) {
+        self.call.bytes += data.len() as u64;
+        self.call.messages.push(data);
+    }
+
+    fn complete(&mut self, status: Code) {
+        let line = self.config.line(&self.call, status);
+        (self.config.writer)(&line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::ColdStart;
+    use http::{HeaderMap, HeaderValue};
+    use http_body::Frame;
+    use http_body_util::{BodyExt, StreamBody};
+    use std::convert::Infallible;
+    use std::sync::Mutex;
+    use tonic::Status;
+    use tower::ServiceExt;
+
+    type Lines = Arc<Mutex<Vec<Value>>>;
+
+    fn config() -> (EmfConfig, Lines) {
+        let lines = Lines::default();
+        let captured = lines.clone();
+        let config = EmfConfig::new("Test").writer(move |line| {
+            captured
+                .lock()
+                .unwrap()
+                .push(serde_json::from_str(line).unwrap());
+        });
+        (config, lines)
+    }
+
+    fn request(path: &str) -> Request<Body> {
+        let mut ctx = LambdaContext::default();
+        ctx.request_id = "abc-123".to_string();
+
+        let mut req = Request::post(path).body(Body::empty()).unwrap();
+        req.extensions_mut().insert(ctx);
+        req
+    }
+
+    /// Two messages of 3 and 2 bytes, split mid header and mid message, then the trailers
+    fn stream(status: &'static str) -> Response<Body> {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static(status));
+        let frames = [
+            Ok::<_, Status>(Frame::data(Bytes::from_static(b"\x00\x00\x00\x00\x03ab"))),
+            Ok(Frame::data(Bytes::from_static(b"c\x00\x00"))),
+            Ok(Frame::data(Bytes::from_static(b"\x00\x00\x02de"))),
+            Ok(Frame::trailers(trailers)),
+        ];
+        Response::new(Body::new(StreamBody::new(futures_util::stream::iter(
+            frames,
+        ))))
+    }
+
+    fn layer(config: EmfConfig) -> EmfLayer {
+        config.layer(&["test.Service"], None)
+    }
+
+    async fn call<F>(layer: &EmfLayer, cold_start: &ColdStart, respond: F)
+    where
+        F: Fn() -> Response<Body> + Send + 'static,
+    {
+        call_path(layer, cold_start, "/test.Service/Stream", respond).await;
+    }
+
+    async fn call_path<F>(layer: &EmfLayer, cold_start: &ColdStart, path: &str, respond: F)
+    where
+        F: Fn() -> Response<Body> + Send + 'static,
+    {
+        let inner = tower::service_fn(move |_req: Request<Body>| {
+            let res = respond();
+            async move { Ok::<_, Infallible>(res) }
+        });
+        let req = cold_start.mark(request(path));
+        let res = layer.layer(inner).oneshot(req).await.unwrap();
+        res.into_body().collect().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn writes_metrics_once_the_stream_ends() {
+        let (config, lines) = config();
+        let layer = layer(config);
+        let cold_start = ColdStart::new();
+
+        call(&layer, &cold_start, || stream("0")).await;
+        call(&layer, &cold_start, || stream("5")).await;
+
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines.len(), 2);
+
+        let line = &lines[0];
+        let metadata = &line["_aws"]["CloudWatchMetrics"][0];
+        assert_eq!(metadata["Namespace"], "Test");
+        assert_eq!(
+            metadata["Dimensions"],
+            json!([["Service", "Method"], ["Service", "Method", "StatusCode"]])
+        );
+        assert_eq!(metadata["Metrics"].as_array().unwrap().len(), METRICS.len());
+        assert!(line["_aws"]["Timestamp"].as_u64().unwrap() > 0);
+
+        assert_eq!(line["Service"], "test.Service");
+        assert_eq!(line["Method"], "Stream");
+        assert_eq!(line["StatusCode"], "OK");
+        assert_eq!(line["Requests"], 1);
+        assert!(line["Latency"].as_f64().unwrap() >= 0.0);
+        assert_eq!(line["ResponseBytes"], 15);
+        assert_eq!(line["ResponseMessages"], 2);
+        assert_eq!(line["ColdStart"], 1);
+        assert_eq!(line["requestId"], "abc-123");
+
+        assert_eq!(lines[1]["StatusCode"], "NOT_FOUND");
+        assert_eq!(lines[1]["ColdStart"], 0);
+    }
+
+    #[tokio::test]
+    async fn writes_configured_dimensions_and_trailers_only_statuses() {
+        let (config, lines) = config();
+        let layer = layer(
+            config
+                .dimension("Environment", "test")
+                .dimension_sets([[EmfDimension::Service]]),
+        );
+
+        call(&layer, &ColdStart::new(), || {
+            Status::permission_denied("denied").into_http()
+        })
+        .await;
+
+        let lines = lines.lock().unwrap();
+        let line = &lines[0];
+        assert_eq!(
+            line["_aws"]["CloudWatchMetrics"][0]["Dimensions"],
+            json!([["Environment", "Service"]])
+        );
+        assert_eq!(line["Environment"], "test");
+        assert_eq!(line["StatusCode"], "PERMISSION_DENIED");
+        assert_eq!(line["ResponseMessages"], 0);
+    }
+
+    #[tokio::test]
+    async fn records_routes_not_served_as_unknown() {
+        let (config, lines) = config();
+        let described = ["/test.Service/Stream".to_string()].into();
+        let layers = [
+            config.layer(&["test.Service"], Some(described)),
+            config.layer(&["test.Service"], None),
+        ];
+        let cold_start = ColdStart::new();
+        let unimplemented = || Status::unimplemented("").into_http();
+
+        for path in ["/random.Service/Method", "/not-a-method"] {
+            call_path(&layers[0], &cold_start, path, unimplemented).await;
+        }
+        call_path(
+            &layers[0],
+            &cold_start,
+            "/test.Service/Other",
+            unimplemented,
+        )
+        .await;
+        call_path(
+            &layers[1],
+            &cold_start,
+            "/test.Service/Other",
+            unimplemented,
+        )
+        .await;
+        call_path(&layers[1], &cold_start, "/test.Service/Other", || {
+            stream("0")
+        })
+        .await;
+
+        let lines = lines.lock().unwrap();
+        let recorded: Vec<(&Value, &Value)> = lines
+            .iter()
+            .map(|line| (&line["Service"], &line["Method"]))
+            .collect();
+        assert_eq!(
+            recorded,
+            [
+                (&json!("Unknown"), &json!("Unknown")),
+                (&json!("Unknown"), &json!("Unknown")),
+                (&json!("test.Service"), &json!("Unknown")),
+                (&json!("test.Service"), &json!("Unknown")),
+                (&json!("test.Service"), &json!("Other")),
+            ]
+        );
+    }
+}
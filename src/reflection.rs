@@ -0,0 +1,510 @@
+//! `grpc.reflection.v1`, served from the `FileDescriptorSet` the services were generated from, and
+//! the registry of the methods it describes.
+
+use futures_util::StreamExt;
+use http::{Request, Response};
+use prost_reflect::{DescriptorError, DescriptorPool, FileDescriptor};
+use std::collections::{BTreeMap, HashSet};
+use std::convert::Infallible;
+use std::future::{Future, ready};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tonic::body::Body;
+use tonic::server::{Grpc, NamedService};
+use tonic::{Status, Streaming};
+use tonic_prost::ProstCodec;
+use tower::Service;
+
+pub(crate) const SERVER_REFLECTION_INFO: &str =
+    "/grpc.reflection.v1.ServerReflection/ServerReflectionInfo";
+
+/// How a method streams its messages
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MethodKind {
+    Unary,
+    ServerStreaming,
+    ClientStreaming,
+    BidiStreaming,
+}
+
+impl MethodKind {
+    /// Whether grpc-web clients can call the method, which rules out client streaming
+    pub fn is_grpc_web_compatible(self) -> bool {
+        matches!(self, MethodKind::Unary | MethodKind::ServerStreaming)
+    }
+}
+
+/// The services and methods described by an encoded `FileDescriptorSet`, keyed by their request
+/// path, i.e. `/package.Service/Method`.
+#[derive(Clone, Debug)]
+pub struct ServiceRegistry {
+    descriptors: DescriptorPool,
+    methods: Arc<BTreeMap<String, MethodKind>>,
+}
+
+impl ServiceRegistry {
+    pub fn new(file_descriptor_set: &[u8]) -> Result<Self, DescriptorError> {
+        let descriptors = DescriptorPool::decode(file_descriptor_set)?;
+
+        let methods = descriptors
+            .services()
+            .flat_map(|service| service.methods().collect::<Vec<_>>())
+            .map(|method| {
+                let path = format!("/{}/{}", method.parent_service().full_name(), method.name());
+                let kind = match (method.is_client_streaming(), method.is_server_streaming()) {
+                    (false, false) => MethodKind::Unary,
+                    (false, true) => MethodKind::ServerStreaming,
+                    (true, false) => MethodKind::ClientStreaming,
+                    (true, true) => MethodKind::BidiStreaming,
+                };
+                (path, kind)
+            })
+            .collect();
+
+        Ok(Self {
+            descriptors,
+            methods: Arc::new(methods),
+        })
+    }
+
+    /// The kind of the method at a request path, if it's described
+    pub fn method(&self, path: &str) -> Option<MethodKind> {
+        self.methods.get(path).copied()
+    }
+
+    /// Request paths and kinds of every described method, in path order
+    pub fn methods(&self) -> impl Iterator<Item = (&str, MethodKind)> {
+        self.methods
+            .iter()
+            .map(|(path, kind)| (path.as_str(), *kind))
+    }
+
+    /// Fully qualified names of the described services
+    pub fn services(&self) -> impl Iterator<Item = String> + '_ {
+        self.descriptors
+            .services()
+            .map(|service| service.full_name().to_string())
+    }
+
+    pub fn descriptors(&self) -> &DescriptorPool {
+        &self.descriptors
+    }
+
+    fn respond(&self, request: ServerReflectionRequest) -> ServerReflectionResponse {
+        use server_reflection_request::MessageRequest;
+        use server_reflection_response::MessageResponse;
+
+        let response = match &request.message_request {
+            Some(MessageRequest::FileByFilename(name)) => self
+                .descriptors
+                .get_file_by_name(name)
+                .map(file_descriptor_response)
+                .ok_or_else(|| Status::not_found(format!("File `{name}` not found"))),
+            Some(MessageRequest::FileContainingSymbol(symbol)) => self
+                .file_containing_symbol(symbol)
+                .map(file_descriptor_response)
+                .ok_or_else(|| Status::not_found(format!("Symbol `{symbol}` not found"))),
+            Some(MessageRequest::FileContainingExtension(extension)) => self
+                .descriptors
+                .get_message_by_name(&extension.containing_type)
+                .and_then(|message| {
+                    message
+                        .extensions()
+                        .find(|ext| ext.number() as i32 == extension.extension_number)
+                })
+                .map(|extension| file_descriptor_response(extension.parent_file()))
+                .ok_or_else(|| {
+                    Status::not_found(format!(
+                        "Extension {} of `{}` not found",
+                        extension.extension_number, extension.containing_type
+                    ))
+                }),
+            Some(MessageRequest::AllExtensionNumbersOfType(name)) => self
+                .descriptors
+                .get_message_by_name(name)
+                .map(|message| {
+                    MessageResponse::AllExtensionNumbersResponse(ExtensionNumberResponse {
+                        base_type_name: name.clone(),
+                        extension_number: message
+                            .extensions()
+                            .map(|ext| ext.number() as i32)
+                            .collect(),
+                    })
+                })
+                .ok_or_else(|| Status::not_found(format!("Type `{name}` not found"))),
+            Some(MessageRequest::ListServices(_)) => {
+                Ok(MessageResponse::ListServicesResponse(ListServiceResponse {
+                    service: self
+                        .services()
+                        .map(|name| ServiceResponse { name })
+                        .collect(),
+                }))
+            }
+            None => Err(Status::invalid_argument("Missing message_request")),
+        };
+
+        let response = response.unwrap_or_else(|status| {
+            MessageResponse::ErrorResponse(ErrorResponse {
+                error_code: status.code() as i32,
+                error_message: status.message().to_string(),
+            })
+        });
+
+        ServerReflectionResponse {
+            valid_host: request.host.clone(),
+            original_request: Some(request),
+            message_response: Some(response),
+        }
+    }
+
+    /// The file defining a message, enum, extension, service or method
+    fn file_containing_symbol(&self, symbol: &str) -> Option<FileDescriptor> {
+        let pool = &self.descriptors;
+        pool.get_message_by_name(symbol)
+            .map(|message| message.parent_file())
+            .or_else(|| pool.get_enum_by_name(symbol).map(|e| e.parent_file()))
+            .or_else(|| pool.get_extension_by_name(symbol).map(|e| e.parent_file()))
+            .or_else(|| pool.get_service_by_name(symbol).map(|s| s.parent_file()))
+            .or_else(|| {
+                let (service, method) = symbol.rsplit_once('.')?;
+                let service = pool.get_service_by_name(service)?;
+                service
+                    .methods()
+                    .any(|m| m.name() == method)
+                    .then(|| service.parent_file())
+            })
+    }
+}
+
+/// The file and its transitive dependencies, each encoded once, as clients need them all to
+/// build the file
+fn file_descriptor_response(file: FileDescriptor) -> server_reflection_response::MessageResponse {
+    let mut seen = HashSet::new();
+    let mut pending = vec![file];
+    let mut file_descriptor_proto = Vec::new();
+
+    while let Some(file) = pending.pop() {
+        if !seen.insert(file.name().to_string()) {
+            continue;
+        }
+        file_descriptor_proto.push(prost::Message::encode_to_vec(file.file_descriptor_proto()));
+        pending.extend(file.dependencies());
+    }
+
+    server_reflection_response::MessageResponse::FileDescriptorResponse(FileDescriptorResponse {
+        file_descriptor_proto,
+    })
+}
+
+/// Serves `grpc.reflection.v1.ServerReflection`. The method is bidirectional, but grpc-web
+/// delivers the request stream in full, so each request message is answered in turn once the
+/// request body has arrived.
+#[derive(Clone)]
+pub(crate) struct ReflectionService {
+    registry: ServiceRegistry,
+}
+
+impl ReflectionService {
+    pub(crate) fn new(registry: ServiceRegistry) -> Self {
+        Self { registry }
+    }
+}
+
+impl NamedService for ReflectionService {
+    const NAME: &'static str = "grpc.reflection.v1.ServerReflection";
+}
+
+impl Service<Request<Body>> for ReflectionService {
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if req.uri().path() != SERVER_REFLECTION_INFO {
+            let status = Status::unimplemented("Unknown reflection method");
+            return Box::pin(ready(Ok(status.into_http())));
+        }
+
+        let registry = self.registry.clone();
+        let info = tower::service_fn(
+            move |request: tonic::Request<Streaming<ServerReflectionRequest>>| {
+                let registry = registry.clone();
+                let responses = request
+                    .into_inner()
+                    .map(move |request| request.map(|request| registry.respond(request)));
+                ready(Ok::<_, Status>(tonic::Response::new(responses)))
+            },
+        );
+
+        Box::pin(async move { Ok(Grpc::new(ProstCodec::default()).streaming(info, req).await) })
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ServerReflectionRequest {
+    #[prost(string, tag = "1")]
+    host: String,
+    #[prost(
+        oneof = "server_reflection_request::MessageRequest",
+        tags = "3, 4, 5, 6, 7"
+    )]
+    message_request: Option<server_reflection_request::MessageRequest>,
+}
+
+mod server_reflection_request {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub(super) enum MessageRequest {
+        #[prost(string, tag = "3")]
+        FileByFilename(String),
+        #[prost(string, tag = "4")]
+        FileContainingSymbol(String),
+        #[prost(message, tag = "5")]
+        FileContainingExtension(super::ExtensionRequest),
+        #[prost(string, tag = "6")]
+        AllExtensionNumbersOfType(String),
+        #[prost(string, tag = "7")]
+        ListServices(String),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ExtensionRequest {
+    #[prost(string, tag = "1")]
+    containing_type: String,
+    #[prost(int32, tag = "2")]
+    extension_number: i32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ServerReflectionResponse {
+    #[prost(string, tag = "1")]
+    valid_host: String,
+    #[prost(message, optional, tag = "2")]
+    original_request: Option<ServerReflectionRequest>,
+    #[prost(
+        oneof = "server_reflection_response::MessageResponse",
+        tags = "4, 5, 6, 7"
+    )]
+    message_response: Option<server_reflection_response::MessageResponse>,
+}
+
+mod server_reflection_response {
+    // named as in `reflection.proto`
+    #[allow(clippy::enum_variant_names)]
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub(super) enum MessageResponse {
+        #[prost(message, tag = "4")]
+        FileDescriptorResponse(super::FileDescriptorResponse),
+        #[prost(message, tag = "5")]
+        AllExtensionNumbersResponse(super::ExtensionNumberResponse),
+        #[prost(message, tag = "6")]
+        ListServicesResponse(super::ListServiceResponse),
+        #[prost(message, tag = "7")]
+        ErrorResponse(super::ErrorResponse),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct FileDescriptorResponse {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    file_descriptor_proto: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ExtensionNumberResponse {
+    #[prost(string, tag = "1")]
+    base_type_name: String,
+    #[prost(int32, repeated, tag = "2")]
+    extension_number: Vec<i32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ListServiceResponse {
+    #[prost(message, repeated, tag = "1")]
+    service: Vec<ServiceResponse>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ServiceResponse {
+    #[prost(string, tag = "1")]
+    name: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ErrorResponse {
+    #[prost(int32, tag = "1")]
+    error_code: i32,
+    #[prost(string, tag = "2")]
+    error_message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::server_reflection_request::MessageRequest;
+    use super::server_reflection_response::MessageResponse;
+    use super::*;
+    use bytes::{Buf, Bytes};
+    use http::header::CONTENT_TYPE;
+    use http_body_util::{BodyExt, Full};
+    use prost::Message;
+    use prost_reflect::prost_types::{
+        DescriptorProto, FileDescriptorProto, FileDescriptorSet, MethodDescriptorProto,
+        ServiceDescriptorProto,
+    };
+    use tower::ServiceExt;
+
+    fn method(name: &str, client_streaming: bool, server_streaming: bool) -> MethodDescriptorProto {
+        MethodDescriptorProto {
+            name: Some(name.to_string()),
+            input_type: Some(".test.Book".to_string()),
+            output_type: Some(".test.Book".to_string()),
+            client_streaming: Some(client_streaming),
+            server_streaming: Some(server_streaming),
+            ..Default::default()
+        }
+    }
+
+    /// `test.Library`, in a file importing the messages from another
+    fn registry() -> ServiceRegistry {
+        let common = FileDescriptorProto {
+            name: Some("test/common.proto".to_string()),
+            package: Some("test".to_string()),
+            message_type: vec![DescriptorProto {
+                name: Some("Book".to_string()),
+                ..Default::default()
+            }],
+            syntax: Some("proto3".to_string()),
+            ..Default::default()
+        };
+        let service = FileDescriptorProto {
+            name: Some("test/service.proto".to_string()),
+            package: Some("test".to_string()),
+            dependency: vec!["test/common.proto".to_string()],
+            service: vec![ServiceDescriptorProto {
+                name: Some("Library".to_string()),
+                method: vec![
+                    method("GetBook", false, false),
+                    method("ListBooks", false, true),
+                    method("AddBooks", true, false),
+                    method("SyncBooks", true, true),
+                ],
+                ..Default::default()
+            }],
+            syntax: Some("proto3".to_string()),
+            ..Default::default()
+        };
+
+        let set = FileDescriptorSet {
+            file: vec![common, service],
+        };
+        ServiceRegistry::new(&set.encode_to_vec()).unwrap()
+    }
+
+    /// Sends the requests in a single gRPC request body, as grpc-web delivers them
+    async fn reflect(requests: Vec<MessageRequest>) -> Vec<MessageResponse> {
+        let mut body = Vec::new();
+        for request in requests {
+            let request = ServerReflectionRequest {
+                host: String::new(),
+                message_request: Some(request),
+            }
+            .encode_to_vec();
+            body.push(0);
+            body.extend_from_slice(&(request.len() as u32).to_be_bytes());
+            body.extend_from_slice(&request);
+        }
+
+        let req = Request::post(SERVER_REFLECTION_INFO)
+            .header(CONTENT_TYPE, "application/grpc")
+            .body(Body::new(Full::new(Bytes::from(body))))
+            .unwrap();
+
+        let res = ReflectionService::new(registry())
+            .oneshot(req)
+            .await
+            .unwrap();
+        let body = res.into_body().collect().await.unwrap();
+        assert_eq!(body.trailers().unwrap()["grpc-status"], "0");
+
+        let mut body = body.to_bytes();
+        let mut responses = Vec::new();
+        while body.has_remaining() {
+            body.advance(1);
+            let len = body.get_u32() as usize;
+            let response = ServerReflectionResponse::decode(body.split_to(len)).unwrap();
+            responses.push(response.message_response.unwrap());
+        }
+        responses
+    }
+
+    #[test]
+    fn registers_method_kinds_by_path() {
+        let registry = registry();
+
+        assert_eq!(
+            registry.methods().collect::<Vec<_>>(),
+            vec![
+                ("/test.Library/AddBooks", MethodKind::ClientStreaming),
+                ("/test.Library/GetBook", MethodKind::Unary),
+                ("/test.Library/ListBooks", MethodKind::ServerStreaming),
+                ("/test.Library/SyncBooks", MethodKind::BidiStreaming),
+            ]
+        );
+        assert_eq!(
+            registry.method("/test.Library/ListBooks"),
+            Some(MethodKind::ServerStreaming)
+        );
+        assert_eq!(registry.method("/test.Library/Missing"), None);
+        assert!(!MethodKind::ClientStreaming.is_grpc_web_compatible());
+    }
+
+    #[tokio::test]
+    async fn answers_each_request_in_turn() {
+        let responses = reflect(vec![
+            MessageRequest::ListServices(String::new()),
+            MessageRequest::FileContainingSymbol("test.Library.GetBook".to_string()),
+        ])
+        .await;
+
+        assert_eq!(
+            responses[0],
+            MessageResponse::ListServicesResponse(ListServiceResponse {
+                service: vec![ServiceResponse {
+                    name: "test.Library".to_string(),
+                }],
+            })
+        );
+
+        let MessageResponse::FileDescriptorResponse(files) = &responses[1] else {
+            panic!("unexpected response {:?}", responses[1]);
+        };
+        let names: Vec<_> = files
+            .file_descriptor_proto
+            .iter()
+            .map(|file| FileDescriptorProto::decode(file.as_slice()).unwrap())
+            .map(|file| file.name().to_string())
+            .collect();
+        assert_eq!(names, vec!["test/service.proto", "test/common.proto"]);
+    }
+
+    #[tokio::test]
+    async fn reports_unknown_symbols() {
+        let responses = reflect(vec![MessageRequest::FileContainingSymbol(
+            "test.Shelf".to_string(),
+        )])
+        .await;
+
+        assert_eq!(
+            responses,
+            vec![MessageResponse::ErrorResponse(ErrorResponse {
+                error_code: tonic::Code::NotFound as i32,
+                error_message: "Symbol `test.Shelf` not found".to_string(),
+            })]
+        );
+    }
+}
@@ -0,0 +1,393 @@
+//! Typed request details from the invocation event, alongside the lambda `Context`.
+
+use lambda_http::RequestExt;
+use lambda_http::aws_lambda_events::apigw::ApiGatewayRequestAuthorizer;
+use lambda_http::request::RequestContext;
+use std::net::IpAddr;
+
+/// Which trigger invoked the function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EventSource {
+    FunctionUrl,
+    /// API Gateway REST API
+    ApiGatewayV1,
+    /// API Gateway HTTP API
+    ApiGatewayV2,
+    Alb,
+    /// Direct call through the Lambda `Invoke` API by an `InvokeChannel`. The caller is authorized
+    /// by IAM for `lambda:InvokeFunction`, but Lambda doesn't pass their identity to the function.
+    Invoke,
+}
+
+/// Request details from the Function URL, API Gateway or ALB event, inserted into the request
+/// extensions of every call next to the lambda `Context`. Retrieve both from a `tonic::Request`
+/// with [`LambdaRequestExt`].
+///
+/// Fields the trigger doesn't provide are `None`, e.g. ALB events carry no request id or stage,
+/// and direct `Invoke` API calls carry nothing but the user agent.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct LambdaRequestInfo {
+    pub source: EventSource,
+    /// Request id assigned by API Gateway or the Function URL. This differs from the lambda
+    /// invocation id, `Context::request_id`.
+    pub request_id: Option<String>,
+    /// Address of the client, from `x-forwarded-for` for ALBs
+    pub source_ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    pub domain_name: Option<String>,
+    pub stage: Option<String>,
+    pub api_id: Option<String>,
+    pub account_id: Option<String>,
+    authorizer: Option<ApiGatewayRequestAuthorizer>,
+    iam: Option<IamIdentity>,
+}
+
+/// The IAM identity of a request signed with SigV4, i.e. Function URLs with `AWS_IAM` auth and
+/// API Gateway routes with IAM authorization
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct IamIdentity {
+    pub access_key: Option<String>,
+    pub account_id: Option<String>,
+    pub caller_id: Option<String>,
+    pub user_arn: Option<String>,
+    pub user_id: Option<String>,
+    pub principal_org_id: Option<String>,
+}
+
+impl LambdaRequestInfo {
+    /// Reads the request context `lambda_http` attached to the request, if any
+    pub(crate) fn from_request(req: &lambda_http::Request) -> Option<Self> {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+
+        let info = match req.request_context_ref()? {
+            RequestContext::ApiGatewayV2(ctx) => {
+                let domain_name = string(&ctx.domain_name);
+                let source = if ctx.route_key.as_deref() == Some(crate::INVOKE_ROUTE_KEY) {
+                    EventSource::Invoke
+                } else if domain_name
+                    .as_deref()
+                    .is_some_and(|domain| domain.contains(".lambda-url."))
+                {
+                    EventSource::FunctionUrl
+                } else {
+                    EventSource::ApiGatewayV2
+                };
+                let authorizer = ctx.authorizer.clone();
+
+                Self {
+                    source,
+                    request_id: string(&ctx.request_id),
+                    source_ip: ip(&ctx.http.source_ip),
+                    user_agent: string(&ctx.http.user_agent),
+                    domain_name,
+                    stage: string(&ctx.stage),
+                    api_id: string(&ctx.apiid),
+                    account_id: string(&ctx.account_id),
+                    iam: authorizer
+                        .as_ref()
+                        .and_then(|authorizer| authorizer.iam.as_ref())
+                        .map(|iam| IamIdentity {
+                            access_key: string(&iam.access_key),
+                            account_id: string(&iam.account_id),
+                            caller_id: string(&iam.caller_id),
+                            user_arn: string(&iam.user_arn),
+                            user_id: string(&iam.user_id),
+                            principal_org_id: string(&iam.principal_org_id),
+                        }),
+                    authorizer,
+                }
+            }
+            RequestContext::ApiGatewayV1(ctx) => {
+                let identity = &ctx.identity;
+
+                Self {
+                    source: EventSource::ApiGatewayV1,
+                    request_id: string(&ctx.request_id),
+                    source_ip: ip(&identity.source_ip),
+                    user_agent: string(&identity.user_agent),
+                    domain_name: string(&ctx.domain_name),
+                    stage: string(&ctx.stage),
+                    api_id: string(&ctx.apiid),
+                    account_id: string(&ctx.account_id),
+                    // the identity is only of the caller with IAM authorization
+                    iam: string(&identity.user_arn).map(|user_arn| IamIdentity {
+                        access_key: string(&identity.access_key),
+                        account_id: string(&identity.account_id),
+                        caller_id: string(&identity.caller),
+                        user_arn: Some(user_arn),
+                        user_id: string(&identity.user),
+                        principal_org_id: None,
+                    }),
+                    authorizer: Some(ctx.authorizer.clone()),
+                }
+            }
+            RequestContext::Alb(_) => Self {
+                source: EventSource::Alb,
+                request_id: None,
+                source_ip: header("x-forwarded-for")
+                    .and_then(|ips| ips.split(',').next()?.trim().parse().ok()),
+                user_agent: header("user-agent"),
+                domain_name: header("host"),
+                stage: None,
+                api_id: None,
+                account_id: None,
+                authorizer: None,
+                iam: None,
+            },
+            #[allow(unreachable_patterns)]
+            _ => return None,
+        };
+
+        Some(info)
+    }
+
+    /// A single claim of the JWT validated by an HTTP API JWT authorizer, or by a REST API Cognito
+    /// user pool authorizer, i.e. `jwt_claim("sub")`. API Gateway passes every claim as a string.
+    pub fn jwt_claim(&self, name: &str) -> Option<&str> {
+        let authorizer = self.authorizer.as_ref()?;
+        match &authorizer.jwt {
+            Some(jwt) => jwt.claims.get(name).map(String::as_str),
+            // REST APIs pass the claims of Cognito authorizers amongst the authorizer fields
+            None => authorizer.fields.get("claims")?.get(name)?.as_str(),
+        }
+    }
+
+    /// Scopes of the JWT validated by an HTTP API JWT authorizer
+    pub fn jwt_scopes(&self) -> impl Iterator<Item = &str> {
+        self.authorizer
+            .as_ref()
+            .and_then(|authorizer| authorizer.jwt.as_ref()?.scopes.as_ref())
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+    }
+
+    /// The SigV4 signer of the request
+    pub fn iam(&self) -> Option<&IamIdentity> {
+        self.iam.as_ref()
+    }
+
+    /// The authorizer output as it appears in the event. The context returned by a lambda
+    /// authorizer, including the `principalId` for REST APIs, is in `fields`.
+    pub fn authorizer(&self) -> Option<&ApiGatewayRequestAuthorizer> {
+        self.authorizer.as_ref()
+    }
+}
+
+fn string(value: &Option<String>) -> Option<String> {
+    value.clone().filter(|value| !value.is_empty())
+}
+
+fn ip(value: &Option<String>) -> Option<IpAddr> {
+    value.as_deref()?.parse().ok()
+}
+
+/// Access to the lambda invocation details a handler receives with each call
+pub trait LambdaRequestExt {
+    /// The lambda invocation `Context`. Always present when served by the lambda runtime, and
+    /// synthesized when served locally or by the `testing` harness.
+    fn lambda_context(&self) -> Option<&lambda_runtime::Context>;
+
+    /// The request details from the invocation event. Absent when served with `serve_local`, as
+    /// there is no event.
+    fn lambda_request_info(&self) -> Option<&LambdaRequestInfo>;
+}
+
+impl<T> LambdaRequestExt for tonic::Request<T> {
+    fn lambda_context(&self) -> Option<&lambda_runtime::Context> {
+        self.extensions().get()
+    }
+
+    fn lambda_request_info(&self) -> Option<&LambdaRequestInfo> {
+        self.extensions().get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    fn info(event: Value) -> LambdaRequestInfo {
+        let req = lambda_http::request::from_str(&event.to_string()).unwrap();
+        LambdaRequestInfo::from_request(&req).unwrap()
+    }
+
+    fn http_api_event(domain_name: &str, authorizer: Value) -> Value {
+        json!({
+            "version": "2.0",
+            "routeKey": "$default",
+            "rawPath": "/greeter.Greeter/SayHello",
+            "rawQueryString": "",
+            "headers": { "content-type": "application/grpc-web+proto" },
+            "requestContext": {
+                "accountId": "123456789012",
+                "apiId": "abc123",
+                "authorizer": authorizer,
+                "domainName": domain_name,
+                "domainPrefix": "abc123",
+                "http": {
+                    "method": "POST",
+                    "path": "/greeter.Greeter/SayHello",
+                    "protocol": "HTTP/1.1",
+                    "sourceIp": "203.0.113.7",
+                    "userAgent": "grpc-web-javascript/0.1",
+                },
+                "requestId": "request-1",
+                "routeKey": "$default",
+                "stage": "$default",
+                "time": "01/Jan/2025:00:00:00 +0000",
+                "timeEpoch": 1735689600000_i64,
+            },
+            "isBase64Encoded": false,
+        })
+    }
+
+    fn rest_api_event(authorizer: Value, identity: Value) -> Value {
+        json!({
+            "resource": "/{proxy+}",
+            "path": "/greeter.Greeter/SayHello",
+            "httpMethod": "POST",
+            "headers": { "content-type": "application/grpc-web+proto" },
+            "multiValueHeaders": {},
+            "queryStringParameters": null,
+            "requestContext": {
+                "accountId": "123456789012",
+                "apiId": "abc123",
+                "authorizer": authorizer,
+                "domainName": "abc123.execute-api.us-east-1.amazonaws.com",
+                "httpMethod": "POST",
+                "identity": identity,
+                "path": "/prod/greeter.Greeter/SayHello",
+                "requestId": "request-2",
+                "resourceId": "r1",
+                "resourcePath": "/{proxy+}",
+                "stage": "prod",
+            },
+            "body": null,
+            "isBase64Encoded": false,
+        })
+    }
+
+    #[test]
+    fn reads_function_url_with_iam_auth() {
+        let info = info(http_api_event(
+            "abc123.lambda-url.us-east-1.on.aws",
+            json!({ "iam": {
+                "accessKey": "AKIA0EXAMPLE",
+                "accountId": "123456789012",
+                "callerId": "AIDA0EXAMPLE",
+                "userArn": "arn:aws:iam::123456789012:user/alice",
+                "userId": "AIDA0EXAMPLE",
+            }}),
+        ));
+
+        assert_eq!(info.source, EventSource::FunctionUrl);
+        assert_eq!(info.request_id.as_deref(), Some("request-1"));
+        assert_eq!(info.source_ip, Some("203.0.113.7".parse().unwrap()));
+        assert_eq!(info.user_agent.as_deref(), Some("grpc-web-javascript/0.1"));
+        assert_eq!(
+            info.iam().unwrap().user_arn.as_deref(),
+            Some("arn:aws:iam::123456789012:user/alice")
+        );
+        assert!(info.jwt_claim("sub").is_none());
+    }
+
+    #[test]
+    fn reads_http_api_jwt_authorizer() {
+        let info = info(http_api_event(
+            "api.example.com",
+            json!({ "jwt": {
+                "claims": { "sub": "user-1", "email": "user@example.com" },
+                "scopes": ["greet", "read"],
+            }}),
+        ));
+
+        assert_eq!(info.source, EventSource::ApiGatewayV2);
+        assert_eq!(info.jwt_claim("sub"), Some("user-1"));
+        assert_eq!(info.jwt_scopes().collect::<Vec<_>>(), vec!["greet", "read"]);
+        assert!(info.iam().is_none());
+        assert!(info.authorizer().unwrap().fields.is_empty());
+    }
+
+    #[test]
+    fn reads_rest_api_authorizers() {
+        let cognito = info(rest_api_event(
+            json!({ "claims": { "sub": "user-2" } }),
+            json!({ "sourceIp": "198.51.100.1", "userAgent": "curl/8.0" }),
+        ));
+
+        assert_eq!(cognito.source, EventSource::ApiGatewayV1);
+        assert_eq!(cognito.stage.as_deref(), Some("prod"));
+        assert_eq!(cognito.source_ip, Some("198.51.100.1".parse().unwrap()));
+        assert_eq!(cognito.jwt_claim("sub"), Some("user-2"));
+
+        let lambda = info(rest_api_event(
+            json!({ "principalId": "user-3", "tenant": "acme" }),
+            json!({
+                "sourceIp": "198.51.100.1",
+                "userArn": "arn:aws:iam::123456789012:user/bob",
+                "caller": "AIDA1EXAMPLE",
+            }),
+        ));
+
+        assert_eq!(lambda.authorizer().unwrap().fields["tenant"], "acme");
+        assert_eq!(
+            lambda.iam().unwrap().caller_id.as_deref(),
+            Some("AIDA1EXAMPLE")
+        );
+    }
+
+    #[test]
+    fn reads_direct_invocations() {
+        let mut event = http_api_event("abc123.lambda-url.us-east-1.on.aws", Value::Null);
+        event["requestContext"] = json!({
+            "http": {
+                "method": "POST",
+                "path": "/greeter.Greeter/SayHello",
+                "protocol": "HTTP/1.1",
+                "userAgent": "tonic/0.14.2",
+            },
+            "routeKey": "$invoke",
+            "timeEpoch": 1735689600000_i64,
+        });
+        let info = info(event);
+
+        assert_eq!(info.source, EventSource::Invoke);
+        assert_eq!(info.user_agent.as_deref(), Some("tonic/0.14.2"));
+        assert!(info.source_ip.is_none());
+        assert!(info.account_id.is_none());
+        assert!(info.iam().is_none());
+    }
+
+    #[test]
+    fn reads_alb_forwarded_headers() {
+        let info = info(json!({
+            "requestContext": { "elb": {
+                "targetGroupArn": "arn:aws:elasticloadbalancing:us-east-1:123456789012:targetgroup/lambda/abc",
+            }},
+            "httpMethod": "POST",
+            "path": "/greeter.Greeter/SayHello",
+            "headers": {
+                "host": "grpc.example.com",
+                "x-forwarded-for": "192.0.2.10, 10.0.0.1",
+            },
+            "body": "",
+            "isBase64Encoded": false,
+        }));
+
+        assert_eq!(info.source, EventSource::Alb);
+        assert_eq!(info.source_ip, Some("192.0.2.10".parse().unwrap()));
+        assert_eq!(info.domain_name.as_deref(), Some("grpc.example.com"));
+        assert!(info.request_id.is_none());
+        assert!(info.authorizer().is_none());
+    }
+}
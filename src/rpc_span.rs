@@ -0,0 +1,344 @@
+//! A tracing span per call, with attributes following the OpenTelemetry RPC and FaaS semantic
+//! conventions.
+
+use crate::grpc_call::{CallObserver, ObservedBody, service_method};
+use crate::lifecycle::IsColdStart;
+use crate::trace_context::TraceContext;
+use bytes::Bytes;
+use http::{Request, Response};
+use http_body::Body as HttpBody;
+use lambda_http::tracing::{Instrument, Span, debug, field, info_span};
+use lambda_runtime::Context as LambdaContext;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tonic::Code;
+use tonic::body::Body;
+use tower::{BoxError, Layer, Service};
+
+/// Opens a span named `grpc` around every call, entered while the handler runs and while its
+/// response streams, as is the [`TraceContext`] of the call, also inserted into the request
+/// extensions. The span carries
+///
+/// * `otel.name`, `otel.kind`, `rpc.system`, `rpc.service` and `rpc.method`
+/// * `faas.invocation_id`, the lambda request id, and `faas.coldstart`, set on the call marked
+///   [`IsColdStart`]
+/// * `client.address`, the address of the client, when the `request-info` feature is enabled
+/// * `rpc.grpc.status_code` and `duration_ms`, recorded once the trailers are sent, along with
+///   `otel.status_code` for the statuses the conventions count as server errors
+#[derive(Clone, Copy, Default)]
+pub(crate) struct RpcSpanLayer;
+
+impl<S> Layer<S> for RpcSpanLayer {
+    type Service = RpcSpanService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RpcSpanService { inner }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct RpcSpanService<S> {
+    inner: S,
+}
+
+impl<S, ResBody> Service<Request<Body>> for RpcSpanService<S>
+where
+    S: Service<Request<Body>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+    ResBody: HttpBody<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        let span = request_span(&req);
+        let start = Instant::now();
+
+        let trace_context = TraceContext::from_request(&req);
+        if let Some(trace_context) = trace_context {
+            req.extensions_mut().insert(trace_context);
+        }
+
+        let call = || span.in_scope(|| self.inner.call(req));
+        let fut = match trace_context {
+            Some(trace_context) => trace_context.sync_scope(call),
+            None => call(),
+        };
+        let fut = fut.instrument(span.clone());
+
+        Box::pin(async move {
+            let res = match trace_context {
+                Some(trace_context) => trace_context.scope(fut).await?,
+                None => fut.await?,
+            };
+
+            Ok(ObservedBody::wrap(
+                res,
+                SpanObserver {
+                    span,
+                    trace_context,
+                    start,
+                },
+            ))
+        })
+    }
+}
+
+fn request_span<B>(req: &Request<B>) -> Span {
+    let path = req.uri().path();
+    let (service, method) = service_method(path).unwrap_or((path, ""));
+
+    let invocation_id = req
+        .extensions()
+        .get::<LambdaContext>()
+        .map(|ctx| ctx.request_id.as_str());
+
+    #[cfg(feature = "request-info")]
+    let client_address = req
+        .extensions()
+        .get::<crate::LambdaRequestInfo>()
+        .and_then(|info| info.source_ip);
+    #[cfg(not(feature = "request-info"))]
+    let client_address: Option<std::net::IpAddr> = None;
+
+    info_span!(
+        "grpc",
+        otel.name = %path.trim_start_matches('/'),
+        otel.kind = "server",
+        otel.status_code = field::Empty,
+        rpc.system = "grpc",
+        rpc.service = service,
+        rpc.method = method,
+        rpc.grpc.status_code = field::Empty,
+        faas.invocation_id = invocation_id,
+        faas.coldstart = req.extensions().get::<IsColdStart>().is_some(),
+        client.address = client_address.map(field::display),
+        duration_ms = field::Empty,
+    )
+}
+
+/// Statuses counted as server errors by the OpenTelemetry gRPC conventions
+fn is_server_error(code: Code) -> bool {
+    matches!(
+        code,
+        Code::Unknown
+            | Code::DeadlineExceeded
+            | Code::Unimplemented
+            | Code::Internal
+            | Code::Unavailable
+            | Code::DataLoss
+    )
+}
+
+/// Records the final status of the call on the span
+struct SpanObserver {
+    span: Span,
+    trace_context: Option<TraceContext>,
+    start: Instant,
+}
+
+impl CallObserver for SpanObserver {
+    fn poll<T>(&mut self, poll: impl FnOnce() -> T) -> T {
+        let _entered = self.span.enter();
+        match self.trace_context {
+            Some(trace_context) => trace_context.sync_scope(poll),
+            None => poll(),
+        }
+    }
+
+    fn complete(&mut self, status: Code) {
+        let duration_ms = self.start.elapsed().as_millis() as u64;
+
+        self.span.record("rpc.grpc.status_code", status as i32);
+        self.span.record("duration_ms", duration_ms);
+        if is_server_error(status) {
+            self.span.record("otel.status_code", "ERROR");
+        }
+
+        debug!(
+            parent: &self.span,
+            grpc_status = ?status,
+            duration_ms,
+            "finished grpc call"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::{HeaderMap, HeaderValue};
+    use http_body::Frame;
+    use http_body_util::BodyExt;
+    use lambda_http::tracing::span::{Attributes, Id, Record};
+    use lambda_http::tracing::subscriber::layer::{Context as LayerContext, SubscriberExt};
+    use lambda_http::tracing::subscriber::{Layer as SubscriberLayer, Registry};
+    use lambda_http::tracing::{Subscriber, dispatcher};
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+    use tonic::Status;
+    use tower::ServiceExt;
+
+    type Fields = Arc<Mutex<HashMap<String, String>>>;
+
+    /// Collects the fields of the spans opened and recorded, by name
+    #[derive(Default, Clone)]
+    struct Recorder(Fields);
+
+    impl field::Visit for Recorder {
+        fn record_debug(&mut self, field: &field::Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(field.name().to_string(), format!("{value:?}"));
+        }
+
+        fn record_str(&mut self, field: &field::Field, value: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    impl<S: Subscriber> SubscriberLayer<S> for Recorder {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: LayerContext<'_, S>) {
+            attrs.record(&mut self.clone());
+        }
+
+        fn on_record(&self, _id: &Id, values: &Record<'_>, _ctx: LayerContext<'_, S>) {
+            values.record(&mut self.clone());
+        }
+    }
+
+    fn request(path: &str) -> Request<Body> {
+        let mut ctx = LambdaContext::default();
+        ctx.request_id = "abc-123".to_string();
+
+        let mut req = Request::post(path).body(Body::empty()).unwrap();
+        req.extensions_mut().insert(ctx);
+        req
+    }
+
+    /// Calls a service behind the layer and reads the response, returning the span fields
+    async fn call<F>(layer: &RpcSpanLayer, req: Request<Body>, respond: F, read: bool) -> Fields
+    where
+        F: Fn() -> Response<Body> + Send + 'static,
+    {
+        let recorder = Recorder::default();
+        let fields = recorder.0.clone();
+        let _guard = dispatcher::set_default(&dispatcher::Dispatch::new(
+            Registry::default().with(recorder),
+        ));
+
+        let inner = tower::service_fn(move |_req: Request<Body>| {
+            let res = respond();
+            async move { Ok::<_, Infallible>(res) }
+        });
+        let res = layer.layer(inner).oneshot(req).await.unwrap();
+        if read {
+            res.into_body().collect().await.unwrap();
+        }
+
+        fields
+    }
+
+    fn field(fields: &Fields, name: &str) -> Option<String> {
+        fields.lock().unwrap().get(name).cloned()
+    }
+
+    fn with_trailers(status: &'static str) -> Response<Body> {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static(status));
+        let frames = [
+            Ok::<_, Status>(Frame::data(Bytes::from_static(b"message"))),
+            Ok(Frame::trailers(trailers)),
+        ];
+        Response::new(Body::new(http_body_util::StreamBody::new(
+            futures_util::stream::iter(frames),
+        )))
+    }
+
+    #[tokio::test]
+    async fn records_the_call_and_its_final_status() {
+        let layer = RpcSpanLayer;
+        let mut req = request("/test.Service/Method");
+        req.extensions_mut().insert(IsColdStart);
+        let fields = call(&layer, req, || with_trailers("0"), true).await;
+
+        assert_eq!(field(&fields, "otel.name").unwrap(), "test.Service/Method");
+        assert_eq!(field(&fields, "rpc.system").unwrap(), "grpc");
+        assert_eq!(field(&fields, "rpc.service").unwrap(), "test.Service");
+        assert_eq!(field(&fields, "rpc.method").unwrap(), "Method");
+        assert_eq!(field(&fields, "faas.invocation_id").unwrap(), "abc-123");
+        assert_eq!(field(&fields, "faas.coldstart").unwrap(), "true");
+        assert_eq!(field(&fields, "rpc.grpc.status_code").unwrap(), "0");
+        assert!(field(&fields, "duration_ms").is_some());
+        assert_eq!(field(&fields, "otel.status_code"), None);
+
+        let fields = call(
+            &layer,
+            request("/test.Service/Method"),
+            || with_trailers("13"),
+            true,
+        )
+        .await;
+        assert_eq!(field(&fields, "faas.coldstart").unwrap(), "false");
+        assert_eq!(field(&fields, "rpc.grpc.status_code").unwrap(), "13");
+        assert_eq!(field(&fields, "otel.status_code").unwrap(), "ERROR");
+    }
+
+    #[tokio::test]
+    async fn reads_trailers_only_responses() {
+        let fields = call(
+            &RpcSpanLayer,
+            request("/test.Service/Method"),
+            || Status::not_found("missing").into_http(),
+            true,
+        )
+        .await;
+
+        assert_eq!(field(&fields, "rpc.grpc.status_code").unwrap(), "5");
+        assert_eq!(field(&fields, "otel.status_code"), None);
+    }
+
+    #[tokio::test]
+    async fn records_abandoned_streams_as_cancelled() {
+        let fields = call(
+            &RpcSpanLayer,
+            request("/test.Service/Stream"),
+            || with_trailers("0"),
+            false,
+        )
+        .await;
+
+        assert_eq!(field(&fields, "rpc.grpc.status_code").unwrap(), "1");
+    }
+
+    #[tokio::test]
+    async fn handlers_run_in_the_trace_context_of_the_call() {
+        let inner = tower::service_fn(|req: Request<Body>| async move {
+            let ctx = req.extensions().get::<TraceContext>().copied();
+            assert_eq!(ctx, TraceContext::current());
+            Ok::<_, Infallible>(Response::new(Body::new(ctx.unwrap().to_traceparent())))
+        });
+
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let mut req = request("/test.Service/Method");
+        req.headers_mut()
+            .insert("traceparent", HeaderValue::from_static(traceparent));
+
+        let res = RpcSpanLayer.layer(inner).oneshot(req).await.unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, traceparent);
+    }
+}
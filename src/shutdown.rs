@@ -0,0 +1,285 @@
+//! Graceful shutdown of the function instance. Lambda only signals the runtime ahead of reclaiming
+//! the execution environment when an extension is registered, so an internal extension subscribed
+//! to no events is registered with the Extensions API. The runtime process then receives `SIGTERM`,
+//! and has until the end of the shutdown budget to run the hooks before `SIGKILL`.
+
+use crate::lifecycle::{self, LifecycleHook};
+use http::Method;
+use http::header::CONTENT_TYPE;
+use lambda_http::tracing::{info, warn};
+use lambda_runtime::Error;
+use lambda_runtime_api_client::body::Body;
+use lambda_runtime_api_client::{Client, build_request};
+use std::future::{Future, pending};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const REGISTER_PATH: &str = "/2020-01-01/extension/register";
+const NEXT_EVENT_PATH: &str = "/2020-01-01/extension/event/next";
+const EXTENSION_NAME_HEADER: &str = "lambda-extension-name";
+const EXTENSION_ID_HEADER: &str = "lambda-extension-identifier";
+
+/// Internal extension names must be unique within the function
+const EXTENSION_NAME: &str = "_lambda-grpc-web-graceful-shutdown";
+
+/// The shutdown budget Lambda allows when only internal extensions are registered
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(500);
+
+type ShutdownFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Resolves when the function instance is to shut down, in place of [`TerminationSignal`]
+type ShutdownSignal = Arc<dyn Fn() -> ShutdownFuture + Send + Sync>;
+
+#[derive(Clone)]
+pub(crate) struct GracefulShutdown {
+    hooks: Vec<LifecycleHook>,
+    timeout: Duration,
+    signal: Option<ShutdownSignal>,
+}
+
+impl Default for GracefulShutdown {
+    fn default() -> Self {
+        Self {
+            hooks: Vec::new(),
+            timeout: DEFAULT_TIMEOUT,
+            signal: None,
+        }
+    }
+}
+
+impl GracefulShutdown {
+    pub(crate) fn push(&mut self, hook: LifecycleHook) {
+        self.hooks.push(hook);
+    }
+
+    pub(crate) fn timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub(crate) fn signal<F, Fut>(&mut self, signal: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.signal = Some(Arc::new(move || Box::pin(signal())));
+    }
+
+    /// Drives the lambda runtime until the function instance is shut down, then runs the hooks.
+    /// Shutdown follows the signal when one is set, or else [`TerminationSignal`]. Without hooks
+    /// there is nothing to shut down, so no extension is registered.
+    pub(crate) async fn run(
+        self,
+        runtime: impl Future<Output = Result<(), Error>>,
+    ) -> Result<(), Error> {
+        if self.hooks.is_empty() {
+            return runtime.await;
+        }
+
+        // listen ahead of registering, as the signal may follow as soon as the init completes
+        let signal: ShutdownFuture = match &self.signal {
+            Some(signal) => signal(),
+            None => match TerminationSignal::listen() {
+                Ok(mut signal) => Box::pin(async move { signal.recv().await }),
+                Err(err) => return Err(lifecycle::fail_init(err.into()).await),
+            },
+        };
+        let extension = match Extension::register().await {
+            Ok(extension) => extension,
+            Err(err) => return Err(lifecycle::fail_init(err).await),
+        };
+
+        tokio::select! {
+            res = runtime => res,
+            err = extension.idle() => Err(err),
+            () = signal => {
+                run_hooks(&self.hooks, self.timeout).await;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Runs the hooks in order within the timeout, carrying on past those that fail
+async fn run_hooks(hooks: &[LifecycleHook], timeout: Duration) {
+    let start = Instant::now();
+    let run = async {
+        for hook in hooks {
+            if let Err(err) = hook().await {
+                warn!(error = %err, "shutdown hook failed");
+            }
+        }
+    };
+
+    match tokio::time::timeout(timeout, run).await {
+        Ok(()) => info!(
+            duration_ms = start.elapsed().as_millis() as u64,
+            "shutdown hooks completed"
+        ),
+        Err(_) => warn!(
+            timeout_ms = timeout.as_millis() as u64,
+            "shutdown hooks timed out"
+        ),
+    }
+}
+
+struct Extension {
+    client: Client,
+    id: String,
+}
+
+impl Extension {
+    async fn register() -> Result<Self, Error> {
+        let client = Client::builder().build()?;
+
+        let req = build_request()
+            .method(Method::POST)
+            .uri(REGISTER_PATH)
+            .header(EXTENSION_NAME_HEADER, EXTENSION_NAME)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(String::from(r#"{"events":[]}"#)))?;
+
+        let res = client.call(req).await?;
+        if !res.status().is_success() {
+            return Err(format!("extension registration responded {}", res.status()).into());
+        }
+
+        let id = res
+            .headers()
+            .get(EXTENSION_ID_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .ok_or("extension registration responded without an identifier")?
+            .to_string();
+
+        Ok(Self { client, id })
+    }
+
+    /// Long polls for the next event, which tells Lambda the extension completed its init. As the
+    /// extension is subscribed to no events, the poll is held open for the life of the instance.
+    /// Until the first poll succeeds the init can't complete, so its failure fails the init.
+    async fn idle(self) -> Error {
+        let mut initialized = false;
+        loop {
+            match self.next_event().await {
+                Ok(()) => initialized = true,
+                Err(err) if !initialized => return lifecycle::fail_init(err).await,
+                Err(err) => {
+                    warn!(error = %err, "extension event poll failed");
+                    return pending().await;
+                }
+            }
+        }
+    }
+
+    async fn next_event(&self) -> Result<(), Error> {
+        let req = build_request()
+            .method(Method::GET)
+            .uri(NEXT_EVENT_PATH)
+            .header(EXTENSION_ID_HEADER, &self.id)
+            .body(Body::empty())?;
+
+        let res = self.client.call(req).await?;
+        if !res.status().is_success() {
+            return Err(format!("runtime api responded {}", res.status()).into());
+        }
+        Ok(())
+    }
+}
+
+/// `SIGTERM` from Lambda, or `SIGINT` when run in a terminal
+struct TerminationSignal {
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    interrupt: tokio::signal::unix::Signal,
+}
+
+impl TerminationSignal {
+    #[cfg(unix)]
+    fn listen() -> std::io::Result<Self> {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        Ok(Self {
+            terminate: signal(SignalKind::terminate())?,
+            interrupt: signal(SignalKind::interrupt())?,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn listen() -> std::io::Result<Self> {
+        Ok(Self {})
+    }
+
+    #[cfg(unix)]
+    async fn recv(&mut self) {
+        tokio::select! {
+            _ = self.terminate.recv() => {}
+            _ = self.interrupt.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn recv(&mut self) {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn recording(calls: &Arc<Mutex<Vec<&'static str>>>, name: &'static str) -> LifecycleHook {
+        let calls = calls.clone();
+        lifecycle::hook(move || {
+            let calls = calls.clone();
+            async move {
+                calls.lock().unwrap().push(name);
+                if name == "pool" {
+                    Err("pool busy")
+                } else {
+                    Ok(())
+                }
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn runs_every_hook_past_failures() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let hooks = [
+            recording(&calls, "metrics"),
+            recording(&calls, "pool"),
+            recording(&calls, "cache"),
+        ];
+
+        run_hooks(&hooks, DEFAULT_TIMEOUT).await;
+
+        assert_eq!(*calls.lock().unwrap(), ["metrics", "pool", "cache"]);
+    }
+
+    #[tokio::test]
+    async fn abandons_hooks_at_the_timeout() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let slow = lifecycle::hook(|| async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok::<_, Error>(())
+        });
+        let hooks = [slow, recording(&calls, "metrics")];
+
+        let start = Instant::now();
+        run_hooks(&hooks, Duration::from_millis(20)).await;
+
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(calls.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn drives_the_runtime_alone_without_hooks() {
+        let res = GracefulShutdown::default()
+            .run(async { Err::<(), Error>("runtime exited".into()) })
+            .await;
+
+        assert_eq!(res.unwrap_err().to_string(), "runtime exited");
+    }
+}
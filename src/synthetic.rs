@@ -0,0 +1,26 @@
+//! Synthetic lambda invocation context, for running the server outside of the lambda runtime
+
+use lambda_runtime::Context;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Lambda's own default function timeout
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub(crate) const FUNCTION_ARN: &str = "arn:aws:lambda:local:000000000000:function:lambda-grpc-web";
+
+/// A lambda context as the runtime would provide it for an invocation starting now
+pub(crate) fn context(timeout: Duration) -> Context {
+    static INVOCATION: AtomicU64 = AtomicU64::new(0);
+
+    let deadline = SystemTime::now() + timeout;
+
+    let mut ctx = Context::default();
+    ctx.request_id = format!("local-{}", INVOCATION.fetch_add(1, Ordering::Relaxed) + 1);
+    ctx.deadline = deadline
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    ctx.invoked_function_arn = FUNCTION_ARN.to_string();
+    ctx
+}
@@ -0,0 +1,183 @@
+//! In-process test harness, driving a [`LambdaRouter`] exactly as the lambda runtime would for a
+//! Function URL invocation, without the Lambda Runtime API, `cargo lambda` or a deployed function.
+//!
+//! Each call is encoded as grpc-web, wrapped in an API Gateway v2 / Function URL event, parsed by
+//! `lambda_http` as the runtime would, and handled with a synthetic lambda `Context`. Use
+//! [`TestTransport`] as the transport of a generated tonic client:
+//!
+//! ```ignore
+//! let transport = TestTransport::new(LambdaServer::builder().add_service(GreeterServer::new(greeter)));
+//! let mut client = GreeterClient::new(transport);
+//! ```
+//!
+//! To exercise the lambda runtime client itself, serve the router against a [`MockRuntimeApi`].
+
+mod runtime_api;
+mod text;
+
+pub use runtime_api::{
+    InitError, Invocation, InvocationResult, MockRuntimeApi, RegisteredExtension, RuntimeResponse,
+};
+
+use crate::function_url::FunctionUrl;
+use crate::lambda_server_builder::{LambdaRouter, LambdaService, into_grpc_request};
+use crate::synthetic;
+use bytes::Bytes;
+use http::{Request, Response};
+use http_body_util::BodyExt;
+use lambda_http::RequestExt;
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use text::TextEncoding;
+use tonic::body::Body;
+use tonic::service::Routes;
+use tonic_web::GrpcWebClientLayer;
+use tower::{BoxError, Layer, Service};
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = Result<T, BoxError>> + Send>>;
+
+const FUNCTION_URL: FunctionUrl = FunctionUrl {
+    domain_prefix: "testingfunctionurl",
+    region: "us-east-1",
+};
+
+/// Invokes the router with a raw HTTP request, as sent by the client to the Function URL.
+/// Responses are returned as the handler produces them, i.e. still grpc-web encoded.
+///
+/// Prefer [`TestTransport`] for tonic clients, this is for asserting on the wire format.
+#[derive(Clone)]
+pub struct TestInvoker {
+    svc: LambdaService,
+    timeout: Duration,
+    base64_encoded: bool,
+}
+
+impl TestInvoker {
+    /// Panics if the router would fail to serve, i.e. when its authorization policy names a
+    /// method that isn't served.
+    pub fn new<L>(router: LambdaRouter<L>) -> Self
+    where
+        L: Layer<Routes>,
+        L::Service: Service<
+                Request<Body>,
+                Response = Response<Body>,
+                Error = Infallible,
+                Future: Send + 'static,
+            > + Clone
+            + Send
+            + 'static,
+    {
+        let timeout = router.synthetic_timeout();
+        if let Err(err) = router.validate() {
+            panic!("invalid router: {err}");
+        }
+
+        Self {
+            svc: router.into_service(),
+            timeout,
+            base64_encoded: true,
+        }
+    }
+
+    /// Whether request bodies are delivered base64 encoded (`isBase64Encoded`), as Function URLs
+    /// do for content types they don't consider text. Defaults to `true`.
+    pub fn base64_encoded(mut self, encoded: bool) -> Self {
+        self.base64_encoded = encoded;
+        self
+    }
+}
+
+impl<B> Service<Request<B>> for TestInvoker
+where
+    B: http_body::Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = BoxFuture<Self::Response>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let mut svc = self.svc.clone();
+        let timeout = self.timeout;
+        let base64_encoded = self.base64_encoded;
+
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = body.collect().await.map_err(Into::into)?.to_bytes();
+
+            let event = FUNCTION_URL.event(&parts, &body, base64_encoded);
+            let req = lambda_http::request::from_str(&event.to_string())?
+                .with_lambda_context(synthetic::context(timeout));
+
+            Ok(svc.call(into_grpc_request(req)).await?)
+        })
+    }
+}
+
+/// A tonic client transport calling the router in-process, see the [module docs](self)
+#[derive(Clone)]
+pub struct TestTransport {
+    invoker: TestInvoker,
+    text: bool,
+}
+
+impl TestTransport {
+    pub fn new<L>(router: LambdaRouter<L>) -> Self
+    where
+        L: Layer<Routes>,
+        L::Service: Service<
+                Request<Body>,
+                Response = Response<Body>,
+                Error = Infallible,
+                Future: Send + 'static,
+            > + Clone
+            + Send
+            + 'static,
+    {
+        Self::from(TestInvoker::new(router))
+    }
+
+    /// Encode calls as grpc-web-text, as browser clients unable to read binary streams do
+    pub fn text(mut self) -> Self {
+        self.text = true;
+        self
+    }
+}
+
+impl From<TestInvoker> for TestTransport {
+    fn from(invoker: TestInvoker) -> Self {
+        Self {
+            invoker,
+            text: false,
+        }
+    }
+}
+
+impl Service<Request<Body>> for TestTransport {
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = BoxFuture<Self::Response>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let client = GrpcWebClientLayer::new();
+
+        if self.text {
+            let fut = client.layer(TextEncoding(self.invoker.clone())).call(req);
+            Box::pin(async move { Ok(fut.await?.map(Body::new)) })
+        } else {
+            let fut = client.layer(self.invoker.clone()).call(req);
+            Box::pin(async move { Ok(fut.await?.map(Body::new)) })
+        }
+    }
+}
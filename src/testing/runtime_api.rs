@@ -0,0 +1,544 @@
+//! A local stand-in for the Lambda Runtime API, for end-to-end tests of [`LambdaRouter::serve`]
+//! and [`LambdaRouter::serve_buffered`] through the real lambda runtime client.
+//!
+//! It also stands in for the registration and event polling of the Lambda Extensions API, see
+//! [`MockRuntimeApi::extensions`].
+//!
+//! [`MockRuntimeApi::start`] points `AWS_LAMBDA_RUNTIME_API` at the mock, so the router must be
+//! served after it is started, see [`MockRuntimeApi::run`]. The environment is process wide, so
+//! mocks are serialised: a second `start` waits until the previous mock is dropped. Modifying it
+//! is only sound while no other thread reads or writes it, so keep tests starting mocks apart from
+//! tests touching the environment, i.e. in their own test binary.
+//!
+//! ```ignore
+//! let runtime = MockRuntimeApi::start().await?;
+//!
+//! let response = runtime
+//!     .run(router.serve(), async {
+//!         runtime.invoke(request).result().await.into_response()
+//!     })
+//!     .await;
+//!
+//! assert_eq!(response.prelude().unwrap()["statusCode"], 200);
+//! ```
+//!
+//! [`LambdaRouter::serve`]: crate::LambdaRouter::serve
+//! [`LambdaRouter::serve_buffered`]: crate::LambdaRouter::serve_buffered
+
+use crate::synthetic;
+use bytes::{Bytes, BytesMut};
+use http::{HeaderMap, Method, Request, Response, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper_util::rt::TokioIo;
+use lambda_runtime::Error;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tokio::sync::{OwnedMutexGuard, mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+
+const INVOCATION_PATH: &str = "/2018-06-01/runtime/invocation/";
+const INIT_ERROR_PATH: &str = "/2018-06-01/runtime/init/error";
+const EXTENSION_REGISTER_PATH: &str = "/2020-01-01/extension/register";
+const EXTENSION_NEXT_PATH: &str = "/2020-01-01/extension/event/next";
+const EXTENSION_ID_HEADER: &str = "lambda-extension-identifier";
+const STREAMING_RESPONSE_MODE: &str = "lambda-runtime-function-response-mode";
+const PRELUDE_DELIMITER: [u8; 8] = [0; 8];
+
+/// Pause after a failed accept before the next, rather than spinning on a persistent error
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Environment the lambda runtime requires to start, set when not already present
+const RUNTIME_ENV: [(&str, &str); 3] = [
+    ("AWS_LAMBDA_FUNCTION_NAME", "lambda-grpc-web"),
+    ("AWS_LAMBDA_FUNCTION_MEMORY_SIZE", "128"),
+    ("AWS_LAMBDA_FUNCTION_VERSION", "$LATEST"),
+];
+
+/// A running mock of the Lambda Runtime API, see the [module docs](self)
+pub struct MockRuntimeApi {
+    addr: SocketAddr,
+    state: Arc<State>,
+    timeout: Duration,
+    server: JoinHandle<()>,
+    _env: OwnedMutexGuard<()>,
+}
+
+struct State {
+    queue: tokio::sync::Mutex<mpsc::UnboundedReceiver<PendingInvocation>>,
+    enqueue: mpsc::UnboundedSender<PendingInvocation>,
+    in_flight: Mutex<HashMap<String, oneshot::Sender<InvocationResult>>>,
+    init_error: Mutex<Option<InitError>>,
+    /// Registered extensions, by identifier
+    extensions: watch::Sender<Vec<(String, RegisteredExtension)>>,
+}
+
+struct PendingInvocation {
+    request_id: String,
+    deadline: SystemTime,
+    event: Value,
+    result: oneshot::Sender<InvocationResult>,
+}
+
+impl MockRuntimeApi {
+    /// Starts the mock on an ephemeral local port and points `AWS_LAMBDA_RUNTIME_API` at it
+    pub async fn start() -> io::Result<Self> {
+        static ENV_LOCK: OnceLock<Arc<tokio::sync::Mutex<()>>> = OnceLock::new();
+
+        let env = ENV_LOCK
+            .get_or_init(Default::default)
+            .clone()
+            .lock_owned()
+            .await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        // SAFETY: `set_var` is only sound while no other thread reads or writes the environment.
+        // `ENV_LOCK` rules out other mocks, and the runtimes driven by their `run`, which end
+        // before the mock releases it. Nothing else in the process is assumed to touch the
+        // environment concurrently, as the module docs require of the tests.
+        unsafe {
+            std::env::set_var("AWS_LAMBDA_RUNTIME_API", addr.to_string());
+            for (key, value) in RUNTIME_ENV {
+                if std::env::var_os(key).is_none() {
+                    std::env::set_var(key, value);
+                }
+            }
+        }
+
+        let (enqueue, queue) = mpsc::unbounded_channel();
+        let state = Arc::new(State {
+            queue: tokio::sync::Mutex::new(queue),
+            enqueue,
+            in_flight: Mutex::default(),
+            init_error: Mutex::default(),
+            extensions: watch::Sender::default(),
+        });
+
+        let server = tokio::spawn(serve(listener, state.clone()));
+
+        Ok(Self {
+            addr,
+            state,
+            timeout: synthetic::DEFAULT_TIMEOUT,
+            server,
+            _env: env,
+        })
+    }
+
+    /// Function timeout used for the deadline of subsequent invocations. Defaults to 3s.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Drives `server`, i.e. `router.serve()`, until `test` completes, returning its output.
+    /// Both are polled in the calling task, as the runtime future can't be spawned.
+    ///
+    /// Panics if the server exits first.
+    pub async fn run<T>(
+        &self,
+        server: impl Future<Output = Result<(), Error>>,
+        test: impl Future<Output = T>,
+    ) -> T {
+        tokio::select! {
+            res = server => panic!("lambda runtime exited: {res:?}"),
+            out = test => out,
+        }
+    }
+
+    /// The error the runtime reported to `/init/error`, when init failed
+    pub fn init_error(&self) -> Option<InitError> {
+        self.state.init_error.lock().unwrap().clone()
+    }
+
+    /// The extensions registered with the Extensions API, in registration order
+    pub fn extensions(&self) -> Vec<RegisteredExtension> {
+        self.state
+            .extensions
+            .borrow()
+            .iter()
+            .map(|(_, extension)| extension.clone())
+            .collect()
+    }
+
+    /// Queues an invocation with the Function URL event for the request, as sent by a client
+    pub fn invoke(&self, req: Request<Bytes>) -> Invocation {
+        let (parts, body) = req.into_parts();
+        self.invoke_event(super::FUNCTION_URL.event(&parts, &body, true))
+    }
+
+    /// Queues an invocation with an arbitrary event payload
+    pub fn invoke_event(&self, event: Value) -> Invocation {
+        static INVOCATION: AtomicU64 = AtomicU64::new(0);
+
+        let request_id = format!("mock-{}", INVOCATION.fetch_add(1, Ordering::Relaxed) + 1);
+        let (result, rx) = oneshot::channel();
+
+        let _ = self.state.enqueue.send(PendingInvocation {
+            request_id: request_id.clone(),
+            deadline: SystemTime::now() + self.timeout,
+            event,
+            result,
+        });
+
+        Invocation { request_id, rx }
+    }
+}
+
+impl Drop for MockRuntimeApi {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// A queued invocation, resolving once the runtime posts its response or error
+pub struct Invocation {
+    request_id: String,
+    rx: oneshot::Receiver<InvocationResult>,
+}
+
+impl Invocation {
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    /// Waits for the runtime to complete the invocation. Panics if the mock is dropped first.
+    pub async fn result(self) -> InvocationResult {
+        self.rx
+            .await
+            .expect("mock runtime api dropped before the invocation completed")
+    }
+}
+
+/// What the runtime reported for an invocation
+#[derive(Debug)]
+pub enum InvocationResult {
+    /// Posted to `/invocation/{id}/response`
+    Response(RuntimeResponse),
+    /// Posted to `/invocation/{id}/error`
+    Error {
+        error_type: Option<String>,
+        body: Value,
+    },
+}
+
+impl InvocationResult {
+    /// The response, panicking if the runtime reported an error
+    pub fn into_response(self) -> RuntimeResponse {
+        match self {
+            Self::Response(res) => res,
+            Self::Error { error_type, body } => {
+                panic!("invocation failed with {error_type:?}: {body}")
+            }
+        }
+    }
+}
+
+/// An init failure as posted by the runtime
+#[derive(Debug, Clone)]
+pub struct InitError {
+    pub error_type: Option<String>,
+    pub body: Value,
+}
+
+/// An extension registered with the Extensions API. Events are never delivered: the event poll of
+/// every extension is held open, as when the function instance is not shutting down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisteredExtension {
+    /// The `Lambda-Extension-Name` registered with
+    pub name: String,
+    /// The events subscribed to
+    pub events: Vec<String>,
+    /// Whether the extension polled for its next event, completing its init
+    pub initialized: bool,
+}
+
+/// A response as posted by the runtime, with the body exactly as received
+#[derive(Debug)]
+pub struct RuntimeResponse {
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    pub trailers: Option<HeaderMap>,
+}
+
+impl RuntimeResponse {
+    /// Whether the response was sent with the `RESPONSE_STREAM` invoke mode
+    pub fn is_streaming(&self) -> bool {
+        self.headers
+            .get(STREAMING_RESPONSE_MODE)
+            .is_some_and(|v| v == "streaming")
+    }
+
+    /// The JSON metadata prelude (status code, headers & cookies) of a streamed response
+    pub fn prelude(&self) -> Option<Value> {
+        let (prelude, _) = self.split_prelude()?;
+        serde_json::from_slice(prelude).ok()
+    }
+
+    /// The streamed response body following the prelude, or the full buffered response
+    pub fn payload(&self) -> &[u8] {
+        match self.split_prelude() {
+            Some((_, payload)) => payload,
+            None => &self.body,
+        }
+    }
+
+    fn split_prelude(&self) -> Option<(&[u8], &[u8])> {
+        if !self.is_streaming() {
+            return None;
+        }
+
+        let end = self
+            .body
+            .windows(PRELUDE_DELIMITER.len())
+            .position(|w| w == PRELUDE_DELIMITER)?;
+
+        Some((
+            &self.body[..end],
+            &self.body[end + PRELUDE_DELIMITER.len()..],
+        ))
+    }
+}
+
+async fn serve(listener: TcpListener, state: Arc<State>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            // i.e. out of file descriptors, which frees up as connections close
+            Err(_) => {
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        let state = state.clone();
+
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |req| handle(state.clone(), req));
+            let _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+async fn handle(
+    state: Arc<State>,
+    req: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let path = req.uri().path().to_string();
+    let route = path
+        .strip_prefix(INVOCATION_PATH)
+        .map(|rest| rest.split_once('/').unwrap_or((rest, "")));
+
+    let res = match (req.method(), route) {
+        (&Method::GET, Some(("next", ""))) => next_invocation(&state).await,
+        (&Method::POST, Some((request_id, "response"))) => {
+            let (parts, body) = req.into_parts();
+            let (body, trailers) = collect(body).await;
+            complete(
+                &state,
+                request_id,
+                InvocationResult::Response(RuntimeResponse {
+                    headers: parts.headers,
+                    body,
+                    trailers,
+                }),
+            )
+        }
+        (&Method::POST, Some((request_id, "error"))) => {
+            let request_id = request_id.to_string();
+            let (error_type, body) = read_error(req).await;
+            complete(
+                &state,
+                &request_id,
+                InvocationResult::Error { error_type, body },
+            )
+        }
+        (&Method::POST, None) if path == INIT_ERROR_PATH => {
+            let (error_type, body) = read_error(req).await;
+            *state.init_error.lock().unwrap() = Some(InitError { error_type, body });
+            status(StatusCode::ACCEPTED)
+        }
+        (&Method::POST, None) if path == EXTENSION_REGISTER_PATH => {
+            register_extension(&state, req).await
+        }
+        (&Method::GET, None) if path == EXTENSION_NEXT_PATH => {
+            next_extension_event(&state, &req).await
+        }
+        _ => status(StatusCode::NOT_FOUND),
+    };
+
+    Ok(res)
+}
+
+/// The error type header and JSON body of a reported error
+async fn read_error(req: Request<Incoming>) -> (Option<String>, Value) {
+    let error_type = req
+        .headers()
+        .get("lambda-runtime-function-error-type")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let (body, _) = collect(req.into_body()).await;
+    let body = serde_json::from_slice(&body)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned()));
+
+    (error_type, body)
+}
+
+async fn register_extension(state: &State, req: Request<Incoming>) -> Response<Full<Bytes>> {
+    static EXTENSION: AtomicU64 = AtomicU64::new(0);
+
+    let Some(name) = req
+        .headers()
+        .get("lambda-extension-name")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+    else {
+        return status(StatusCode::BAD_REQUEST);
+    };
+
+    let (body, _) = collect(req.into_body()).await;
+    let Some(events) = serde_json::from_slice::<Value>(&body)
+        .ok()
+        .and_then(|body| serde_json::from_value::<Vec<String>>(body["events"].clone()).ok())
+    else {
+        return status(StatusCode::BAD_REQUEST);
+    };
+
+    let id = format!(
+        "mock-extension-{}",
+        EXTENSION.fetch_add(1, Ordering::Relaxed) + 1
+    );
+    state.extensions.send_modify(|extensions| {
+        extensions.push((
+            id.clone(),
+            RegisteredExtension {
+                name,
+                events,
+                initialized: false,
+            },
+        ))
+    });
+
+    let body = serde_json::json!({
+        "functionName": std::env::var("AWS_LAMBDA_FUNCTION_NAME").unwrap_or_default(),
+        "functionVersion": std::env::var("AWS_LAMBDA_FUNCTION_VERSION").unwrap_or_default(),
+        "handler": "bootstrap",
+    });
+
+    Response::builder()
+        .header(EXTENSION_ID_HEADER, id)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body.to_string())))
+        .expect("valid response")
+}
+
+async fn next_extension_event(state: &State, req: &Request<Incoming>) -> Response<Full<Bytes>> {
+    let id = req
+        .headers()
+        .get(EXTENSION_ID_HEADER)
+        .and_then(|v| v.to_str().ok());
+
+    let mut known = false;
+    state.extensions.send_if_modified(|extensions| {
+        let extension = extensions
+            .iter_mut()
+            .find(|(extension_id, _)| Some(extension_id.as_str()) == id);
+        known = extension.is_some();
+
+        match extension {
+            Some((_, extension)) if !extension.initialized => {
+                extension.initialized = true;
+                true
+            }
+            _ => false,
+        }
+    });
+
+    if !known {
+        return status(StatusCode::FORBIDDEN);
+    }
+    std::future::pending().await
+}
+
+async fn next_invocation(state: &State) -> Response<Full<Bytes>> {
+    // as with lambda, the init completes once every registered extension polled for an event
+    let _ = state
+        .extensions
+        .subscribe()
+        .wait_for(|extensions| extensions.iter().all(|(_, ext)| ext.initialized))
+        .await;
+
+    let Some(invocation) = state.queue.lock().await.recv().await else {
+        return status(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+
+    state
+        .in_flight
+        .lock()
+        .unwrap()
+        .insert(invocation.request_id.clone(), invocation.result);
+
+    let deadline_ms = invocation
+        .deadline
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+
+    Response::builder()
+        .header("lambda-runtime-aws-request-id", invocation.request_id)
+        .header("lambda-runtime-deadline-ms", deadline_ms.to_string())
+        .header(
+            "lambda-runtime-invoked-function-arn",
+            synthetic::FUNCTION_ARN,
+        )
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(invocation.event.to_string())))
+        .expect("valid response")
+}
+
+fn complete(state: &State, request_id: &str, result: InvocationResult) -> Response<Full<Bytes>> {
+    match state.in_flight.lock().unwrap().remove(request_id) {
+        Some(tx) => {
+            let _ = tx.send(result);
+            status(StatusCode::ACCEPTED)
+        }
+        None => status(StatusCode::BAD_REQUEST),
+    }
+}
+
+async fn collect(mut body: Incoming) -> (Bytes, Option<HeaderMap>) {
+    let mut buf = BytesMut::new();
+    let mut trailers = None;
+
+    while let Some(Ok(frame)) = body.frame().await {
+        match frame.into_data() {
+            Ok(data) => buf.extend_from_slice(&data),
+            Err(frame) => trailers = frame.into_trailers().ok(),
+        }
+    }
+
+    (buf.freeze(), trailers)
+}
+
+fn status(status: StatusCode) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .body(Full::default())
+        .expect("valid response")
+}
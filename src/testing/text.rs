@@ -0,0 +1,177 @@
+//! Client side grpc-web-text encoding, as used by browser clients unable to read binary streams.
+//! `tonic_web` clients only speak binary grpc-web, so this sits between the grpc-web client layer
+//! and the invoker, translating to and from the text encoding on the wire.
+
+use super::{BoxFuture, TestInvoker};
+use crate::grpc_web::{GRPC_WEB_TEXT, is_text};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use bytes::{Bytes, BytesMut};
+use http::header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE};
+use http::{HeaderMap, HeaderValue, Request, Response};
+use http_body::Frame;
+use http_body_util::{BodyExt, Full};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use tonic::Status;
+use tonic::body::Body;
+use tower::{BoxError, Service};
+
+const GRPC_WEB: &str = "application/grpc-web";
+
+#[derive(Clone)]
+pub(super) struct TextEncoding(pub(super) TestInvoker);
+
+impl<B> Service<Request<B>> for TextEncoding
+where
+    B: http_body::Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = BoxFuture<Self::Response>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let mut invoker = self.0.clone();
+
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+            let body = body.collect().await.map_err(Into::into)?.to_bytes();
+
+            replace_content_type(&mut parts.headers, GRPC_WEB, GRPC_WEB_TEXT);
+            if let Some(content_type) = parts.headers.get(CONTENT_TYPE).cloned() {
+                parts.headers.insert(ACCEPT, content_type);
+            }
+            parts.headers.remove(CONTENT_LENGTH);
+
+            let body = Full::new(Bytes::from(STANDARD.encode(body)));
+            let res = invoker.call(Request::from_parts(parts, body)).await?;
+
+            if !is_text(res.headers()) {
+                return Ok(res);
+            }
+
+            let (mut parts, body) = res.into_parts();
+            replace_content_type(&mut parts.headers, GRPC_WEB_TEXT, GRPC_WEB);
+
+            Ok(Response::from_parts(
+                parts,
+                Body::new(Base64Body {
+                    inner: body,
+                    buf: BytesMut::new(),
+                }),
+            ))
+        })
+    }
+}
+
+fn replace_content_type(headers: &mut HeaderMap, from: &str, to: &str) {
+    let replaced = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix(from))
+        .and_then(|suffix| HeaderValue::try_from(format!("{to}{suffix}")).ok());
+
+    if let Some(value) = replaced {
+        headers.insert(CONTENT_TYPE, value);
+    }
+}
+
+/// Decodes a grpc-web-text response body. Servers may pad the base64 of each chunk, so decoding
+/// stops at every padded quantum rather than treating the body as one base64 string.
+struct Base64Body {
+    inner: Body,
+    buf: BytesMut,
+}
+
+impl Base64Body {
+    fn decode_chunk(&mut self) -> Result<Option<Bytes>, Status> {
+        let aligned = self.buf.len() / 4 * 4;
+        let end = match self.buf[..aligned].iter().position(|b| *b == b'=') {
+            Some(padding) => (padding / 4 + 1) * 4,
+            None => aligned,
+        };
+
+        if end == 0 {
+            return Ok(None);
+        }
+
+        STANDARD
+            .decode(self.buf.split_to(end))
+            .map(|decoded| Some(decoded.into()))
+            .map_err(|err| Status::internal(format!("malformed grpc-web-text response: {err}")))
+    }
+}
+
+impl http_body::Body for Base64Body {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        loop {
+            if let Some(decoded) = self.decode_chunk()? {
+                return Poll::Ready(Some(Ok(Frame::data(decoded))));
+            }
+
+            match ready!(Pin::new(&mut self.inner).poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => self.buf.extend_from_slice(&data),
+                    Err(frame) => return Poll::Ready(Some(Ok(frame))),
+                },
+                Some(Err(status)) => return Poll::Ready(Some(Err(status))),
+                None if self.buf.is_empty() => return Poll::Ready(None),
+                None => {
+                    return Poll::Ready(Some(Err(Status::internal(
+                        "malformed grpc-web-text response",
+                    ))));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::StreamBody;
+
+    async fn decode(chunks: Vec<&'static str>) -> Vec<u8> {
+        let frames = chunks
+            .into_iter()
+            .map(|chunk| Ok::<_, Status>(Frame::data(Bytes::from_static(chunk.as_bytes()))));
+        let inner = Body::new(StreamBody::new(futures_util::stream::iter(frames)));
+
+        Base64Body {
+            inner,
+            buf: BytesMut::new(),
+        }
+        .collect()
+        .await
+        .unwrap()
+        .to_bytes()
+        .to_vec()
+    }
+
+    #[tokio::test]
+    async fn decodes_padded_chunks() {
+        assert_eq!(
+            decode(vec!["AAAAAAJoaQ==", "gAAAAA8="]).await,
+            b"\x00\x00\x00\x00\x02hi\x80\x00\x00\x00\x0f"
+        );
+    }
+
+    #[tokio::test]
+    async fn decodes_chunks_split_mid_quantum() {
+        assert_eq!(
+            decode(vec!["AAAAAA", "JoaQ=", "="]).await,
+            b"\x00\x00\x00\x00\x02hi"
+        );
+    }
+}
@@ -0,0 +1,511 @@
+//! Trace context propagation between functions, in the AWS X-Ray, W3C `traceparent` and gRPC
+//! `grpc-trace-bin` formats.
+
+use base64::Engine;
+use base64::alphabet::STANDARD;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use http::{HeaderMap, HeaderValue, Request};
+use lambda_runtime::Context as LambdaContext;
+use std::collections::hash_map::RandomState;
+use std::fmt::Write;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use tonic::metadata::{BinaryMetadataValue, MetadataMap};
+use tower::{Layer, Service};
+
+const XRAY_ENV: &str = "_X_AMZN_TRACE_ID";
+const XRAY_HEADER: &str = "x-amzn-trace-id";
+const TRACEPARENT_HEADER: &str = "traceparent";
+const GRPC_TRACE_BIN_HEADER: &str = "grpc-trace-bin";
+
+/// Binary metadata is base64 encoded, padded or not
+const BINARY_METADATA: GeneralPurpose = GeneralPurpose::new(
+    &STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+tokio::task_local! {
+    static CURRENT: TraceContext;
+}
+
+/// The trace a call belongs to and the span it was made from, inserted into the request
+/// extensions of every call. Read from the `traceparent` or `grpc-trace-bin` metadata sent by the
+/// caller, and otherwise from the X-Ray trace of the invocation.
+///
+/// While a call is handled it is also the [`TraceContext::current`] context, propagated to the
+/// calls made with clients behind a [`TraceContextLayer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    trace_id: [u8; 16],
+    parent_id: [u8; 8],
+    sampled: Option<bool>,
+}
+
+impl TraceContext {
+    /// A context with the ids, which must not be all zero. `sampled` is `None` when the caller
+    /// left the sampling decision to the callee, as X-Ray allows.
+    pub fn new(trace_id: [u8; 16], parent_id: [u8; 8], sampled: Option<bool>) -> Option<Self> {
+        (trace_id != [0; 16] && parent_id != [0; 8]).then_some(Self {
+            trace_id,
+            parent_id,
+            sampled,
+        })
+    }
+
+    pub fn trace_id(&self) -> [u8; 16] {
+        self.trace_id
+    }
+
+    /// Id of the span the call was made from
+    pub fn parent_id(&self) -> [u8; 8] {
+        self.parent_id
+    }
+
+    pub fn sampled(&self) -> Option<bool> {
+        self.sampled
+    }
+
+    /// The context of the call being handled, or outside of calls the X-Ray trace of the
+    /// invocation from the `_X_AMZN_TRACE_ID` environment variable
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(|ctx| *ctx).ok().or_else(|| {
+            std::env::var(XRAY_ENV)
+                .ok()
+                .and_then(|header| Self::from_xray(&header))
+        })
+    }
+
+    /// The same trace and sampling decision, with a new parent span id, for calls made from this
+    /// span
+    pub fn child(&self) -> Self {
+        Self {
+            parent_id: random_span_id(),
+            ..*self
+        }
+    }
+
+    /// Parses the X-Ray `Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1`
+    /// header
+    pub fn from_xray(header: &str) -> Option<Self> {
+        let (mut root, mut parent, mut sampled) = (None, None, None);
+        for field in header.split(';') {
+            let Some(field) = field.trim().split_once('=') else {
+                continue;
+            };
+            match field {
+                ("Root", value) => root = Some(value),
+                ("Parent", value) => parent = Some(value),
+                ("Sampled", "1") => sampled = Some(true),
+                ("Sampled", "0") => sampled = Some(false),
+                _ => {}
+            }
+        }
+
+        let (epoch, random) = root?.strip_prefix("1-")?.split_once('-')?;
+        if epoch.len() != 8 {
+            return None;
+        }
+
+        Self::new(
+            decode_hex(&format!("{epoch}{random}"))?,
+            decode_hex(parent?)?,
+            sampled,
+        )
+    }
+
+    /// The X-Ray header, without `Sampled` when the sampling decision is left to the callee
+    pub fn to_xray(&self) -> String {
+        let trace_id = encode_hex(&self.trace_id);
+        let mut header = format!(
+            "Root=1-{}-{};Parent={}",
+            &trace_id[..8],
+            &trace_id[8..],
+            encode_hex(&self.parent_id)
+        );
+        if let Some(sampled) = self.sampled {
+            let _ = write!(header, ";Sampled={}", sampled as u8);
+        }
+        header
+    }
+
+    /// Parses the W3C `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01` header. Headers of
+    /// later versions are read as version `00`, ignoring any fields it doesn't have.
+    pub fn from_traceparent(header: &str) -> Option<Self> {
+        let mut fields = header.trim().split('-');
+        let version = fields.next()?;
+        let (trace_id, parent_id, flags) = (fields.next()?, fields.next()?, fields.next()?);
+
+        match version {
+            "00" if fields.next().is_some() => return None,
+            "ff" => return None,
+            version if decode_hex::<1>(version).is_none() => return None,
+            _ => {}
+        }
+
+        let [flags] = decode_hex::<1>(flags)?;
+        Self::new(
+            decode_hex(trace_id)?,
+            decode_hex(parent_id)?,
+            Some(flags & 1 == 1),
+        )
+    }
+
+    /// The W3C header, unsampled unless sampled is set
+    pub fn to_traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            encode_hex(&self.trace_id),
+            encode_hex(&self.parent_id),
+            self.sampled.unwrap_or(false) as u8
+        )
+    }
+
+    /// Parses the OpenCensus binary format of the `grpc-trace-bin` metadata
+    pub fn from_grpc_trace_bin(bytes: &[u8]) -> Option<Self> {
+        let [0, 0, rest @ ..] = bytes else {
+            return None;
+        };
+        let (trace_id, rest) = rest.split_first_chunk::<16>()?;
+        let [1, rest @ ..] = rest else {
+            return None;
+        };
+        let (parent_id, rest) = rest.split_first_chunk::<8>()?;
+        let sampled = match rest {
+            [2, options, ..] => Some(options & 1 == 1),
+            _ => None,
+        };
+
+        Self::new(*trace_id, *parent_id, sampled)
+    }
+
+    /// The OpenCensus binary format, unsampled unless sampled is set
+    pub fn to_grpc_trace_bin(&self) -> [u8; 29] {
+        let mut bytes = [0; 29];
+        bytes[2..18].copy_from_slice(&self.trace_id);
+        bytes[18] = 1;
+        bytes[19..27].copy_from_slice(&self.parent_id);
+        bytes[27] = 2;
+        bytes[28] = self.sampled.unwrap_or(false) as u8;
+        bytes
+    }
+
+    /// The context propagated by the caller, or else the X-Ray trace of the invocation
+    pub(crate) fn from_request<B>(req: &Request<B>) -> Option<Self> {
+        let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+
+        header(TRACEPARENT_HEADER)
+            .and_then(Self::from_traceparent)
+            .or_else(|| {
+                let value = req.headers().get(GRPC_TRACE_BIN_HEADER)?;
+                let bytes = BINARY_METADATA.decode(value.as_bytes()).ok()?;
+                Self::from_grpc_trace_bin(&bytes)
+            })
+            .or_else(|| {
+                let ctx = req.extensions().get::<LambdaContext>()?;
+                Self::from_xray(ctx.xray_trace_id.as_deref()?)
+            })
+            .or_else(|| {
+                std::env::var(XRAY_ENV)
+                    .ok()
+                    .and_then(|header| Self::from_xray(&header))
+            })
+    }
+
+    /// Runs the future with this as the current context
+    pub(crate) fn scope<F: Future>(self, fut: F) -> impl Future<Output = F::Output> {
+        CURRENT.scope(self, fut)
+    }
+
+    /// Calls the function with this as the current context
+    pub(crate) fn sync_scope<R>(self, f: impl FnOnce() -> R) -> R {
+        CURRENT.sync_scope(self, f)
+    }
+
+    /// Adds the headers of every format, keeping any already set
+    fn inject(&self, headers: &mut HeaderMap) {
+        if !headers.contains_key(TRACEPARENT_HEADER) {
+            headers.insert(
+                TRACEPARENT_HEADER,
+                HeaderValue::try_from(self.to_traceparent()).expect("hex is a valid header"),
+            );
+        }
+        if !headers.contains_key(XRAY_HEADER) {
+            headers.insert(
+                XRAY_HEADER,
+                HeaderValue::try_from(self.to_xray()).expect("hex is a valid header"),
+            );
+        }
+        if !headers.contains_key(GRPC_TRACE_BIN_HEADER) {
+            let mut metadata = MetadataMap::from_headers(std::mem::take(headers));
+            metadata.insert_bin(
+                GRPC_TRACE_BIN_HEADER,
+                BinaryMetadataValue::from_bytes(&self.to_grpc_trace_bin()),
+            );
+            *headers = metadata.into_headers();
+        }
+    }
+}
+
+fn random_span_id() -> [u8; 8] {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    // the hasher is seeded randomly, so the hash of a counter is unique and unpredictable enough
+    // for span ids
+    let id = RandomState::new().hash_one(COUNTER.fetch_add(1, Ordering::Relaxed));
+    id.max(1).to_be_bytes()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// Lowercase hex of exactly `N` bytes
+fn decode_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != N * 2 || !hex.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+
+    let mut bytes = [0; N];
+    for (byte, pair) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// Propagates the [`TraceContext::current`] context to calls made with a tonic client, as
+/// `traceparent`, `grpc-trace-bin` and `x-amzn-trace-id` metadata, with a new parent span id.
+/// Metadata already set on the request is left as is.
+///
+/// ```ignore
+/// let channel = ServiceBuilder::new()
+///     .layer(TraceContextLayer)
+///     .service(LambdaChannel::new(origin));
+/// let mut client = GreeterClient::new(channel);
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraceContextLayer;
+
+impl<S> Layer<S> for TraceContextLayer {
+    type Service = TraceContextService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TraceContextService { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceContextService<S> {
+    inner: S,
+}
+
+impl<S, B> Service<Request<B>> for TraceContextService<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        if let Some(ctx) = TraceContext::current() {
+            ctx.child().inject(req.headers_mut());
+        }
+
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    const TRACE_ID: [u8; 16] = [
+        0x4b, 0xf9, 0x2f, 0x35, 0x77, 0xb3, 0x4d, 0xa6, 0xa3, 0xce, 0x92, 0x9d, 0x0e, 0x0e, 0x47,
+        0x36,
+    ];
+    const PARENT_ID: [u8; 8] = [0x00, 0xf0, 0x67, 0xaa, 0x0b, 0xa9, 0x02, 0xb7];
+
+    fn context(sampled: Option<bool>) -> TraceContext {
+        TraceContext::new(TRACE_ID, PARENT_ID, sampled).unwrap()
+    }
+
+    #[test]
+    fn parses_and_formats_xray() {
+        let header = "Root=1-4bf92f35-77b34da6a3ce929d0e0e4736;Parent=00f067aa0ba902b7;Sampled=1";
+        assert_eq!(TraceContext::from_xray(header), Some(context(Some(true))));
+        assert_eq!(context(Some(true)).to_xray(), header);
+
+        // lambda appends the lineage, and the sampling decision may be left to the callee
+        let header =
+            "Root=1-4bf92f35-77b34da6a3ce929d0e0e4736;Parent=00f067aa0ba902b7;Lineage=a87bd80c:1";
+        assert_eq!(TraceContext::from_xray(header), Some(context(None)));
+        assert_eq!(
+            context(None).to_xray(),
+            "Root=1-4bf92f35-77b34da6a3ce929d0e0e4736;Parent=00f067aa0ba902b7"
+        );
+
+        // fields without a value are skipped, i.e. a trailing separator
+        let header = "Root=1-4bf92f35-77b34da6a3ce929d0e0e4736;Parent=00f067aa0ba902b7;Sampled=1;";
+        assert_eq!(TraceContext::from_xray(header), Some(context(Some(true))));
+
+        for invalid in [
+            "Root=1-4bf92f35-77b34da6a3ce929d0e0e4736",
+            "Root=2-4bf92f35-77b34da6a3ce929d0e0e4736;Parent=00f067aa0ba902b7",
+            "Root=1-4bf92f3-577b34da6a3ce929d0e0e4736;Parent=00f067aa0ba902b7",
+            "Root=1-4bf92f35-77b34da6a3ce929d0e0e4736;Parent=00f067aa0ba902",
+        ] {
+            assert_eq!(TraceContext::from_xray(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn parses_and_formats_traceparent() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        assert_eq!(
+            TraceContext::from_traceparent(header),
+            Some(context(Some(true)))
+        );
+        assert_eq!(context(Some(true)).to_traceparent(), header);
+        assert_eq!(
+            context(None).to_traceparent(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00"
+        );
+
+        // later versions may add fields
+        assert_eq!(
+            TraceContext::from_traceparent(
+                "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra"
+            ),
+            Some(context(Some(false)))
+        );
+
+        for invalid in [
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        ] {
+            assert_eq!(TraceContext::from_traceparent(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn parses_and_formats_grpc_trace_bin() {
+        let bytes = context(Some(true)).to_grpc_trace_bin();
+        assert_eq!(&bytes[..2], &[0, 0]);
+        assert_eq!(
+            TraceContext::from_grpc_trace_bin(&bytes),
+            Some(context(Some(true)))
+        );
+
+        // the trace options are optional
+        assert_eq!(
+            TraceContext::from_grpc_trace_bin(&bytes[..27]),
+            Some(context(None))
+        );
+
+        let mut unknown_version = bytes;
+        unknown_version[0] = 1;
+        assert_eq!(TraceContext::from_grpc_trace_bin(&unknown_version), None);
+        assert_eq!(TraceContext::from_grpc_trace_bin(&bytes[..20]), None);
+    }
+
+    #[test]
+    fn reads_propagated_context_before_the_invocation_trace() {
+        let mut ctx = LambdaContext::default();
+        ctx.xray_trace_id = Some(
+            "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1".into(),
+        );
+        let request = |headers: &[(&'static str, &str)]| {
+            let mut req = Request::new(());
+            for (name, value) in headers {
+                req.headers_mut()
+                    .insert(*name, HeaderValue::from_str(value).unwrap());
+            }
+            req.extensions_mut().insert(ctx.clone());
+            req
+        };
+
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        assert_eq!(
+            TraceContext::from_request(&request(&[(TRACEPARENT_HEADER, traceparent)])),
+            Some(context(Some(true)))
+        );
+
+        let mut metadata = MetadataMap::new();
+        metadata.insert_bin(
+            GRPC_TRACE_BIN_HEADER,
+            BinaryMetadataValue::from_bytes(&context(None).to_grpc_trace_bin()),
+        );
+        let trace_bin = metadata.into_headers();
+        let trace_bin = trace_bin[GRPC_TRACE_BIN_HEADER].to_str().unwrap();
+        assert_eq!(
+            TraceContext::from_request(&request(&[(GRPC_TRACE_BIN_HEADER, trace_bin)])),
+            Some(context(Some(false)))
+        );
+        let padded = format!("{trace_bin}=");
+        assert_eq!(
+            TraceContext::from_request(&request(&[(GRPC_TRACE_BIN_HEADER, &padded)])),
+            Some(context(Some(false)))
+        );
+
+        let xray = TraceContext::from_request(&request(&[])).unwrap();
+        assert_eq!(
+            encode_hex(&xray.trace_id()),
+            "5759e988bd862e3fe1be46a994272793"
+        );
+    }
+
+    #[tokio::test]
+    async fn layer_injects_a_child_of_the_current_context() {
+        let svc = TraceContextLayer.layer(tower::service_fn(|req: Request<()>| async move {
+            Ok::<_, Infallible>(req.headers().clone())
+        }));
+
+        let headers = context(Some(true))
+            .scope(svc.clone().oneshot(Request::new(())))
+            .await
+            .unwrap();
+
+        let child =
+            TraceContext::from_traceparent(headers[TRACEPARENT_HEADER].to_str().unwrap()).unwrap();
+        assert_eq!(child.trace_id(), TRACE_ID);
+        assert_ne!(child.parent_id(), PARENT_ID);
+        assert_eq!(
+            TraceContext::from_xray(headers[XRAY_HEADER].to_str().unwrap()),
+            Some(child)
+        );
+        let metadata = MetadataMap::from_headers(headers);
+        let bytes = metadata
+            .get_bin(GRPC_TRACE_BIN_HEADER)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        assert_eq!(TraceContext::from_grpc_trace_bin(&bytes), Some(child));
+
+        // metadata set by the caller is kept
+        let mut req = Request::new(());
+        req.headers_mut().insert(
+            TRACEPARENT_HEADER,
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        );
+        let headers = context(Some(true)).scope(svc.oneshot(req)).await.unwrap();
+        assert_eq!(
+            headers[TRACEPARENT_HEADER],
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+    }
+}
@@ -0,0 +1,1160 @@
+//! gRPC-JSON transcoding, serving plain REST clients from the `google.api.http` annotations of
+//! the services. Matching requests are translated into binary grpc-web requests ahead of the
+//! grpc-web layer, and the grpc-web responses translated back to JSON.
+//!
+//! Server streaming methods respond with newline delimited JSON, one message per line.
+
+mod template;
+
+use crate::translate::{
+    GrpcWebEvent, GrpcWebMessages, UnaryResponse, grpc_web_request, http_status, json_to_message,
+    message_to_json, metadata, status_details, trailers_only,
+};
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE};
+use bytes::Bytes;
+use http::header::CONTENT_TYPE;
+use http::{HeaderName, HeaderValue, Method, Request, Response, Uri};
+use http_body::Frame;
+use http_body_util::{BodyExt, Full};
+use lambda_http::tracing::log::warn;
+use prost::Message;
+use prost_reflect::{
+    DescriptorError, DescriptorPool, DynamicMessage, Kind, MessageDescriptor, MethodDescriptor,
+    ReflectMessage, SerializeOptions, Value as ProtoValue,
+};
+use serde_json::{Map, Value, json};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+use template::{PathTemplate, percent_decode};
+use tonic::Status;
+use tonic::body::Body;
+use tower::{BoxError, Layer, Service};
+
+const NDJSON: &str = "application/x-ndjson";
+
+/// JSON transcoding configuration, see
+/// [`LambdaServer::transcoding`](crate::LambdaServer::transcoding).
+///
+/// Built from the descriptors of the services, as generated by `tonic_prost_build` with
+/// `file_descriptor_set_path`, which must have been compiled with `google/api/annotations.proto`:
+///
+/// ```ignore
+/// TranscodingConfig::new(tonic::include_file_descriptor_set!("descriptors"))?
+/// ```
+#[derive(Clone, Debug)]
+pub struct TranscodingConfig {
+    bindings: Arc<Vec<Binding>>,
+}
+
+impl TranscodingConfig {
+    /// Reads the HTTP rules of every method in an encoded `FileDescriptorSet`. Client streaming
+    /// methods can't be transcoded, so their rules are ignored.
+    pub fn new(file_descriptor_set: &[u8]) -> Result<Self, TranscodingError> {
+        let pool = DescriptorPool::decode(file_descriptor_set)?;
+
+        let mut bindings = Vec::new();
+        for service in pool.services() {
+            for method in service.methods() {
+                let Some(rule) = http_rule(&method) else {
+                    continue;
+                };
+
+                if method.is_client_streaming() {
+                    warn!(
+                        "Ignoring HTTP rule of client streaming method {}",
+                        method.full_name()
+                    );
+                    continue;
+                }
+
+                let additional = rule.additional_bindings.clone();
+                for rule in std::iter::once(rule).chain(additional) {
+                    bindings.push(Binding::new(&method, &rule).map_err(|message| {
+                        TranscodingError::InvalidRule {
+                            method: method.full_name().to_string(),
+                            message,
+                        }
+                    })?);
+                }
+            }
+        }
+
+        Ok(Self {
+            bindings: Arc::new(bindings),
+        })
+    }
+
+    pub(crate) fn layer(&self) -> TranscodingLayer {
+        TranscodingLayer {
+            bindings: self.bindings.clone(),
+        }
+    }
+}
+
+/// The descriptors could not be read, or carry an HTTP rule that can't be served
+#[derive(Debug)]
+pub enum TranscodingError {
+    Descriptors(DescriptorError),
+    InvalidRule { method: String, message: String },
+}
+
+impl fmt::Display for TranscodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscodingError::Descriptors(err) => write!(f, "Invalid descriptors: {err}"),
+            TranscodingError::InvalidRule { method, message } => {
+                write!(f, "Invalid HTTP rule for {method}: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TranscodingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TranscodingError::Descriptors(err) => Some(err),
+            TranscodingError::InvalidRule { .. } => None,
+        }
+    }
+}
+
+impl From<DescriptorError> for TranscodingError {
+    fn from(err: DescriptorError) -> Self {
+        TranscodingError::Descriptors(err)
+    }
+}
+
+/// `google.api.HttpRule`, with the `pattern` oneof read as its separate fields
+#[derive(Clone, PartialEq, prost::Message)]
+struct HttpRule {
+    #[prost(string, optional, tag = "2")]
+    get: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    put: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    post: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    delete: Option<String>,
+    #[prost(string, optional, tag = "6")]
+    patch: Option<String>,
+    #[prost(message, optional, tag = "8")]
+    custom: Option<CustomHttpPattern>,
+    #[prost(string, tag = "7")]
+    body: String,
+    #[prost(string, tag = "12")]
+    response_body: String,
+    #[prost(message, repeated, tag = "11")]
+    additional_bindings: Vec<HttpRule>,
+}
+
+/// `google.api.CustomHttpPattern`
+#[derive(Clone, PartialEq, prost::Message)]
+struct CustomHttpPattern {
+    #[prost(string, tag = "1")]
+    kind: String,
+    #[prost(string, tag = "2")]
+    path: String,
+}
+
+/// `google.protobuf.MethodOptions`, reading only the `google.api.http` extension
+#[derive(Clone, PartialEq, prost::Message)]
+struct HttpMethodOptions {
+    /// Field number of the `google.api.http` extension
+    #[prost(message, optional, tag = "72295728")]
+    http: Option<HttpRule>,
+}
+
+/// The HTTP rule of a method. Read from the encoded options, so the descriptors needn't include
+/// `google/api/http.proto` itself.
+fn http_rule(method: &MethodDescriptor) -> Option<HttpRule> {
+    HttpMethodOptions::decode(method.options().encode_to_vec().as_slice())
+        .ok()?
+        .http
+}
+
+/// A single HTTP rule, bound to its method
+#[derive(Clone, Debug)]
+struct Binding {
+    method: Method,
+    template: PathTemplate,
+    /// `*` for the whole request message, or the field the body is read into
+    body: Option<Vec<String>>,
+    /// The response field to respond with, rather than the whole response message
+    response_body: Option<Vec<String>>,
+    grpc_path: Uri,
+    input: MessageDescriptor,
+    output: MessageDescriptor,
+    server_streaming: bool,
+}
+
+impl Binding {
+    fn new(method: &MethodDescriptor, rule: &HttpRule) -> Result<Self, String> {
+        let (http_method, template) = match rule {
+            HttpRule {
+                get: Some(path), ..
+            } => (Method::GET, path),
+            HttpRule {
+                put: Some(path), ..
+            } => (Method::PUT, path),
+            HttpRule {
+                post: Some(path), ..
+            } => (Method::POST, path),
+            HttpRule {
+                delete: Some(path), ..
+            } => (Method::DELETE, path),
+            HttpRule {
+                patch: Some(path), ..
+            } => (Method::PATCH, path),
+            HttpRule {
+                custom: Some(custom),
+                ..
+            } => {
+                let kind = Method::from_bytes(custom.kind.as_bytes())
+                    .map_err(|_| format!("invalid custom method `{}`", custom.kind))?;
+                (kind, &custom.path)
+            }
+            _ => return Err("no HTTP method and path".to_string()),
+        };
+
+        let template = PathTemplate::parse(template)?;
+        let input = method.input();
+        let output = method.output();
+
+        for path in template.field_paths() {
+            if !resolves(&input, path) {
+                return Err(format!(
+                    "`{}` is not a field of the request",
+                    path.join(".")
+                ));
+            }
+        }
+
+        let field_path = |path: &str, message: &MessageDescriptor| {
+            let path: Vec<String> = path.split('.').map(str::to_string).collect();
+            if resolves(message, &path) {
+                Ok(path)
+            } else {
+                Err(format!(
+                    "`{}` is not a field of {}",
+                    path.join("."),
+                    message.full_name()
+                ))
+            }
+        };
+
+        let body = match rule.body.as_str() {
+            "" => None,
+            "*" => Some(Vec::new()),
+            path => Some(field_path(path, &input)?),
+        };
+
+        let response_body = match rule.response_body.as_str() {
+            "" => None,
+            path => Some(field_path(path, &output)?),
+        };
+
+        let grpc_path = format!("/{}/{}", method.parent_service().full_name(), method.name());
+
+        Ok(Self {
+            method: http_method,
+            template,
+            body,
+            response_body,
+            grpc_path: Uri::try_from(grpc_path).map_err(|err| err.to_string())?,
+            input,
+            output,
+            server_streaming: method.is_server_streaming(),
+        })
+    }
+
+    /// Builds the request message from the path variables, the body and the query string
+    async fn request_message(
+        &self,
+        variables: Vec<(&[String], String)>,
+        query: Option<&str>,
+        body: Body,
+    ) -> Result<Bytes, Status> {
+        let body = body
+            .collect()
+            .await
+            .map_err(|err| Status::invalid_argument(format!("Failed to read request: {err}")))?
+            .to_bytes();
+
+        let mut message = match &self.body {
+            None => DynamicMessage::new(self.input.clone()),
+            Some(field) if field.is_empty() => {
+                json_to_message(&self.input, if body.is_empty() { b"{}" } else { &body })?
+            }
+            Some(field) => {
+                let value: Value = serde_json::from_slice(&body).map_err(|err| {
+                    Status::invalid_argument(format!("Invalid JSON request: {err}"))
+                })?;
+                // nest the body under its field, so it's read with the rest of the JSON mapping
+                let value = field
+                    .iter()
+                    .rev()
+                    .fold(value, |value, name| json!({ name.as_str(): value }));
+                DynamicMessage::deserialize(self.input.clone(), value).map_err(|err| {
+                    Status::invalid_argument(format!("Invalid JSON request: {err}"))
+                })?
+            }
+        };
+
+        for (field, value) in &variables {
+            set_field(&mut message, field, value).map_err(|err| {
+                Status::invalid_argument(format!("Invalid `{}`: {err}", field.join(".")))
+            })?;
+        }
+
+        // with the whole message read from the body, there are no fields left for the query
+        let query = query.filter(|_| !self.body.as_ref().is_some_and(Vec::is_empty));
+        for pair in query
+            .unwrap_or_default()
+            .split('&')
+            .filter(|p| !p.is_empty())
+        {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let (Some(key), Some(value)) = (percent_decode(key, true), percent_decode(value, true))
+            else {
+                return Err(Status::invalid_argument("Invalid query string"));
+            };
+
+            let field: Vec<String> = key.split('.').map(str::to_string).collect();
+            let Some(names) = field_names(&self.input, &field) else {
+                continue;
+            };
+            // the query only fills the fields left unbound by the path template and the body
+            if variables
+                .iter()
+                .map(|(bound, _)| bound)
+                .chain(self.body.as_deref().as_slice())
+                .any(|bound| bound.starts_with(&names) || names.starts_with(bound))
+            {
+                continue;
+            }
+
+            match set_field(&mut message, &field, &value) {
+                Ok(()) | Err(FieldError::Unknown) => {}
+                Err(err) => {
+                    return Err(Status::invalid_argument(format!("Invalid `{key}`: {err}")));
+                }
+            }
+        }
+
+        Ok(message.encode_to_vec().into())
+    }
+
+    /// Renders a response message as JSON, or just its `response_body` field
+    fn response_json(&self, message: Bytes) -> Result<Value, Status> {
+        let Some(field) = &self.response_body else {
+            return message_to_json(&self.output, message);
+        };
+
+        let encode_error =
+            |err: String| Status::internal(format!("Failed to encode response as JSON: {err}"));
+
+        let message = DynamicMessage::decode(self.output.clone(), message)
+            .map_err(|err| encode_error(err.to_string()))?;
+
+        // fields are all included, so the selected field is present even when it's the default
+        let options = SerializeOptions::new().skip_default_fields(false);
+        let mut value = message
+            .serialize_with_options(serde_json::value::Serializer, &options)
+            .map_err(|err| encode_error(err.to_string()))?;
+
+        let mut descriptor = self.output.clone();
+        for name in field {
+            let field = descriptor
+                .get_field_by_name(name)
+                .expect("response_body is resolved when the binding is created");
+            value = value
+                .get_mut(field.json_name())
+                .map(Value::take)
+                .unwrap_or_default();
+            if let Kind::Message(message) = field.kind() {
+                descriptor = message;
+            }
+        }
+
+        Ok(value)
+    }
+}
+
+/// Whether a dotted field path resolves against the message
+fn resolves(message: &MessageDescriptor, path: &[String]) -> bool {
+    let mut message = message.clone();
+    for (i, name) in path.iter().enumerate() {
+        let Some(field) = message.get_field_by_name(name) else {
+            return false;
+        };
+        match field.kind() {
+            Kind::Message(child) if !field.is_list() => message = child,
+            _ => return i == path.len() - 1,
+        }
+    }
+    true
+}
+
+/// The field names of a dotted path that may use JSON names, or `None` when it doesn't resolve
+fn field_names(message: &MessageDescriptor, path: &[String]) -> Option<Vec<String>> {
+    let mut message = Some(message.clone());
+    path.iter()
+        .map(|name| {
+            let descriptor = message.take()?;
+            let field = descriptor
+                .get_field_by_name(name)
+                .or_else(|| descriptor.get_field_by_json_name(name))?;
+            if let Kind::Message(child) = field.kind()
+                && !field.is_list()
+            {
+                message = Some(child);
+            }
+            Some(field.name().to_string())
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+enum FieldError {
+    Unknown,
+    Invalid(String),
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldError::Unknown => f.write_str("unknown field"),
+            FieldError::Invalid(message) => f.write_str(message),
+        }
+    }
+}
+
+/// Sets the field at a dotted path from its string form, appending to repeated fields
+fn set_field(message: &mut DynamicMessage, path: &[String], value: &str) -> Result<(), FieldError> {
+    let (name, rest) = path.split_first().ok_or(FieldError::Unknown)?;
+    let descriptor = message.descriptor();
+    let field = descriptor
+        .get_field_by_name(name)
+        .or_else(|| descriptor.get_field_by_json_name(name))
+        .ok_or(FieldError::Unknown)?;
+
+    if !rest.is_empty() {
+        return match message.get_field_mut(&field) {
+            ProtoValue::Message(child) if !field.is_list() => set_field(child, rest, value),
+            _ => Err(FieldError::Unknown),
+        };
+    }
+
+    let parsed = parse_value(&field.kind(), value)?;
+    if field.is_map() {
+        return Err(FieldError::Invalid("map fields can't be set".to_string()));
+    } else if field.is_list() {
+        if let ProtoValue::List(list) = message.get_field_mut(&field) {
+            list.push(parsed);
+        }
+    } else {
+        message.set_field(&field, parsed);
+    }
+    Ok(())
+}
+
+fn parse_value(kind: &Kind, value: &str) -> Result<ProtoValue, FieldError> {
+    let invalid = || FieldError::Invalid(format!("`{value}` is not a valid {kind:?}"));
+
+    Ok(match kind {
+        Kind::Double => ProtoValue::F64(value.parse().map_err(|_| invalid())?),
+        Kind::Float => ProtoValue::F32(value.parse().map_err(|_| invalid())?),
+        Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => {
+            ProtoValue::I32(value.parse().map_err(|_| invalid())?)
+        }
+        Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => {
+            ProtoValue::I64(value.parse().map_err(|_| invalid())?)
+        }
+        Kind::Uint32 | Kind::Fixed32 => ProtoValue::U32(value.parse().map_err(|_| invalid())?),
+        Kind::Uint64 | Kind::Fixed64 => ProtoValue::U64(value.parse().map_err(|_| invalid())?),
+        Kind::Bool => ProtoValue::Bool(value.parse().map_err(|_| invalid())?),
+        Kind::String => ProtoValue::String(value.to_string()),
+        Kind::Bytes => {
+            let padded = format!("{value:=<width$}", width = value.len().div_ceil(4) * 4);
+            let bytes = STANDARD
+                .decode(&padded)
+                .or_else(|_| URL_SAFE.decode(&padded))
+                .map_err(|_| invalid())?;
+            ProtoValue::Bytes(bytes.into())
+        }
+        Kind::Enum(descriptor) => match descriptor.get_value_by_name(value) {
+            Some(enum_value) => ProtoValue::EnumNumber(enum_value.number()),
+            None => ProtoValue::EnumNumber(value.parse().map_err(|_| invalid())?),
+        },
+        Kind::Message(_) => return Err(invalid()),
+    })
+}
+
+#[derive(Clone)]
+pub(crate) struct TranscodingLayer {
+    bindings: Arc<Vec<Binding>>,
+}
+
+impl<S> Layer<S> for TranscodingLayer {
+    type Service = TranscodingService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TranscodingService {
+            inner,
+            bindings: self.bindings.clone(),
+        }
+    }
+}
+
+/// Translates requests matching an HTTP rule to grpc-web, passing any other request through
+#[derive(Clone)]
+pub(crate) struct TranscodingService<S> {
+    inner: S,
+    bindings: Arc<Vec<Binding>>,
+}
+
+impl<S> TranscodingService<S> {
+    fn find(&self, req: &Request<Body>) -> Option<&Binding> {
+        // grpc-web requests use the gRPC paths, which a broad template could otherwise match
+        let is_grpc = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("application/grpc"));
+        if is_grpc {
+            return None;
+        }
+
+        self.bindings.iter().find(|binding| {
+            binding.method == req.method() && binding.template.matches(req.uri().path()).is_some()
+        })
+    }
+}
+
+impl<S, ResBody> Service<Request<Body>> for TranscodingService<S>
+where
+    S: Service<Request<Body>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+    ResBody: http_body::Body<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let Some(binding) = self.find(&req).cloned() else {
+            let fut = self.inner.call(req);
+            return Box::pin(async move { Ok(fut.await?.map(Body::new)) });
+        };
+
+        // the request body has to be collected before the inner service is called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+
+            let variables = binding
+                .template
+                .matches(parts.uri.path())
+                .expect("binding matched the path");
+            let message = binding
+                .request_message(variables, parts.uri.query(), body)
+                .await;
+
+            let message = match message {
+                Ok(message) => message,
+                Err(status) => return Ok(error_response(&status, binding.output.parent_pool())),
+            };
+
+            parts.method = Method::POST;
+            parts.uri = binding.grpc_path.clone();
+            parts.headers.remove(CONTENT_TYPE);
+
+            let res = inner.call(grpc_web_request(parts, vec![message])).await?;
+            Ok(to_json(&binding, res.map(Body::new)).await)
+        })
+    }
+}
+
+async fn to_json(binding: &Binding, res: Response<Body>) -> Response<Body> {
+    let pool = binding.output.parent_pool();
+
+    if binding.server_streaming {
+        let (parts, body) = res.into_parts();
+        if let Some(status) = trailers_only(&parts) {
+            return error_response(&status, pool);
+        }
+
+        let mut res = Response::new(Body::new(NdjsonBody {
+            messages: GrpcWebMessages::new(body),
+            binding: binding.clone(),
+            done: false,
+        }));
+        *res.headers_mut() = metadata(&parts.headers);
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(NDJSON));
+        return res;
+    }
+
+    let unary = UnaryResponse::read(res).await;
+
+    let mut res = match unary
+        .result
+        .and_then(|message| binding.response_json(message))
+    {
+        Ok(json) => json_response(&json),
+        Err(status) => error_response(&status, pool),
+    };
+
+    let headers = res.headers_mut();
+    headers.extend(unary.headers);
+    for (name, value) in unary.trailers.iter() {
+        if let Ok(name) = HeaderName::try_from(format!("grpc-trailer-{name}")) {
+            headers.append(name, value.clone());
+        }
+    }
+    res
+}
+
+/// Server stream body, with each message as a line of JSON. A failed stream ends with an
+/// `{"error": ..}` line, as the HTTP status has been sent already.
+struct NdjsonBody {
+    messages: GrpcWebMessages,
+    binding: Binding,
+    done: bool,
+}
+
+impl http_body::Body for NdjsonBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        let line = match ready!(this.messages.poll_next(cx)) {
+            Some(GrpcWebEvent::Message(message)) => match this.binding.response_json(message) {
+                Ok(json) => json,
+                Err(status) => {
+                    this.done = true;
+                    json!({ "error": status_json(&status, this.binding.output.parent_pool()) })
+                }
+            },
+            Some(GrpcWebEvent::End(status, _)) if status.code() != tonic::Code::Ok => {
+                this.done = true;
+                json!({ "error": status_json(&status, this.binding.output.parent_pool()) })
+            }
+            Some(GrpcWebEvent::End(..)) | None => {
+                this.done = true;
+                return Poll::Ready(None);
+            }
+        };
+
+        let mut line = serde_json::to_vec(&line).expect("JSON values serialize");
+        line.push(b'\n');
+        Poll::Ready(Some(Ok(Frame::data(line.into()))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done
+    }
+}
+
+fn json_response(json: &Value) -> Response<Body> {
+    let body = serde_json::to_vec(json).expect("JSON values serialize");
+
+    let mut res = Response::new(Body::new(Full::new(Bytes::from(body))));
+    res.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    res
+}
+
+fn error_response(status: &Status, pool: &DescriptorPool) -> Response<Body> {
+    let mut res = json_response(&status_json(status, pool));
+    *res.status_mut() = http_status(status.code());
+    res
+}
+
+/// The status in the JSON mapping of `google.rpc.Status`. Details are included when their type
+/// is in the descriptors.
+fn status_json(status: &Status, pool: &DescriptorPool) -> Value {
+    let details: Vec<Value> = status_details(status)
+        .into_iter()
+        .filter_map(|any| {
+            let type_name = any.type_url.rsplit('/').next()?;
+            let message = DynamicMessage::decode(pool.get_message_by_name(type_name)?, &*any.value);
+            let Ok(Value::Object(fields)) = serde_json::to_value(message.ok()?) else {
+                return None;
+            };
+
+            let mut detail = Map::new();
+            detail.insert("@type".to_string(), any.type_url.into());
+            detail.extend(fields);
+            Some(Value::Object(detail))
+        })
+        .collect();
+
+    json!({
+        "code": status.code() as i32,
+        "message": status.message(),
+        "details": details,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grpc_web::{FrameDecoder, encode_frame, encode_trailers_frame};
+    use http::{HeaderMap, StatusCode};
+    use prost_reflect::prost_types::field_descriptor_proto::{Label, Type};
+    use prost_reflect::prost_types::{DescriptorProto, FieldDescriptorProto, FileDescriptorProto};
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Book {
+        #[prost(string, tag = "1")]
+        shelf: String,
+        #[prost(string, tag = "2")]
+        id: String,
+        #[prost(string, tag = "3")]
+        title: String,
+        #[prost(int32, tag = "4")]
+        year: i32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct GetBookRequest {
+        #[prost(string, tag = "1")]
+        shelf: String,
+        #[prost(string, tag = "2")]
+        id: String,
+        #[prost(bool, tag = "3")]
+        full: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct UpdateBookRequest {
+        #[prost(string, tag = "1")]
+        shelf: String,
+        #[prost(message, optional, tag = "2")]
+        book: Option<Book>,
+    }
+
+    /// `google.protobuf.MethodDescriptorProto`, with the options carrying the HTTP rule
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct MethodProto {
+        #[prost(string, tag = "1")]
+        name: String,
+        #[prost(string, tag = "2")]
+        input_type: String,
+        #[prost(string, tag = "3")]
+        output_type: String,
+        #[prost(message, optional, tag = "4")]
+        options: Option<HttpMethodOptions>,
+        #[prost(bool, tag = "6")]
+        server_streaming: bool,
+    }
+
+    /// `google.protobuf.ServiceDescriptorProto`
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct ServiceProto {
+        #[prost(string, tag = "1")]
+        name: String,
+        #[prost(message, repeated, tag = "2")]
+        method: Vec<MethodProto>,
+    }
+
+    fn field(name: &str, number: i32, kind: Type, type_name: Option<&str>) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_string()),
+            json_name: Some(name.to_string()),
+            number: Some(number),
+            label: Some(Label::Optional as i32),
+            r#type: Some(kind as i32),
+            type_name: type_name.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    fn message(name: &str, field: Vec<FieldDescriptorProto>) -> DescriptorProto {
+        DescriptorProto {
+            name: Some(name.to_string()),
+            field,
+            ..Default::default()
+        }
+    }
+
+    fn method(name: &str, input: &str, output: &str, rule: HttpRule) -> MethodProto {
+        MethodProto {
+            name: name.to_string(),
+            input_type: format!(".test.{input}"),
+            output_type: format!(".test.{output}"),
+            options: Some(HttpMethodOptions { http: Some(rule) }),
+            server_streaming: false,
+        }
+    }
+
+    /// Descriptors of `test.Library`, with the HTTP rules of each method
+    fn descriptors(get_book: HttpRule) -> Vec<u8> {
+        let file = FileDescriptorProto {
+            name: Some("test.proto".to_string()),
+            package: Some("test".to_string()),
+            syntax: Some("proto3".to_string()),
+            message_type: vec![
+                message(
+                    "Book",
+                    vec![
+                        field("shelf", 1, Type::String, None),
+                        field("id", 2, Type::String, None),
+                        field("title", 3, Type::String, None),
+                        field("year", 4, Type::Int32, None),
+                    ],
+                ),
+                message(
+                    "GetBookRequest",
+                    vec![
+                        field("shelf", 1, Type::String, None),
+                        field("id", 2, Type::String, None),
+                        field("full", 3, Type::Bool, None),
+                    ],
+                ),
+                message(
+                    "UpdateBookRequest",
+                    vec![
+                        field("shelf", 1, Type::String, None),
+                        field("book", 2, Type::Message, Some(".test.Book")),
+                    ],
+                ),
+            ],
+            ..Default::default()
+        };
+
+        let service = ServiceProto {
+            name: "Library".to_string(),
+            method: vec![
+                method("GetBook", "GetBookRequest", "Book", get_book),
+                method(
+                    "UpdateBook",
+                    "UpdateBookRequest",
+                    "Book",
+                    HttpRule {
+                        patch: Some("/v1/shelves/{shelf}/books/{book.id}".to_string()),
+                        body: "book".to_string(),
+                        response_body: "title".to_string(),
+                        ..Default::default()
+                    },
+                ),
+                MethodProto {
+                    server_streaming: true,
+                    ..method(
+                        "ListBooks",
+                        "GetBookRequest",
+                        "Book",
+                        HttpRule {
+                            get: Some("/v1/{shelf=shelves/*}/books".to_string()),
+                            ..Default::default()
+                        },
+                    )
+                },
+            ],
+        };
+
+        // the service is appended to the encoded file, as prost_types can't carry the extension
+        let mut file = file.encode_to_vec();
+        prost::encoding::message::encode(6, &service, &mut file);
+
+        let mut set = Vec::new();
+        prost::encoding::bytes::encode(1, &file, &mut set);
+        set
+    }
+
+    fn get_book_rule() -> HttpRule {
+        HttpRule {
+            get: Some("/v1/shelves/{shelf}/books/{id}".to_string()),
+            additional_bindings: vec![HttpRule {
+                post: Some("/v1/shelves/{shelf}/books/{id}:get".to_string()),
+                body: "*".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn grpc_web_response(messages: Vec<Vec<u8>>, status: Status) -> Response<Body> {
+        let mut body = Vec::new();
+        for message in messages {
+            body.extend_from_slice(&encode_frame(0, &message));
+        }
+
+        let mut trailers = HeaderMap::new();
+        status.add_header(&mut trailers).unwrap();
+        body.extend_from_slice(&encode_trailers_frame(&trailers));
+
+        let mut res = Response::new(Body::new(Full::new(Bytes::from(body))));
+        res.headers_mut()
+            .insert("x-path", HeaderValue::from_static("grpc"));
+        res
+    }
+
+    /// A library serving books with the request fields echoed back
+    async fn library(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+        let path = req.uri().path().to_string();
+        if req.headers()[CONTENT_TYPE] != "application/grpc-web+proto" {
+            return Ok(Response::new(Body::new(Full::new(Bytes::from(path)))));
+        }
+
+        let mut decoder = FrameDecoder::default();
+        decoder.push(req.into_body().collect().await.unwrap().to_bytes());
+        let (_, request) = decoder.next_frame().unwrap();
+
+        let res = match path.as_str() {
+            "/test.Library/GetBook" => {
+                let req = GetBookRequest::decode(request).unwrap();
+                if req.id == "missing" {
+                    return Ok(Status::not_found("no such book").into_http());
+                }
+                let book = Book {
+                    shelf: req.shelf,
+                    id: req.id,
+                    title: if req.full { "full" } else { "" }.to_string(),
+                    year: 0,
+                };
+                grpc_web_response(vec![book.encode_to_vec()], Status::ok(""))
+            }
+            "/test.Library/UpdateBook" => {
+                let req = UpdateBookRequest::decode(request).unwrap();
+                let book = Book {
+                    shelf: req.shelf,
+                    ..req.book.unwrap()
+                };
+                grpc_web_response(vec![book.encode_to_vec()], Status::ok(""))
+            }
+            "/test.Library/ListBooks" => {
+                let req = GetBookRequest::decode(request).unwrap();
+                let books = ["a", "b"].map(|id| {
+                    Book {
+                        shelf: req.shelf.clone(),
+                        id: id.to_string(),
+                        ..Default::default()
+                    }
+                    .encode_to_vec()
+                });
+                grpc_web_response(books.to_vec(), Status::aborted("shelf collapsed"))
+            }
+            _ => unreachable!("unexpected path {path}"),
+        };
+
+        Ok(res)
+    }
+
+    async fn call(req: Request<Body>) -> (StatusCode, HeaderMap, Bytes) {
+        let config = TranscodingConfig::new(&descriptors(get_book_rule())).unwrap();
+        let res = config
+            .layer()
+            .layer(tower::service_fn(library))
+            .oneshot(req)
+            .await
+            .unwrap();
+
+        let (parts, body) = res.into_parts();
+        (
+            parts.status,
+            parts.headers,
+            body.collect().await.unwrap().to_bytes(),
+        )
+    }
+
+    fn request(method: Method, uri: &str, body: &'static str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::new(Full::new(Bytes::from_static(body.as_bytes()))))
+            .unwrap()
+    }
+
+    fn json(body: &[u8]) -> Value {
+        serde_json::from_slice(body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn binds_path_variables_and_query() {
+        let req = request(
+            Method::GET,
+            "/v1/shelves/s1/books/b%201?full=true&cache=1",
+            "",
+        );
+
+        let (status, headers, body) = call(req).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[CONTENT_TYPE], "application/json");
+        assert_eq!(headers["x-path"], "grpc");
+        assert_eq!(
+            json(&body),
+            json!({ "shelf": "s1", "id": "b 1", "title": "full" })
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_whole_body_for_additional_binding() {
+        let req = request(
+            Method::POST,
+            "/v1/shelves/s1/books/b1:get",
+            r#"{"full":true}"#,
+        );
+
+        let (status, _, body) = call(req).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            json(&body),
+            json!({ "shelf": "s1", "id": "b1", "title": "full" })
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_body_field_and_responds_with_response_body() {
+        let req = request(
+            Method::PATCH,
+            "/v1/shelves/s1/books/b1",
+            r#"{"title":"Dune","year":1965}"#,
+        );
+
+        let (status, _, body) = call(req).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json(&body), json!("Dune"));
+    }
+
+    #[tokio::test]
+    async fn maps_status_to_http() {
+        let req = request(Method::GET, "/v1/shelves/s1/books/missing", "");
+
+        let (status, _, body) = call(req).await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            json(&body),
+            json!({ "code": 5, "message": "no such book", "details": [] })
+        );
+    }
+
+    #[tokio::test]
+    async fn query_cannot_override_path_or_body_fields() {
+        let req = request(Method::GET, "/v1/shelves/s1/books/b1?id=b2&shelf=s2", "");
+        let (status, _, body) = call(req).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json(&body), json!({ "shelf": "s1", "id": "b1" }));
+
+        let req = request(
+            Method::PATCH,
+            "/v1/shelves/s1/books/b1?book.title=Emma&book.id=b2",
+            r#"{"title":"Dune"}"#,
+        );
+        let (status, _, body) = call(req).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json(&body), json!("Dune"));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_query_values() {
+        let req = request(Method::GET, "/v1/shelves/s1/books/b1?full=maybe", "");
+
+        let (status, _, body) = call(req).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(json(&body)["code"], 3);
+    }
+
+    #[tokio::test]
+    async fn streams_newline_delimited_json() {
+        let req = request(Method::GET, "/v1/shelves/s1/books", "");
+
+        let (status, headers, body) = call(req).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[CONTENT_TYPE], NDJSON);
+
+        let lines: Vec<Value> = body
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(json)
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                json!({ "shelf": "shelves/s1", "id": "a" }),
+                json!({ "shelf": "shelves/s1", "id": "b" }),
+                json!({ "error": { "code": 10, "message": "shelf collapsed", "details": [] } }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_body_ends_with_the_error_line() {
+        let config = TranscodingConfig::new(&descriptors(get_book_rule())).unwrap();
+        let res = config
+            .layer()
+            .layer(tower::service_fn(library))
+            .oneshot(request(Method::GET, "/v1/shelves/s1/books", ""))
+            .await
+            .unwrap();
+        let mut body = res.into_body();
+
+        for _ in 0..2 {
+            assert!(body.frame().await.unwrap().unwrap().is_data());
+            assert!(!http_body::Body::is_end_stream(&body));
+        }
+
+        let error = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        assert_eq!(json(&error)["error"]["code"], 10);
+        assert!(http_body::Body::is_end_stream(&body));
+        assert!(body.frame().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn passes_other_requests_through() {
+        let req = request(Method::GET, "/v1/shelves", "");
+
+        let (status, _, body) = call(req).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "/v1/shelves");
+    }
+
+    #[test]
+    fn rejects_rules_binding_unknown_fields() {
+        let rule = HttpRule {
+            get: Some("/v1/books/{isbn}".to_string()),
+            ..Default::default()
+        };
+
+        let err = TranscodingConfig::new(&descriptors(rule)).unwrap_err();
+
+        assert!(matches!(
+            err,
+            TranscodingError::InvalidRule { ref method, .. } if method == "test.Library.GetBook"
+        ));
+    }
+}
@@ -0,0 +1,269 @@
+//! `google.api.http` path templates, i.e. `/v1/{name=shelves/*/books/*}:publish`, see
+//! [`google/api/http.proto`](https://github.com/googleapis/googleapis/blob/master/google/api/http.proto).
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    /// `*`, a single segment
+    Wildcard,
+    /// `**`, any number of segments, only valid as the final segment
+    DoubleWildcard,
+}
+
+/// A `{field.path=segments}` variable, binding the request path segments it spans to a field
+#[derive(Clone, Debug)]
+struct Variable {
+    field_path: Vec<String>,
+    start: usize,
+    end: usize,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct PathTemplate {
+    segments: Vec<Segment>,
+    variables: Vec<Variable>,
+    verb: Option<String>,
+}
+
+impl PathTemplate {
+    pub(crate) fn parse(template: &str) -> Result<Self, String> {
+        let rest = template
+            .strip_prefix('/')
+            .ok_or_else(|| format!("path template `{template}` must start with `/`"))?;
+
+        // the verb follows the final segment, which can't be inside a variable
+        let after_last_segment = rest.rfind(['/', '}']).map_or(0, |i| i + 1);
+        let (rest, verb) = match rest[after_last_segment..].find(':') {
+            Some(i) => {
+                let colon = after_last_segment + i;
+                (&rest[..colon], Some(rest[colon + 1..].to_string()))
+            }
+            None => (rest, None),
+        };
+
+        let mut segments = Vec::new();
+        let mut variables = Vec::new();
+
+        for part in split_top_level(rest).map_err(|err| format!("`{template}` {err}"))? {
+            match part.strip_prefix('{').and_then(|v| v.strip_suffix('}')) {
+                Some(variable) => {
+                    let (field_path, pattern) = variable.split_once('=').unwrap_or((variable, "*"));
+                    let field_path: Vec<String> =
+                        field_path.split('.').map(str::to_string).collect();
+                    if field_path.iter().any(String::is_empty) {
+                        return Err(format!("`{template}` has an invalid variable `{variable}`"));
+                    }
+
+                    let start = segments.len();
+                    for segment in pattern.split('/') {
+                        segments.push(parse_segment(segment, template)?);
+                    }
+                    variables.push(Variable {
+                        field_path,
+                        start,
+                        end: segments.len(),
+                    });
+                }
+                None => segments.push(parse_segment(part, template)?),
+            }
+        }
+
+        if let Some(i) = segments.iter().position(|s| *s == Segment::DoubleWildcard)
+            && i != segments.len() - 1
+        {
+            return Err(format!(
+                "`{template}` may only use `**` as the final segment"
+            ));
+        }
+
+        Ok(Self {
+            segments,
+            variables,
+            verb,
+        })
+    }
+
+    /// Field paths of the variables, as they must be resolved against the request message
+    pub(crate) fn field_paths(&self) -> impl Iterator<Item = &[String]> {
+        self.variables.iter().map(|v| v.field_path.as_slice())
+    }
+
+    /// Matches a request path, returning the percent-decoded value of each variable
+    pub(crate) fn matches(&self, path: &str) -> Option<Vec<(&[String], String)>> {
+        let path = path.strip_prefix('/')?;
+        let path = match &self.verb {
+            Some(verb) => path.strip_suffix(verb.as_str())?.strip_suffix(':')?,
+            None => path,
+        };
+
+        let parts: Vec<&str> = path.split('/').collect();
+
+        // the path segments each template segment spans
+        let mut spans = Vec::with_capacity(self.segments.len());
+        let mut next = 0;
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => {
+                    if parts.get(next) != Some(&literal.as_str()) {
+                        return None;
+                    }
+                    spans.push((next, next + 1));
+                    next += 1;
+                }
+                Segment::Wildcard => {
+                    if parts.get(next).is_none_or(|part| part.is_empty()) {
+                        return None;
+                    }
+                    spans.push((next, next + 1));
+                    next += 1;
+                }
+                Segment::DoubleWildcard => {
+                    spans.push((next, parts.len()));
+                    next = parts.len();
+                }
+            }
+        }
+
+        if next != parts.len() {
+            return None;
+        }
+
+        self.variables
+            .iter()
+            .map(|variable| {
+                let start = spans.get(variable.start).map_or(next, |span| span.0);
+                let end = spans.get(variable.end - 1).map_or(next, |span| span.1);
+                let value = parts[start..end]
+                    .iter()
+                    .map(|part| percent_decode(part, false))
+                    .collect::<Option<Vec<_>>>()?
+                    .join("/");
+                Some((variable.field_path.as_slice(), value))
+            })
+            .collect()
+    }
+}
+
+fn parse_segment(segment: &str, template: &str) -> Result<Segment, String> {
+    match segment {
+        "*" => Ok(Segment::Wildcard),
+        "**" => Ok(Segment::DoubleWildcard),
+        "" => Err(format!("`{template}` has an empty segment")),
+        literal if literal.contains(['{', '}', '=']) => {
+            Err(format!("`{template}` has an invalid segment `{literal}`"))
+        }
+        literal => Ok(Segment::Literal(literal.to_string())),
+    }
+}
+
+/// Splits on the `/` outside of variables
+fn split_top_level(path: &str) -> Result<Vec<&str>, &'static str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in path.char_indices() {
+        match c {
+            '{' if depth == 0 => depth = 1,
+            '}' if depth == 1 => depth = 0,
+            '{' | '}' => return Err("has unbalanced braces"),
+            '/' if depth == 0 => {
+                parts.push(&path[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    if depth != 0 {
+        return Err("has unbalanced braces");
+    }
+    parts.push(&path[start..]);
+    Ok(parts)
+}
+
+/// Decodes `%XX` escapes, and `+` as a space in query strings. `None` if the result isn't UTF-8.
+pub(crate) fn percent_decode(value: &str, plus_as_space: bool) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+
+    while let Some(b) = input.next() {
+        match b {
+            b'%' => {
+                let hex = [input.next()?, input.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            b'+' if plus_as_space => bytes.push(b' '),
+            b => bytes.push(b),
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bindings(template: &str, path: &str) -> Option<Vec<(String, String)>> {
+        let template = PathTemplate::parse(template).unwrap();
+        template.matches(path).map(|vars| {
+            vars.into_iter()
+                .map(|(field, value)| (field.join("."), value))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn matches_literals_and_variables() {
+        assert_eq!(
+            bindings(
+                "/v1/shelves/{shelf}/books/{book.id}",
+                "/v1/shelves/1/books/a%20b"
+            ),
+            Some(vec![
+                ("shelf".to_string(), "1".to_string()),
+                ("book.id".to_string(), "a b".to_string()),
+            ])
+        );
+        assert_eq!(bindings("/v1/shelves/{shelf}", "/v1/shelves/1/books"), None);
+        assert_eq!(bindings("/v1/shelves/{shelf}", "/v1/shelves/"), None);
+        assert_eq!(bindings("/v1/shelves/{shelf}", "/v2/shelves/1"), None);
+    }
+
+    #[test]
+    fn matches_multi_segment_variables() {
+        assert_eq!(
+            bindings("/v1/{name=shelves/*/books/*}", "/v1/shelves/1/books/2"),
+            Some(vec![("name".to_string(), "shelves/1/books/2".to_string())])
+        );
+        assert_eq!(
+            bindings("/v1/{path=files/**}", "/v1/files/a/b/c"),
+            Some(vec![("path".to_string(), "files/a/b/c".to_string())])
+        );
+        assert_eq!(bindings("/v1/{name=shelves/*}", "/v1/books/1"), None);
+    }
+
+    #[test]
+    fn matches_verbs() {
+        assert_eq!(
+            bindings("/v1/{name=books/*}:publish", "/v1/books/1:publish"),
+            Some(vec![("name".to_string(), "books/1".to_string())])
+        );
+        assert_eq!(bindings("/v1/{name=books/*}:publish", "/v1/books/1"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        for template in [
+            "v1/books",
+            "/v1/{name",
+            "/v1/**/books",
+            "/v1//books",
+            "/v1/{=*}",
+        ] {
+            assert!(PathTemplate::parse(template).is_err(), "{template}");
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Helpers shared by the protocols translated to binary grpc-web ahead of the grpc-web layer:
+//! building the grpc-web request, reading the grpc-web response back as messages and a status,
+//! and the protobuf JSON mapping.
+
+use crate::grpc_web::{FrameDecoder, TRAILERS_FLAG, decode_trailers, encode_frame};
+use bytes::{Bytes, BytesMut};
+use http::header::{ACCEPT, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE};
+use http::{HeaderMap, HeaderValue, Request, Response, StatusCode, request, response};
+use http_body::Body as HttpBody;
+use http_body_util::Full;
+use lambda_http::tracing::log::warn;
+use prost::Message;
+use prost_reflect::{DynamicMessage, MessageDescriptor};
+use serde_json::Value;
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use tonic::body::Body;
+use tonic::{Code, Status};
+
+pub(crate) const GRPC_WEB_PROTO: &str = "application/grpc-web+proto";
+
+/// Headers carrying the gRPC status, which the translated protocols convey differently
+const STATUS_HEADERS: [&str; 3] = ["grpc-status", "grpc-message", "grpc-status-details-bin"];
+
+/// Builds a binary grpc-web request of the messages, keeping the request metadata
+pub(crate) fn grpc_web_request(mut parts: request::Parts, messages: Vec<Bytes>) -> Request<Body> {
+    let mut body = BytesMut::new();
+    for message in messages {
+        body.extend_from_slice(&encode_frame(0, &message));
+    }
+
+    for name in [CONTENT_LENGTH, ACCEPT, ACCEPT_ENCODING, CONTENT_ENCODING] {
+        parts.headers.remove(name);
+    }
+    parts
+        .headers
+        .insert(CONTENT_TYPE, HeaderValue::from_static(GRPC_WEB_PROTO));
+
+    Request::from_parts(parts, Body::new(Full::new(body.freeze())))
+}
+
+/// The status of a trailers-only grpc-web response, which has no body to read
+pub(crate) fn trailers_only(parts: &response::Parts) -> Option<Status> {
+    if parts.headers.contains_key(STATUS_HEADERS[0]) || !parts.status.is_success() {
+        Some(grpc_status(parts.status, &parts.headers))
+    } else {
+        None
+    }
+}
+
+/// A unary grpc-web response, read in full
+pub(crate) struct UnaryResponse {
+    pub(crate) headers: HeaderMap,
+    pub(crate) trailers: HeaderMap,
+    pub(crate) result: Result<Bytes, Status>,
+}
+
+impl UnaryResponse {
+    pub(crate) async fn read(res: Response<Body>) -> Self {
+        let (parts, body) = res.into_parts();
+
+        if let Some(status) = trailers_only(&parts) {
+            return Self {
+                headers: HeaderMap::new(),
+                trailers: metadata(&parts.headers),
+                result: Err(status),
+            };
+        }
+
+        let mut messages = GrpcWebMessages::new(body);
+        let mut message = None;
+        let (status, trailers) = loop {
+            match poll_fn(|cx| messages.poll_next(cx)).await {
+                Some(GrpcWebEvent::Message(payload)) => message = Some(payload),
+                Some(GrpcWebEvent::End(status, trailers)) => break (status, trailers),
+                None => unreachable!("the end of the messages is always reported"),
+            }
+        };
+
+        let result = match (status.code(), message) {
+            (Code::Ok, Some(message)) => Ok(message),
+            (Code::Ok, None) => Err(Status::internal("Missing response message")),
+            _ => Err(status),
+        };
+
+        Self {
+            headers: metadata(&parts.headers),
+            trailers,
+            result,
+        }
+    }
+}
+
+pub(crate) enum GrpcWebEvent {
+    Message(Bytes),
+    /// The status and trailing metadata, always the final event
+    End(Status, HeaderMap),
+}
+
+/// Reads the messages of a grpc-web response body as they arrive
+pub(crate) struct GrpcWebMessages {
+    inner: Body,
+    decoder: FrameDecoder,
+    done: bool,
+}
+
+impl GrpcWebMessages {
+    pub(crate) fn new(inner: Body) -> Self {
+        Self {
+            inner,
+            decoder: FrameDecoder::default(),
+            done: false,
+        }
+    }
+
+    pub(crate) fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<GrpcWebEvent>> {
+        loop {
+            if self.done {
+                return Poll::Ready(None);
+            }
+
+            if let Some((flags, payload)) = self.decoder.next_frame() {
+                if flags & TRAILERS_FLAG != 0 {
+                    return self.end_with(decode_trailers(&payload));
+                }
+                return Poll::Ready(Some(GrpcWebEvent::Message(payload)));
+            }
+
+            match ready!(Pin::new(&mut self.inner).poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => self.decoder.push(data),
+                    Err(frame) => {
+                        if let Ok(trailers) = frame.into_trailers() {
+                            return self.end_with(trailers);
+                        }
+                    }
+                },
+                Some(Err(status)) => {
+                    warn!("Response stream failed: {status:?}");
+                    return self.end(status, HeaderMap::new());
+                }
+                None => {
+                    let status = Status::internal("Response stream ended without a status");
+                    return self.end(status, HeaderMap::new());
+                }
+            }
+        }
+    }
+
+    fn end_with(&mut self, trailers: HeaderMap) -> Poll<Option<GrpcWebEvent>> {
+        let status = grpc_status(StatusCode::OK, &trailers);
+        self.end(status, metadata(&trailers))
+    }
+
+    fn end(&mut self, status: Status, trailers: HeaderMap) -> Poll<Option<GrpcWebEvent>> {
+        self.done = true;
+        Poll::Ready(Some(GrpcWebEvent::End(status, trailers)))
+    }
+}
+
+/// The status carried by grpc-web trailers, or by the headers of a trailers-only response
+fn grpc_status(http_status: StatusCode, headers: &HeaderMap) -> Status {
+    Status::from_header_map(headers).unwrap_or_else(|| {
+        if http_status.is_success() {
+            Status::internal("Response is missing grpc-status")
+        } else {
+            Status::unknown(format!("Unexpected HTTP status {http_status}"))
+        }
+    })
+}
+
+/// Custom metadata, i.e. everything but the protocol headers
+pub(crate) fn metadata(headers: &HeaderMap) -> HeaderMap {
+    let mut metadata = headers.clone();
+    for name in STATUS_HEADERS {
+        metadata.remove(name);
+    }
+    metadata.remove(CONTENT_TYPE);
+    metadata.remove(CONTENT_LENGTH);
+    metadata
+}
+
+/// Parses a JSON message, rejecting it as `invalid_argument` if it doesn't fit the descriptor
+pub(crate) fn json_to_message(
+    descriptor: &MessageDescriptor,
+    json: &[u8],
+) -> Result<DynamicMessage, Status> {
+    let mut deserializer = serde_json::Deserializer::from_slice(json);
+    DynamicMessage::deserialize(descriptor.clone(), &mut deserializer)
+        .and_then(|message| deserializer.end().map(|_| message))
+        .map_err(|err| Status::invalid_argument(format!("Invalid JSON request: {err}")))
+}
+
+/// Renders an encoded response message as JSON
+pub(crate) fn message_to_json(
+    descriptor: &MessageDescriptor,
+    message: Bytes,
+) -> Result<Value, Status> {
+    DynamicMessage::decode(descriptor.clone(), message)
+        .map_err(|err| err.to_string())
+        .and_then(|message| serde_json::to_value(&message).map_err(|err| err.to_string()))
+        .map_err(|err| Status::internal(format!("Failed to encode response as JSON: {err}")))
+}
+
+/// The `google.protobuf.Any` details of a status
+pub(crate) fn status_details(status: &Status) -> Vec<ProtoAny> {
+    RpcStatus::decode(status.details())
+        .map(|rpc_status| rpc_status.details)
+        .unwrap_or_default()
+}
+
+/// `google.rpc.Status`, as carried by `grpc-status-details-bin`
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    pub(crate) code: i32,
+    #[prost(string, tag = "2")]
+    pub(crate) message: String,
+    #[prost(message, repeated, tag = "3")]
+    pub(crate) details: Vec<ProtoAny>,
+}
+
+/// `google.protobuf.Any`
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct ProtoAny {
+    #[prost(string, tag = "1")]
+    pub(crate) type_url: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub(crate) value: Vec<u8>,
+}
+
+/// HTTP status conveying a gRPC status code, as mapped by both Connect and `google.api.http`
+pub(crate) fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::Cancelled => StatusCode::from_u16(499).expect("499 is a valid status code"),
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+            StatusCode::BAD_REQUEST
+        }
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::Unknown | Code::Internal | Code::DataLoss => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}